@@ -1,17 +1,21 @@
-extern crate reqwest;
-
 use anyhow::{Context, Result};
 use clap::command;
 use clap::Arg;
-use iloveair::config::file_older_than_minutes;
+use iloveair::cache::{ttl, Cache};
+use iloveair::cli::{exit, http_args, init_http, init_logging, logging_args, Failure};
 use iloveair::config::read_weather_config;
+use iloveair::history::History;
+use iloveair::http::{self, Request};
+use iloveair::influx;
+use iloveair::pollution::{aqi_description, pollution_aqi};
 //use iloveair::weather::{load_weather_response, weather_humidity, weather_tempurature};
-use std::fs::OpenOptions;
 use std::io::{stdout, Write};
+use std::process::ExitCode;
+use tracing::{debug, info};
 
 // https://openweathermap.org/api/air-pollution
 
-fn main() {
+fn main() -> ExitCode {
     let command = command!()
         .version("0.9")
         .arg(
@@ -30,43 +34,61 @@ fn main() {
                 .value_name("FILE")
                 .required(true)
                 .help("config $HOME/.config/iloveair/openweathermap.json"),
+        )
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .required(false)
+                .help("record the pollution in the history database ~/.local/share/iloveair/history.db"),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .required(false)
+                .num_args(0)
+                .help("fetch even if the cached pollution is still fresh"),
+        )
+        .arg(
+            Arg::new("influx")
+                .long("influx")
+                .value_name("FILE")
+                .required(false)
+                .help("write the pollution as Influx line protocol, config ~/.config/iloveair/influx.json"),
         );
-    let matches = command.get_matches();
+    let matches = http_args(logging_args(command)).get_matches();
+    init_logging(&matches);
+    init_http(&matches);
 
     let outfile = matches.get_one::<String>("out");
-    if let Some(config_file) = matches.get_one::<String>("config") {
-        match app_main(config_file, outfile) {
-            Ok(_) => (),
-            Err(e) => println!("Error: {}", e),
-        }
-    } else {
-        println!("Error: --config -c required");
-    }
+    let history_db_path = matches.get_one::<String>("history_db");
+    let influx_config_path = matches.get_one::<String>("influx");
+    let is_force = matches.get_flag("force");
+    let Some(config_file) = matches.get_one::<String>("config") else {
+        // This else block is unreachable because the argument is required.
+        unreachable!();
+    };
+    exit(app_main(
+        config_file,
+        outfile,
+        history_db_path,
+        influx_config_path,
+        is_force,
+    ))
 }
 fn save_pollution_response(
     maybe_pollution_json_path: Option<&String>,
+    url: &str,
+    status: u16,
     response: &serde_json::Value,
 ) -> Result<()> {
     if let Some(pollution_json_path) = maybe_pollution_json_path {
-        let mut fout = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(pollution_json_path)
-            .with_context(|| {
-                format!(
-                    "save_pollution_response: could not open for write {}",
-                    pollution_json_path
-                )
-            })?;
-        fout.write_all(serde_json::to_string_pretty(response)?.as_bytes())
-            .with_context(|| {
-                format!(
-                    "save_pollution_response: could write {}",
-                    pollution_json_path
-                )
-            })?;
-        println!("wrote: {}", pollution_json_path);
+        Cache::new(pollution_json_path, ttl("owm_pollution")).write(
+            response,
+            Some(url),
+            Some(status),
+        )?;
+        info!("wrote: {}", pollution_json_path);
     } else {
         stdout()
             .write_all(serde_json::to_string_pretty(response)?.as_bytes())
@@ -74,41 +96,43 @@ fn save_pollution_response(
     }
     Ok(())
 }
-fn aqi_description(aqi: u64) -> &'static str {
-    match aqi {
-        1 => "Good",
-        2 => "Fair",
-        3 => "Moderate",
-        4 => "Poor",
-        5 => "Very Poor",
-        _ => "Unknown",
-    }
-}
-fn app_main(config_file: &String, maybe_pollution_json_path: Option<&String>) -> Result<()> {
+fn app_main(
+    config_file: &String,
+    maybe_pollution_json_path: Option<&String>,
+    maybe_history_db_path: Option<&String>,
+    maybe_influx_config_path: Option<&String>,
+    is_force: bool,
+) -> Result<()> {
     let config = read_weather_config(config_file)
-        .with_context(|| format!("could not read config {}", config_file))?;
+        .with_context(|| format!("could not read config {}", config_file))
+        .context(Failure::Config)?;
 
-    let update_no_more_than_minutes = 10;
     if let Some(pollution_json_path) = maybe_pollution_json_path {
-        if file_older_than_minutes(pollution_json_path, update_no_more_than_minutes) {
-            println!(
+        let ttl = ttl("owm_pollution");
+        if !is_force && Cache::new(pollution_json_path, ttl).is_fresh() {
+            info!(
                 "pollution.json is less than {} minutes old",
-                update_no_more_than_minutes
+                ttl.num_minutes()
             );
             return Ok(());
         }
     }
 
     //println!("API Key: {}", config.api_key);
-    println!("Latitude: {}", config.latitude);
-    println!("longitude: {}", config.longitude);
+    debug!("Latitude: {}", config.latitude);
+    debug!("longitude: {}", config.longitude);
+    let pollution_url = http::endpoint(
+        &config.base_url,
+        "/data/2.5/air_pollution",
+        config.allow_http,
+    )?;
     let api_key = config.api_key;
     // let city_name = config.city;
     // let country_code = config.country;
 
     let url = format!(
-        "http://api.openweathermap.org/data/2.5/air_pollution?lat={}&lon={}&appid={}",
-        config.latitude, config.longitude, api_key
+        "{}?lat={}&lon={}&appid={}",
+        pollution_url, config.latitude, config.longitude, api_key
     );
     // example response
     // {
@@ -135,10 +159,31 @@ fn app_main(config_file: &String, maybe_pollution_json_path: Option<&String>) ->
     //     }
     //   ]
     // }
-    let response = reqwest::blocking::get(url)?.json::<serde_json::Value>()?;
+    let fetched = http::client()
+        .send(&Request::get(&url))
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| {
+            response
+                .json::<serde_json::Value>()
+                .map(|json| (response.status, json))
+        });
+    let history = match maybe_history_db_path {
+        Some(history_db_path) => Some(History::open(history_db_path)?),
+        None => None,
+    };
+    if let Some(history) = &history {
+        history.record_fetch_result("owm_pollution", &fetched)?;
+    }
+    let (status, response) = fetched?;
 
-    save_pollution_response(maybe_pollution_json_path, &response)?;
-    let aqi = response["list"][0]["main"]["aqi"].as_u64().unwrap();
+    save_pollution_response(maybe_pollution_json_path, &url, status, &response)?;
+    if let Some(history) = &history {
+        history.record_pollution(&response)?;
+    }
+    if let Some(influx_config_path) = maybe_influx_config_path {
+        influx::write_points(influx_config_path, &influx::pollution_points(&response))?;
+    }
+    let aqi = pollution_aqi(&response).context(Failure::Parse)?;
 
     println!("The Air Quality Index is {} {}", aqi, aqi_description(aqi));
     Ok(())
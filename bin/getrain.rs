@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use chrono::{Days, Local, NaiveDate};
+use clap::{command, Arg};
+use iloveair::cli::{exit, http_args, init_http, init_logging, logging_args, Failure};
+use iloveair::history::History;
+use iloveair::http::{self, Request};
+use iloveair::rain::{parse_report, rain_regex, read_rain_config, report_text, Precipitation};
+use std::process::ExitCode;
+use tracing::{debug, info};
+
+// Yesterday's high, low and precipitation from an NWS climate report, replaces
+// python/save_rain_data.py and its ~/.cache/air/rain.csv
+
+fn main() -> ExitCode {
+    let command = command!()
+        .version("0.9")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .default_value("~/.config/iloveair/rain.json")
+                .help("rain_data_url and rain_regex"),
+        )
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .default_value("~/.local/share/iloveair/history.db")
+                .help("history database"),
+        )
+        .arg(
+            Arg::new("date")
+                .long("date")
+                .value_name("YYYY-MM-DD")
+                .help("day the report is for, defaults to yesterday"),
+        );
+    let matches = http_args(logging_args(command)).get_matches();
+    init_logging(&matches);
+    init_http(&matches);
+
+    let Some(config_file) = matches.get_one::<String>("config") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let Some(history_db_path) = matches.get_one::<String>("history_db") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let date = matches.get_one::<String>("date");
+    exit(app_main(config_file, history_db_path, date))
+}
+
+fn app_main(config_file: &str, history_db_path: &str, date: Option<&String>) -> Result<()> {
+    let config = read_rain_config(config_file)
+        .with_context(|| format!("could not read config {}", config_file))
+        .context(Failure::Config)?;
+    let regex = rain_regex(&config.rain_regex)?;
+    let url = http::endpoint(&config.rain_data_url, "", config.allow_http)?;
+    // the report is published in the morning for the day before
+    let date = match date {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .with_context(|| format!("app_main: bad --date {}", date))
+            .context(Failure::Config)?,
+        None => Local::now().date_naive() - Days::new(1),
+    };
+
+    let history = History::open(history_db_path)?;
+    let fetched = http::client()
+        .send(&Request::get(&url))
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| report_text(&response.body))
+        .and_then(|report| {
+            parse_report(&report, &regex, date).inspect_err(|_| {
+                debug!("report: {}", report);
+            })
+        });
+    history.record_fetch_result("nws_climate", &fetched)?;
+    let climate = fetched?;
+    history.record_climate(&climate)?;
+    info!("recorded: {} in {}", climate.date, history_db_path);
+
+    let temperature = |value: Option<f64>| match value {
+        Some(value) => format!("{}°F", value),
+        None => "missing".into(),
+    };
+    println!("{}", climate.date);
+    println!("High Temperature: {}", temperature(climate.high));
+    println!("Low Temperature: {}", temperature(climate.low));
+    match climate.precipitation {
+        Precipitation::Inches(inches) => println!("Precipitation: {} inches", inches),
+        Precipitation::Trace => println!("Precipitation: trace"),
+        Precipitation::Missing => println!("Precipitation: missing"),
+    }
+    Ok(())
+}
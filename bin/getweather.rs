@@ -1,15 +1,20 @@
-extern crate reqwest;
-
 use anyhow::{Context, Result};
+use chrono::Duration;
 use clap::{command, Arg};
+use iloveair::cache::{ttl, Cache};
+use iloveair::cli::{exit, http_args, init_http, init_logging, logging_args, Failure};
 use iloveair::config::read_weather_config;
+use iloveair::history::History;
+use iloveair::http;
+use iloveair::influx;
+use iloveair::provider::{self, Reading};
+use serde_json::Value;
 
-use std::fs::OpenOptions;
 use std::io::{stdout, Write};
+use std::process::ExitCode;
+use tracing::{debug, info};
 
-static UPDATE_NO_MORE_THAN_MINUTES: u64 = 10;
-
-fn main() {
+fn main() -> ExitCode {
     let command = command!()
         .version("0.9")
         .arg(
@@ -27,39 +32,69 @@ fn main() {
                 .long("config")
                 .value_name("FILE")
                 .required(true)
-                .help("config $HOME/.config/iloveair/openweathermap.json"),
+                .help("config $HOME/.config/iloveair/openweathermap.json, \"providers\" picks OWM, NWS or Open-Meteo"),
+        )
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .required(false)
+                .help(
+                    "record the weather in the history database ~/.local/share/iloveair/history.db",
+                ),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .required(false)
+                .num_args(0)
+                .help("fetch even if the cached weather is still fresh"),
+        )
+        .arg(
+            Arg::new("influx")
+                .long("influx")
+                .value_name("FILE")
+                .required(false)
+                .help("write the weather as Influx line protocol, config ~/.config/iloveair/influx.json"),
         );
-    let matches = command.get_matches();
+    let matches = http_args(logging_args(command)).get_matches();
+    init_logging(&matches);
+    init_http(&matches);
 
     let outfile = matches.get_one::<String>("out");
-    if let Some(config_file) = matches.get_one::<String>("config") {
-        match app_main(config_file, outfile) {
-            Ok(_) => (),
-            Err(e) => println!("Error: {}", e),
-        }
-    } else {
-        println!("Error: --config -c required");
-    }
+    let history_db_path = matches.get_one::<String>("history_db");
+    let influx_config_path = matches.get_one::<String>("influx");
+    let is_force = matches.get_flag("force");
+    let Some(config_file) = matches.get_one::<String>("config") else {
+        // This else block is unreachable because the argument is required.
+        unreachable!();
+    };
+    exit(app_main(
+        config_file,
+        outfile,
+        history_db_path,
+        influx_config_path,
+        is_force,
+    ))
 }
 fn save_weather_response(
     maybe_weather_json_path: Option<&String>,
-    response: &serde_json::Value,
+    reading: &Reading,
 ) -> Result<()> {
+    let Reading {
+        weather_json: response,
+        url,
+        status,
+        ..
+    } = reading;
     if let Some(weather_json_path) = maybe_weather_json_path {
-        let mut fout = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(weather_json_path)
-            .with_context(|| {
-                format!(
-                    "save_weather_response: could not open for write {}",
-                    weather_json_path
-                )
-            })?;
-        fout.write_all(serde_json::to_string_pretty(response)?.as_bytes())
-            .with_context(|| format!("save_weather_response: could write {}", weather_json_path))?;
-        println!("wrote: {}", weather_json_path);
+        // the TTL is only for reading, see cached_provider_ttl
+        Cache::new(weather_json_path, Duration::zero()).write(
+            response,
+            Some(url),
+            Some(*status),
+        )?;
+        info!("wrote: {}", weather_json_path);
     } else {
         stdout()
             .write_all(serde_json::to_string_pretty(response)?.as_bytes())
@@ -67,59 +102,69 @@ fn save_weather_response(
     }
     Ok(())
 }
-fn file_modified_in_last_minutes(path: &str, minutes: u64) -> bool {
-    if !std::path::Path::new(path).exists() {
-        return false;
-    }
-    let metadata = std::fs::metadata(path).unwrap();
-    let modified = metadata.modified().unwrap();
-    let modified = modified.elapsed().unwrap().as_secs();
-    modified < minutes * 60
+/// the TTL of the provider that answered last time, it is in the cached payload as "provider"
+/// (OWM's own documents have none)
+fn cached_provider_ttl(weather_json_path: &str) -> Duration {
+    let provider = Cache::new(weather_json_path, Duration::zero())
+        .read::<Value>()
+        .ok()
+        .and_then(|entry| entry.payload["provider"].as_str().map(String::from))
+        .unwrap_or_else(|| "owm_weather".into());
+    ttl(&provider)
 }
-fn app_main(config_file: &String, maybe_weather_json_path: Option<&String>) -> Result<()> {
+fn app_main(
+    config_file: &String,
+    maybe_weather_json_path: Option<&String>,
+    maybe_history_db_path: Option<&String>,
+    maybe_influx_config_path: Option<&String>,
+    is_force: bool,
+) -> Result<()> {
     let config = read_weather_config(config_file)
-        .with_context(|| format!("could not read config {}", config_file))?;
+        .with_context(|| format!("could not read config {}", config_file))
+        .context(Failure::Config)?;
 
+    let providers = provider::providers(&config)?;
     if let Some(weather_json_path) = maybe_weather_json_path {
-        if file_modified_in_last_minutes(weather_json_path, UPDATE_NO_MORE_THAN_MINUTES) {
-            println!(
+        let ttl = cached_provider_ttl(weather_json_path);
+        if !is_force && Cache::new(weather_json_path, ttl).is_fresh() {
+            info!(
                 "weather.json is less than {} minutes old",
-                UPDATE_NO_MORE_THAN_MINUTES
+                ttl.num_minutes()
             );
             return Ok(());
         }
     }
 
-    //println!("API Key: {}", config.api_key);
-    println!("City: {}", config.city);
-    println!("Country: {}", config.country);
-    let api_key = config.api_key;
-    let city_name = config.city;
-    let country_code = config.country;
-
-    let url = format!(
-        "https://api.openweathermap.org/data/2.5/weather?q={},{}&appid={}&units=imperial",
-        city_name, country_code, api_key
-    );
-
-    let response = reqwest::blocking::get(url)?.json::<serde_json::Value>()?;
-    save_weather_response(maybe_weather_json_path, &response)?;
-
-    let temperature = response["main"]["temp"].as_f64().unwrap();
-    let humidity = response["main"]["humidity"].as_u64().unwrap();
+    debug!("City: {}", config.city);
+    debug!("Country: {}", config.country);
+    let history = match maybe_history_db_path {
+        Some(history_db_path) => Some(History::open(history_db_path)?),
+        None => None,
+    };
+    let reading = provider::observe(&providers, http::client(), history.as_ref())?;
+    save_weather_response(maybe_weather_json_path, &reading)?;
+    let response = &reading.weather_json;
+    if let Some(history) = &history {
+        history.record_weather(response)?;
+    }
+    if let Some(influx_config_path) = maybe_influx_config_path {
+        influx::write_points(
+            influx_config_path,
+            &influx::weather_point(response)
+                .into_iter()
+                .collect::<Vec<_>>(),
+        )?;
+    }
 
+    let observation = reading.observation;
+    debug!("Provider: {}", observation.provider);
     println!(
         "The temperature in {} is {:.2}°F and the humidity is {}%",
-        city_name, temperature, humidity
+        config.city, observation.temp, observation.humidity
     );
-
-    //let weather_json = load_weather_response(weather_json_path)?;
-    //let temperature = weather_tempurature(&weather_json)?;
-    //let humidity = weather_humidity(&weather_json)?;
-
     println!(
         "The temperature is {:.2}°F and the humidity is {}%",
-        temperature, humidity
+        observation.temp, observation.humidity
     );
     Ok(())
 }
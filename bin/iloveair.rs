@@ -0,0 +1,518 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use chrono_tz::Tz;
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
+use iloveair::backfill::{self, RateLimit, Source};
+use iloveair::cli::{exit, http_args, init_http, init_logging, logging_args, Failure};
+use iloveair::config::read_weather_config;
+use iloveair::dashboard;
+use iloveair::export::{Format, Table};
+use iloveair::history::{aggregate, metric_names, parse_time, History, Interval};
+use iloveair::http;
+use iloveair::influx;
+use iloveair::metrics;
+use iloveair::mqtt;
+use iloveair::server;
+use std::fs::File;
+use std::io::stdout;
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing::info;
+
+fn history_command() -> Command {
+    Command::new("history")
+        .about("query and export readings from the history database")
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .default_value("~/.local/share/iloveair/history.db")
+                .help("history database"),
+        )
+        .arg(
+            Arg::new("metric")
+                .short('m')
+                .long("metric")
+                .value_name("NAME")
+                .action(ArgAction::Append)
+                .help(format!(
+                    "metric to export, repeatable, defaults to all: {}",
+                    metric_names().join(", ")
+                )),
+        )
+        .arg(
+            Arg::new("device")
+                .short('d')
+                .long("device")
+                .value_name("ID")
+                .help("Airthings device id or weather location"),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("TIME")
+                .help("RFC 3339, YYYY-MM-DD or a duration ago like 24h, 7d"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .value_name("TIME")
+                .help("RFC 3339, YYYY-MM-DD or a duration ago like 24h, 7d"),
+        )
+        .arg(
+            Arg::new("interval")
+                .short('i')
+                .long("interval")
+                .value_name("raw|hourly|daily")
+                .default_value("raw")
+                .help("raw readings or min/mean/max per hour or day"),
+        )
+        .arg(
+            Arg::new("timezone")
+                .long("timezone")
+                .value_name("TZ")
+                .help("timezone days start in, e.g. America/New_York, defaults to local"),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_name("table|csv|jsonl|parquet")
+                .default_value("table")
+                .help("output format, parquet needs the parquet feature"),
+        )
+        .arg(
+            Arg::new("out")
+                .short('o')
+                .long("out")
+                .value_name("FILE")
+                .help("output file, defaults to stdout"),
+        )
+}
+
+fn dashboard_command() -> Command {
+    Command::new("dashboard")
+        .about("indoor vs outdoor conditions, the last 24 hours and the recommendation")
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .default_value("~/.local/share/iloveair/history.db")
+                .help("history database"),
+        )
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .required(false)
+                .num_args(0)
+                .help("keep the dashboard open and refresh it, q quits"),
+        )
+        .arg(
+            Arg::new("refresh")
+                .value_parser(value_parser!(u64))
+                .long("refresh")
+                .value_name("SECONDS")
+                .default_value("60")
+                .help("seconds between refreshes with --daemon"),
+        )
+}
+
+fn serve_command() -> Command {
+    Command::new("serve")
+        .about("HTTP API and web page for phones on the LAN")
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .default_value("~/.local/share/iloveair/history.db")
+                .help("history database"),
+        )
+        .arg(
+            Arg::new("listen")
+                .short('l')
+                .long("listen")
+                .value_name("ADDRESS:PORT")
+                .default_value("0.0.0.0:8080")
+                .help("address to listen on"),
+        )
+}
+
+fn metrics_command() -> Command {
+    Command::new("metrics")
+        .about("Prometheus metrics for the node exporter textfile collector")
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .default_value("~/.local/share/iloveair/history.db")
+                .help("history database"),
+        )
+        .arg(
+            Arg::new("textfile")
+                .long("textfile")
+                .value_name("FILE")
+                .help(
+                "e.g. /var/lib/node_exporter/textfile_collector/iloveair.prom, defaults to stdout",
+            ),
+        )
+}
+
+fn mqtt_command() -> Command {
+    Command::new("mqtt")
+        .about("publish readings and the recommendation to MQTT with Home Assistant discovery")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .default_value("~/.config/iloveair/mqtt.json")
+                .help("broker, credentials and topics"),
+        )
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .default_value("~/.local/share/iloveair/history.db")
+                .help("history database"),
+        )
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .required(false)
+                .num_args(0)
+                .help("stay connected, republish and reconnect when the broker goes away"),
+        )
+        .arg(
+            Arg::new("interval")
+                .value_parser(value_parser!(u64))
+                .long("interval")
+                .value_name("SECONDS")
+                .default_value("60")
+                .help("seconds between publishes with --daemon"),
+        )
+}
+
+fn influx_command() -> Command {
+    Command::new("influx")
+        .about("write readings from the history database as Influx line protocol, sending any spooled points first")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .default_value("~/.config/iloveair/influx.json")
+                .help("target, token, batch size and spool"),
+        )
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .default_value("~/.local/share/iloveair/history.db")
+                .help("history database"),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("TIME")
+                .help("RFC 3339, YYYY-MM-DD or a duration ago like 24h, 7d, defaults to nothing so only the spool is sent"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .value_name("TIME")
+                .help("RFC 3339, YYYY-MM-DD or a duration ago like 24h, 7d"),
+        )
+}
+
+fn backfill_command() -> Command {
+    Command::new("backfill")
+        .about("fill the history database with past OWM weather and pollution, resuming an interrupted run")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .default_value("~/.config/iloveair/openweathermap.json")
+                .help("API key, location and base URLs"),
+        )
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .default_value("~/.local/share/iloveair/history.db")
+                .help("history database"),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("TIME")
+                .required(true)
+                .help("RFC 3339, YYYY-MM-DD or a duration ago like 24h, 7d, rerun with the same value to resume"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .value_name("TIME")
+                .help("RFC 3339, YYYY-MM-DD or a duration ago like 24h, 7d, defaults to now"),
+        )
+        .arg(
+            Arg::new("source")
+                .short('s')
+                .long("source")
+                .value_name("weather|pollution")
+                .action(ArgAction::Append)
+                .help("what to backfill, repeatable, defaults to both, weather needs the OWM history API"),
+        )
+        .arg(
+            Arg::new("requests_per_minute")
+                .value_parser(value_parser!(u32))
+                .long("requests-per-minute")
+                .value_name("N")
+                .default_value("50")
+                .help("stay under the OWM rate limit, 429 and 5xx responses are retried with backoff"),
+        )
+}
+
+fn main() -> ExitCode {
+    let command = command!()
+        .version("0.9")
+        .subcommand_required(true)
+        .subcommand(history_command())
+        .subcommand(dashboard_command())
+        .subcommand(serve_command())
+        .subcommand(metrics_command())
+        .subcommand(mqtt_command())
+        .subcommand(influx_command())
+        .subcommand(backfill_command());
+    let matches = http_args(logging_args(command)).get_matches();
+    init_logging(&matches);
+    init_http(&matches);
+
+    let result = match matches.subcommand() {
+        Some(("history", matches)) => history_main(matches),
+        Some(("dashboard", matches)) => dashboard_main(matches),
+        Some(("serve", matches)) => serve_main(matches),
+        Some(("metrics", matches)) => metrics_main(matches),
+        Some(("mqtt", matches)) => mqtt_main(matches),
+        Some(("influx", matches)) => influx_main(matches),
+        Some(("backfill", matches)) => backfill_main(matches),
+        _ => {
+            // This block is unreachable because a subcommand is required.
+            unreachable!();
+        }
+    };
+    exit(result)
+}
+
+fn dashboard_main(matches: &ArgMatches) -> Result<()> {
+    let Some(history_db_path) = matches.get_one::<String>("history_db") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let Some(refresh) = matches.get_one::<u64>("refresh") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    dashboard::run(
+        history_db_path,
+        Duration::from_secs(*refresh),
+        matches.get_flag("daemon"),
+    )
+}
+
+fn serve_main(matches: &ArgMatches) -> Result<()> {
+    let Some(history_db_path) = matches.get_one::<String>("history_db") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let Some(listen) = matches.get_one::<String>("listen") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    server::serve(history_db_path, listen)
+}
+
+fn metrics_main(matches: &ArgMatches) -> Result<()> {
+    let Some(history_db_path) = matches.get_one::<String>("history_db") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let history = History::open(history_db_path)?;
+    match matches.get_one::<String>("textfile") {
+        Some(textfile_path) => metrics::write_textfile(&history, textfile_path),
+        None => {
+            print!("{}", metrics::render(&history)?);
+            Ok(())
+        }
+    }
+}
+
+fn mqtt_main(matches: &ArgMatches) -> Result<()> {
+    let Some(mqtt_config_path) = matches.get_one::<String>("config") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let Some(history_db_path) = matches.get_one::<String>("history_db") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let Some(interval) = matches.get_one::<u64>("interval") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let config = mqtt::read_mqtt_config(mqtt_config_path).context(Failure::Config)?;
+    if matches.get_flag("daemon") {
+        mqtt::publish_daemon(&config, history_db_path, Duration::from_secs(*interval))
+    } else {
+        let history = History::open(history_db_path)?;
+        mqtt::publish_once(&config, &history, Duration::from_secs(30))?;
+        info!("published: {}:{}", config.host, config.port);
+        Ok(())
+    }
+}
+
+fn influx_main(matches: &ArgMatches) -> Result<()> {
+    let Some(influx_config_path) = matches.get_one::<String>("config") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let Some(history_db_path) = matches.get_one::<String>("history_db") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let now = Utc::now();
+    let to = match matches.get_one::<String>("to") {
+        Some(to) => Some(parse_time(to, now)?),
+        None => None,
+    };
+    let mut points = vec![];
+    if let Some(from) = matches.get_one::<String>("from") {
+        let from = Some(parse_time(from, now)?);
+        let history = History::open(history_db_path)?;
+        for (device, indoor_json) in history.json_rows("indoor", from, to)? {
+            points.extend(influx::indoor_point(
+                device.as_deref().unwrap_or(""),
+                &indoor_json,
+            ));
+        }
+        for (_, weather_json) in history.json_rows("weather", from, to)? {
+            points.extend(influx::weather_point(&weather_json));
+        }
+        for (_, pollution_json) in history.json_rows("pollution", from, to)? {
+            points.extend(influx::pollution_points(&pollution_json));
+        }
+    }
+    influx::write_points(influx_config_path, &points)
+}
+
+fn backfill_main(matches: &ArgMatches) -> Result<()> {
+    let Some(config_path) = matches.get_one::<String>("config") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let Some(history_db_path) = matches.get_one::<String>("history_db") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let Some(from) = matches.get_one::<String>("from") else {
+        // This else block is unreachable because --from is required.
+        unreachable!();
+    };
+    let Some(requests_per_minute) = matches.get_one::<u32>("requests_per_minute") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let sources: Vec<Source> = match matches.get_many::<String>("source") {
+        Some(sources) => sources
+            .map(|source| source.parse())
+            .collect::<Result<_>>()
+            .context(Failure::Config)?,
+        None => vec![Source::Weather, Source::Pollution],
+    };
+    let now = Utc::now();
+    let from = parse_time(from, now)?;
+    let to = match matches.get_one::<String>("to") {
+        Some(to) => parse_time(to, now)?,
+        None => now.timestamp(),
+    };
+    let config = read_weather_config(config_path).context(Failure::Config)?;
+    let history = History::open(history_db_path)?;
+    let rate = RateLimit::per_minute(*requests_per_minute);
+    for source in sources {
+        let summary = backfill::backfill(
+            http::client(),
+            &config,
+            &history,
+            source,
+            from,
+            to,
+            &rate,
+            &mut std::thread::sleep,
+        )?;
+        info!(
+            "backfilled: {} {} requests, {} records",
+            source.name(),
+            summary.requests,
+            summary.records
+        );
+    }
+    Ok(())
+}
+
+fn history_main(matches: &ArgMatches) -> Result<()> {
+    let Some(history_db_path) = matches.get_one::<String>("history_db") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let metrics: Vec<&str> = match matches.get_many::<String>("metric") {
+        Some(metrics) => metrics.map(String::as_str).collect(),
+        None => metric_names(),
+    };
+    let device = matches.get_one::<String>("device").map(String::as_str);
+    let now = Utc::now();
+    let from = match matches.get_one::<String>("from") {
+        Some(from) => Some(parse_time(from, now)?),
+        None => None,
+    };
+    let to = match matches.get_one::<String>("to") {
+        Some(to) => Some(parse_time(to, now)?),
+        None => None,
+    };
+    let Some(interval) = matches.get_one::<String>("interval") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let interval: Interval = interval.parse()?;
+    let timezone: Option<Tz> = match matches.get_one::<String>("timezone") {
+        Some(name) => Some(
+            name.parse()
+                .map_err(|e| anyhow!("unknown timezone {} {}", name, e))?,
+        ),
+        None => None,
+    };
+    let Some(format) = matches.get_one::<String>("format") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let format: Format = format.parse()?;
+
+    let history = History::open(history_db_path)?;
+    let mut table = Table::default();
+    for metric in metrics {
+        let samples = history.samples(metric, device, from, to)?;
+        table.extend(match interval {
+            Interval::Raw => Table::samples(metric, &samples),
+            _ => Table::aggregates(metric, &aggregate(&samples, interval, timezone.as_ref())),
+        });
+    }
+    match matches.get_one::<String>("out") {
+        Some(out_path) => {
+            let fout = File::create(out_path)
+                .with_context(|| format!("history_main: could not open for write {}", out_path))?;
+            table.write(format, fout)?;
+            info!("wrote: {}", out_path);
+        }
+        None => table.write(format, stdout())?,
+    }
+    Ok(())
+}
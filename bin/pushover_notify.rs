@@ -1,16 +1,24 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use anyhow::{Context, Result};
-use clap::{command, Arg};
+use chrono::{DateTime, Local};
+use clap::{command, value_parser, Arg};
 use crc32fast::Hasher;
 use fs::File;
 use iloveair::audit::read_to_string_with_shellexpand;
+use iloveair::cli::{exit, http_args, init_http, init_logging, logging_args, Failure};
+use iloveair::decision::load_decision;
+use iloveair::history::History;
 use iloveair::notify::read_pushover_json;
 use iloveair::notify::send_pushover_notification;
 use iloveair::notify::PushoverConfig;
+use iloveair::template::render;
 use std::fs;
 use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
+use tracing::{debug, info};
 
-fn main() {
+fn main() -> ExitCode {
     let command = command!()
         .version("0.9")
         .arg(
@@ -27,66 +35,200 @@ fn main() {
                 .short('i')
                 .long("text-in")
                 .value_name("FILE")
-                .required(true)
+                .required_unless_present("json_in")
+                .conflicts_with("json_in")
                 .help("~/.cache/iloveair/Indoor.txt"),
         )
+        .arg(
+            Arg::new("json_in")
+                .short('j')
+                .long("json-in")
+                .value_name("FILE")
+                .help(
+                    "decision written by weather_notify --json-out ~/.cache/iloveair/decision.json",
+                ),
+        )
+        .arg(
+            Arg::new("template")
+                .long("template")
+                .value_name("NAME|FILE")
+                .default_value("pushover")
+                .help("template for --json-in: pushover, text, plain, sms, markdown or a handlebars file"),
+        )
         .arg(
             Arg::new("dry_run")
                 .long("dry-run")
                 .required(false)
                 .num_args(0)
                 .help("don't send notification or write window state"),
+        )
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .required(false)
+                .help("record sent notifications in the history database ~/.local/share/iloveair/history.db"),
+        )
+        .arg(
+            Arg::new("source_down_after")
+                .value_parser(value_parser!(u64))
+                .long("source-down-after")
+                .value_name("N")
+                .requires("history_db")
+                .help("also notify once when Airthings or OpenWeatherMap fetches have failed N times in a row"),
         );
-    let matches = command.get_matches();
+    let matches = http_args(logging_args(command)).get_matches();
+    init_logging(&matches);
+    init_http(&matches);
 
     let Some(pushover_config_path) = matches.get_one::<String>("pushover_config") else {
         // This else block is unreachable because the argument is required.
         unreachable!();
     };
-    let Some(text_in_path) = matches.get_one::<String>("text_in") else {
-        // This else block is unreachable because the argument is required.
+    let input = if let Some(json_in_path) = matches.get_one::<String>("json_in") {
+        let Some(template) = matches.get_one::<String>("template") else {
+            // This else block is unreachable because of the default value.
+            unreachable!();
+        };
+        Input::Decision(json_in_path, template)
+    } else if let Some(text_in_path) = matches.get_one::<String>("text_in") {
+        Input::Text(text_in_path)
+    } else {
+        // This else block is unreachable because one of the arguments is required.
         unreachable!();
     };
 
     let is_dry_run = matches.get_flag("dry_run");
-    match app_main(pushover_config_path, text_in_path, is_dry_run) {
-        Ok(_) => (),
-        Err(e) => println!("Error: {}", e),
-    }
+    let history_db_path = matches.get_one::<String>("history_db");
+    let source_down_after = matches.get_one::<u64>("source_down_after").copied();
+    exit(app_main(
+        pushover_config_path,
+        input,
+        history_db_path,
+        source_down_after,
+        is_dry_run,
+    ))
 }
 
-fn is_changed(text_in_path: &str, text_in: &String) -> Result<bool> {
-    // Step 1: Define checksum path by changing extension from .txt to .md5
-    let checksum_path = text_in_path.replace(".txt", ".pushover.crc32");
+enum Input<'a> {
+    Text(&'a String),
+    Decision(&'a String, &'a String),
+}
+
+/// the checksum lives next to the input: Indoor.txt -> Indoor.pushover.crc32
+fn checksum_path(input_path: &str) -> Result<String> {
+    let input_path = shellexpand::full(input_path)?.to_string();
+    let checksum_path = Path::new(&input_path).with_extension("pushover.crc32");
+    if checksum_path == Path::new(&input_path) {
+        bail!("checksum_path: {} would overwrite itself", input_path);
+    }
+    Ok(checksum_path.to_string_lossy().into())
+}
 
+fn checksum(text_in: &str) -> String {
     let mut hasher = Hasher::new();
     hasher.update(text_in.as_bytes());
-    let computed_checksum = hasher.finalize().to_string();
+    hasher.finalize().to_string()
+}
 
-    // Step 3: Determine if the checksum has changed
-    let changed = match read_to_string_with_shellexpand(&checksum_path) {
-        Ok(existing_checksum) => existing_checksum.trim() != computed_checksum,
+fn is_changed(checksum_path: &str, text_in: &str) -> bool {
+    match fs::read_to_string(checksum_path) {
+        Ok(existing_checksum) => existing_checksum.trim() != checksum(text_in),
         Err(_) => true,
-    };
-
-    // Step 4: If changed, write the new checksum to the checksum file
-    if changed {
-        let mut file = File::create(&checksum_path)?;
-        writeln!(file, "{}", computed_checksum)?;
-        println!("WROTE: {}", checksum_path);
     }
+}
 
-    Ok(changed)
+// only once the notification went out, so a failed send is retried on the next run
+fn write_checksum(checksum_path: &str, text_in: &str) -> Result<()> {
+    let mut file = File::create(checksum_path)?;
+    writeln!(file, "{}", checksum(text_in))?;
+    info!("WROTE: {}", checksum_path);
+    Ok(())
 }
 
-fn app_main(pushover_config_path: &String, text_in_path: &String, is_dry_run: bool) -> Result<()> {
-    let pushover_config = read_pushover_json(pushover_config_path)?;
-    let text_in = read_to_string_with_shellexpand(text_in_path)
-        .with_context(|| anyhow!("could not read {}", text_in_path))?;
-    let is_changed =
-        is_changed(text_in_path, &text_in).with_context(|| anyhow!("error checking checksum"))?;
-    if is_changed {
-        notify_pushover(&pushover_config, is_dry_run, &text_in)?;
+fn app_main(
+    pushover_config_path: &String,
+    input: Input,
+    maybe_history_db_path: Option<&String>,
+    maybe_source_down_after: Option<u64>,
+    is_dry_run: bool,
+) -> Result<()> {
+    let pushover_config = read_pushover_json(pushover_config_path).context(Failure::Config)?;
+    let history = match maybe_history_db_path {
+        Some(history_db_path) => Some(History::open(history_db_path)?),
+        None => None,
+    };
+    if let (Some(history), Some(source_down_after)) = (&history, maybe_source_down_after) {
+        notify_sources_down(&pushover_config, history, source_down_after, is_dry_run)?;
+    }
+    let (text_in, checksum_path) = match input {
+        Input::Text(text_in_path) => {
+            let text_in = read_to_string_with_shellexpand(text_in_path)
+                .with_context(|| anyhow!("could not read {}", text_in_path))?;
+            (text_in, checksum_path(text_in_path)?)
+        }
+        Input::Decision(json_in_path, template) => {
+            let decision = load_decision(json_in_path)?;
+            (render(template, &decision)?, checksum_path(json_in_path)?)
+        }
+    };
+    if is_changed(&checksum_path, &text_in) {
+        let sent = notify_pushover(&pushover_config, is_dry_run, &text_in);
+        if let Some(history) = &history {
+            if !is_dry_run {
+                history.record_fetch_result("pushover", &sent)?;
+            }
+            if sent.is_ok() {
+                history.record_notification("pushover", &text_in, is_dry_run)?;
+            }
+        }
+        sent?;
+        if !is_dry_run {
+            write_checksum(&checksum_path, &text_in)
+                .with_context(|| anyhow!("error writing checksum"))?;
+        }
+    }
+    Ok(())
+}
+/// one notification per outage: a source that has failed `after` times in a row is reported
+/// unless it was already reported since the first failure
+fn notify_sources_down(
+    pushover_config: &PushoverConfig,
+    history: &History,
+    after: u64,
+    is_dry_run: bool,
+) -> Result<()> {
+    for streak in history.failure_streaks()? {
+        // no point telling pushover that pushover is down
+        if streak.source == "pushover" || streak.failures < after {
+            continue;
+        }
+        let prefix = format!("data source down: {}", streak.source);
+        let already_sent = history
+            .notifications(Some(streak.since), None)?
+            .iter()
+            .any(|n| !n.dry_run && n.message.starts_with(&prefix));
+        if already_sent {
+            continue;
+        }
+        let since = DateTime::from_timestamp(streak.since, 0)
+            .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        let message = format!(
+            "{}, {} failed fetches in a row since {}, last error: {}",
+            prefix,
+            streak.failures,
+            since,
+            streak.last_error.as_deref().unwrap_or("unknown")
+        );
+        let sent = notify_pushover(pushover_config, is_dry_run, &message);
+        if !is_dry_run {
+            history.record_fetch_result("pushover", &sent)?;
+        }
+        if sent.is_ok() {
+            history.record_notification("pushover", &message, is_dry_run)?;
+        }
+        sent?;
     }
     Ok(())
 }
@@ -95,7 +237,7 @@ fn notify_pushover(
     is_dry_run: bool,
     text_in: &str,
 ) -> Result<()> {
-    println!("send notification");
+    debug!("send notification");
     send_pushover_notification(is_dry_run, pushover_config, text_in)?;
     Ok(())
 }
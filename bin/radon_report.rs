@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use clap::{command, Arg};
+use iloveair::cli::{exit, init_logging, logging_args};
+use iloveair::history::History;
+use iloveair::radon::{monthly_report, previous_month, read_radon_log, RadonReading};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::ExitCode;
+use tracing::info;
+
+// monthly radon summary from the log read_waveplus --radon-log appends to
+
+fn main() -> ExitCode {
+    let command = command!()
+        .version("0.9")
+        .arg(
+            Arg::new("radon_log")
+                .short('l')
+                .long("log")
+                .value_name("FILE")
+                .required_unless_present("history_db")
+                .conflicts_with("history_db")
+                .help("radon log written by read_waveplus ~/.cache/iloveair/radon.jsonl"),
+        )
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .required(false)
+                .help("read radon from the history database instead of a log ~/.local/share/iloveair/history.db"),
+        )
+        .arg(
+            Arg::new("month")
+                .short('m')
+                .long("month")
+                .value_name("YYYY-MM")
+                .required(false)
+                .help("month to report on, defaults to last month"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .required(false)
+                .num_args(0)
+                .help("write the report as JSON instead of text"),
+        )
+        .arg(
+            Arg::new("out")
+                .short('o')
+                .long("out")
+                .value_name("FILE")
+                .required(false)
+                .help("output file, defaults to stdout"),
+        );
+    let matches = logging_args(command).get_matches();
+    init_logging(&matches);
+
+    let source = if let Some(history_db_path) = matches.get_one::<String>("history_db") {
+        Source::History(history_db_path)
+    } else if let Some(radon_log_path) = matches.get_one::<String>("radon_log") {
+        Source::Log(radon_log_path)
+    } else {
+        // This else block is unreachable because one of the arguments is required.
+        unreachable!();
+    };
+    let month = matches.get_one::<String>("month");
+    let is_json = matches.get_flag("json");
+    let out_path = matches.get_one::<String>("out");
+
+    exit(app_main(source, month, is_json, out_path))
+}
+
+enum Source<'a> {
+    Log(&'a String),
+    History(&'a String),
+}
+
+fn parse_month(month: &str) -> Result<(i32, u32)> {
+    let (year, month_number) = month
+        .split_once('-')
+        .ok_or_else(|| anyhow!("parse_month: expected YYYY-MM, got {}", month))?;
+    let year: i32 = year
+        .parse()
+        .with_context(|| format!("parse_month: bad year {}", month))?;
+    let month_number: u32 = month_number
+        .parse()
+        .with_context(|| format!("parse_month: bad month {}", month))?;
+    if !(1..=12).contains(&month_number) {
+        return Err(anyhow!("parse_month: bad month {}", month));
+    }
+    Ok((year, month_number))
+}
+
+fn app_main(
+    source: Source,
+    month: Option<&String>,
+    is_json: bool,
+    out_path: Option<&String>,
+) -> Result<()> {
+    let (year, month) = match month {
+        Some(month) => parse_month(month)?,
+        None => previous_month(Utc::now()),
+    };
+    let readings = match source {
+        Source::Log(radon_log_path) => read_radon_log(radon_log_path)?,
+        Source::History(history_db_path) => History::open(history_db_path)?
+            .samples("indoor.radon", None, None, None)?
+            .into_iter()
+            .map(|sample| RadonReading {
+                time: sample.time,
+                bq_m3: sample.value,
+            })
+            .collect(),
+    };
+    let report = monthly_report(&readings, year, month);
+    let text = if is_json {
+        serde_json::to_string_pretty(&report)?
+    } else {
+        report.to_string()
+    };
+    if let Some(out_path) = out_path {
+        let mut fout = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(out_path)
+            .with_context(|| format!("app_main: could not open for write {}", out_path))?;
+        fout.write_all(text.as_bytes())
+            .with_context(|| format!("app_main: could not write {}", out_path))?;
+        info!("wrote: {}", out_path);
+    } else {
+        print!("{}", text);
+    }
+    Ok(())
+}
@@ -1,15 +1,23 @@
-extern crate reqwest;
 extern crate tokio;
 use anyhow::{anyhow, Context, Error, Result};
 use chrono::{DateTime, Duration, Utc};
 use clap::{command, Arg};
 use iloveair::audit::read_to_string_with_path;
-use iloveair::config::read_airthings_config;
-use reqwest::Response;
+use iloveair::cache::{ttl, Cache};
+use iloveair::cli::{
+    exit, failure_class, http_args, init_http, init_logging, logging_args, Failure,
+};
+use iloveair::config::{read_airthings_config, AirthingsConfig};
+use iloveair::history::History;
+use iloveair::http::{self, Request};
+use iloveair::influx;
+use iloveair::radon::{append_radon_log, RadonReading};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::fs::{self, OpenOptions};
+use std::fs;
 use std::path::Path;
+use std::process::ExitCode;
+use tracing::{debug, info, warn};
 
 #[derive(Serialize, Deserialize)]
 struct TokenResponse {
@@ -52,7 +60,11 @@ fn write_access_token<P: AsRef<Path>>(path: P, access_token: &AccessToken) -> Re
     let json_data = serde_json::to_string_pretty(access_token)
         .map_err(|e| anyhow!(format!("could not write token {}", e)))?;
 
-    fs::write(path, json_data)?;
+    // temp file and rename so a token is never half written
+    let mut tmp_path = path.as_ref().as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, json_data)?;
+    fs::rename(&tmp_path, path)?;
     Ok(())
 }
 
@@ -64,38 +76,48 @@ struct TokenRequest<'a> {
     //scope: Vec<&'static str>,
 }
 
-async fn fetch_token<'a>(client_id: &'a str, client_secret: &'a str) -> Result<AccessToken, Error> {
-    let client = reqwest::Client::new();
-    //let token_request =
-    const TOKEN_URL: &str = "https://accounts-api.airthings.com/v1/token";
+// the blocking HTTP client must not run on a runtime worker directly
+fn send(request: &Request) -> Result<http::Response> {
+    tokio::task::block_in_place(|| http::client().send(request))
+}
 
-    let response: Response = client
-        .post(TOKEN_URL)
-        .json(&TokenRequest {
-            grant_type: "client_credentials",
-            client_id,
-            client_secret,
-        })
-        .send()
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to send request: {} {}", TOKEN_URL, e))?;
+async fn fetch_token<'a>(
+    token_url: &'a str,
+    client_id: &'a str,
+    client_secret: &'a str,
+) -> Result<AccessToken, Error> {
+    //let token_request =
+    let response = send(&Request::post(token_url).json(&TokenRequest {
+        grant_type: "client_credentials",
+        client_id,
+        client_secret,
+    })?)
+    .with_context(|| format!("Failed to send request: {}", token_url))?;
     //scope: vec!["read:device"],
 
-    println!("response {:?}", response);
-    let text = response
-        .text()
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to get text: {} {}", TOKEN_URL, e))?;
-
-    println!("text {:?}", text);
-    let json_data = serde_json::from_str::<Value>(&text)
-        .map_err(|e| anyhow::anyhow!("Failed to parse response to JSON: {} {} ", text, e))?;
+    // the response holds the access token
+    debug!("token response status {}", response.status);
+    // bad client credentials come back as a JSON error, anything else like a 500 is the service
+    if !response.is_success() {
+        if let Some(e) = response
+            .json::<Value>()
+            .ok()
+            .and_then(|json| check_json_errors(&json).err())
+        {
+            return Err(e.context(Failure::Auth));
+        }
+    }
+    let response = response.error_for_status()?;
+    let json_data = response
+        .json::<Value>()
+        .with_context(|| format!("Failed to parse response to JSON: {}", token_url))?;
 
-    check_json_errors(&json_data)?;
+    // errors from the token endpoint are bad client credentials
+    check_json_errors(&json_data).context(Failure::Auth)?;
     let token_response: TokenResponse = serde_json::from_value(json_data).map_err(|e| {
         anyhow::anyhow!(
             "could not parse json into TokenResponse: {} {}",
-            TOKEN_URL,
+            token_url,
             e
         )
     })?;
@@ -109,7 +131,7 @@ async fn fetch_token<'a>(client_id: &'a str, client_secret: &'a str) -> Result<A
 // https://airthings.org/api/air-indoor
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 10)]
-async fn main() {
+async fn main() -> ExitCode {
     let command = command!()
         .version("0.9")
         .arg(
@@ -142,8 +164,40 @@ async fn main() {
                 .required(false)
                 .num_args(0)
                 .help("list devices instead of downloading data"),
+        )
+        .arg(
+            Arg::new("radon_log")
+                .long("radon-log")
+                .value_name("FILE")
+                .required(false)
+                .help("append radon readings for radon_report ~/.cache/iloveair/radon.jsonl"),
+        )
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .required(false)
+                .help(
+                    "record the sample in the history database ~/.local/share/iloveair/history.db",
+                ),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .required(false)
+                .num_args(0)
+                .help("fetch even if the cached sample is still fresh"),
+        )
+        .arg(
+            Arg::new("influx")
+                .long("influx")
+                .value_name("FILE")
+                .required(false)
+                .help("write the sample as Influx line protocol, config ~/.config/iloveair/influx.json"),
         );
-    let matches = command.get_matches();
+    let matches = http_args(logging_args(command)).get_matches();
+    init_logging(&matches);
+    init_http(&matches);
 
     let Some(airthings_config_path) = matches.get_one::<String>("airthing_config") else {
         // This else block is unreachable because the argument is required.
@@ -160,47 +214,36 @@ async fn main() {
         unreachable!();
     };
     let do_list_devices = matches.get_flag("list_devices");
+    let is_force = matches.get_flag("force");
+    let outputs = Outputs {
+        radon_log_path: matches.get_one::<String>("radon_log"),
+        history_db_path: matches.get_one::<String>("history_db"),
+        influx_config_path: matches.get_one::<String>("influx"),
+    };
 
-    match app_main(
-        airthings_config_path,
-        indoor_cache_path,
-        airthings_token_cache_path,
-        do_list_devices,
+    exit(
+        app_main(
+            airthings_config_path,
+            indoor_cache_path,
+            airthings_token_cache_path,
+            &outputs,
+            do_list_devices,
+            is_force,
+        )
+        .await,
     )
-    .await
-    {
-        Ok(_) => (),
-        Err(e) => println!("Error: {}", e),
-    }
 }
 
-fn save_sample_data(indoor_json_path: &str, sample: &SampleData) -> Result<()> {
-    let fout = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(indoor_json_path)
-        .with_context(|| {
-            format!(
-                "save_indoor_response: could not open for write {}",
-                indoor_json_path
-            )
-        })?;
-    serde_json::to_writer_pretty(fout, sample)
-        .map_err(|e| anyhow!(format!("could not convert Sample to json {}", e)))?;
-
-    println!("wrote: {}", indoor_json_path);
+fn save_sample_data(
+    indoor_json_path: &str,
+    url: &str,
+    status: u16,
+    sample: &SampleData,
+) -> Result<()> {
+    Cache::new(indoor_json_path, ttl("airthings")).write(sample, Some(url), Some(status))?;
+    info!("wrote: {}", indoor_json_path);
     Ok(())
 }
-fn file_modified_in_last_minutes(path: &str, minutes: u64) -> bool {
-    if !std::path::Path::new(path).exists() {
-        return false;
-    }
-    let metadata = std::fs::metadata(path).unwrap();
-    let modified = metadata.modified().unwrap();
-    let modified = modified.elapsed().unwrap().as_secs();
-    modified < minutes * 60
-}
 fn check_json_errors(json: &Value) -> Result<(), Error> {
     if let Some(error) = json.get("error").and_then(Value::as_str) {
         let error_desc = json
@@ -224,68 +267,140 @@ fn check_json_errors(json: &Value) -> Result<(), Error> {
     Ok(())
 }
 
+struct Outputs<'a> {
+    radon_log_path: Option<&'a String>,
+    history_db_path: Option<&'a String>,
+    influx_config_path: Option<&'a String>,
+}
+
 async fn app_main(
     airthings_config_json_path: &String,
     indoor_json_cache_path: &String,
     airthings_token_cache_path: &String,
+    outputs: &Outputs<'_>,
     do_list_devices: bool,
+    is_force: bool,
 ) -> Result<()> {
-    let config = read_airthings_config(airthings_config_json_path).map_err(|e| {
-        anyhow!(format!(
-            "could not read config {} {}",
-            airthings_config_json_path, e
-        ))
-    })?;
-    let update_no_more_than_minutes = 10;
-    if file_modified_in_last_minutes(indoor_json_cache_path, update_no_more_than_minutes) {
-        println!(
+    let config = read_airthings_config(airthings_config_json_path)
+        .with_context(|| format!("could not read config {}", airthings_config_json_path))
+        .context(Failure::Config)?;
+    let ttl = ttl("airthings");
+    if !is_force && !do_list_devices && Cache::new(indoor_json_cache_path, ttl).is_fresh() {
+        info!(
             "SKIPPING: {} less than {} minutes old",
-            indoor_json_cache_path, update_no_more_than_minutes
+            indoor_json_cache_path,
+            ttl.num_minutes()
         );
         return Ok(());
     }
-    let access_token = if let Some(access_token) = read_json_token(airthings_token_cache_path) {
-        access_token
-    } else {
-        println!("client_id: {}", config.client_id);
-        println!("client_secret: {}", config.client_secret);
-        let access_token = fetch_token(config.client_id.as_str(), config.client_secret.as_str())
-            .await
-            .map_err(|e| anyhow!(format!("fetch_token {}", e)))?;
-        write_access_token(airthings_token_cache_path, &access_token)
-            .map_err(|e| anyhow!(format!("write failed {} {}", airthings_token_cache_path, e)))?;
-        access_token
+    let token_url = http::endpoint(&config.accounts_url, "/v1/token", config.allow_http)?;
+    let devices_url = http::endpoint(&config.api_url, "/v1/devices", config.allow_http)?;
+    let history = match outputs.history_db_path {
+        Some(history_db_path) => Some(History::open(history_db_path)?),
+        None => None,
+    };
+    let cached_token = read_json_token(airthings_token_cache_path);
+    let is_cached = cached_token.is_some();
+    let mut access_token = match cached_token {
+        Some(access_token) => access_token,
+        None => {
+            new_access_token(
+                &token_url,
+                &config,
+                airthings_token_cache_path,
+                history.as_ref(),
+            )
+            .await?
+        }
     };
 
     if do_list_devices {
-        list_devices(&access_token)
+        list_devices(&devices_url, &access_token)
             .await
-            .map_err(|e| anyhow!(format!("list_devices {}", e)))?;
+            .context("list_devices")?;
         return Ok(());
     }
 
-    let device_id = config.device_id;
-    let sample = get_latest_reading(&device_id, &access_token)
-        .await
-        .map_err(|e| anyhow!(format!("get_latest_reading {} {}", &device_id, e)))?;
-    println!("sample: {:?}", sample);
-    save_sample_data(indoor_json_cache_path, &sample)
+    let device_id = &config.device_id;
+    let mut fetched = get_latest_reading(&devices_url, device_id, &access_token).await;
+    if let Some(history) = &history {
+        history.record_fetch_result("airthings", &fetched)?;
+    }
+    // a token revoked or expired on the server before its local expiration, without a new
+    // one every run would fail the same way until then
+    let is_rejected = matches!(&fetched, Err(e) if failure_class(e) == Some(Failure::Auth));
+    if is_cached && is_rejected {
+        warn!("cached token rejected, fetching a new one");
+        fs::remove_file(airthings_token_cache_path)
+            .with_context(|| format!("could not remove {}", airthings_token_cache_path))?;
+        access_token = new_access_token(
+            &token_url,
+            &config,
+            airthings_token_cache_path,
+            history.as_ref(),
+        )
+        .await?;
+        fetched = get_latest_reading(&devices_url, device_id, &access_token).await;
+        if let Some(history) = &history {
+            history.record_fetch_result("airthings", &fetched)?;
+        }
+    }
+    let (url, status, sample) =
+        fetched.with_context(|| format!("get_latest_reading {}", &device_id))?;
+    debug!("sample: {:?}", sample);
+    save_sample_data(indoor_json_cache_path, &url, status, &sample)?;
+    if let Some(radon_log_path) = outputs.radon_log_path {
+        let reading = RadonReading {
+            time: sample.time as i64,
+            bq_m3: sample.radon_short_term_avg,
+        };
+        append_radon_log(radon_log_path, &reading)?;
+        info!("appended: {}", radon_log_path);
+    }
+    if let Some(history) = &history {
+        history.record_indoor(device_id, &serde_json::to_value(&sample)?)?;
+    }
+    if let Some(influx_config_path) = outputs.influx_config_path {
+        let points: Vec<_> = influx::indoor_point(device_id, &serde_json::to_value(&sample)?)
+            .into_iter()
+            .collect();
+        // the blocking HTTP client must not run on a runtime worker directly
+        tokio::task::block_in_place(|| influx::write_points(influx_config_path, &points))?;
+    }
+    Ok(())
 }
 
-async fn list_devices(token: &AccessToken) -> Result<()> {
-    let client = reqwest::Client::new();
-    let url = "https://ext-api.airthings.com/v1/devices";
-    let text = client
-        .get(url) // assuming this is the correct endpoint
-        .bearer_auth(&token.access_token) // set the authorization header
-        .send()
-        .await
-        .map_err(|e| anyhow!(format!("list devices {}", e)))?
-        .text()
-        .await
-        .map_err(|e| anyhow!(format!("list devices {}", e)))?;
-    let json: serde_json::Value = serde_json::from_str(&text)
-        .with_context(|| format!("list_devices: could not parse devices {}", text))?;
+/// a token from the accounts API, cached in `airthings_token_cache_path`
+async fn new_access_token(
+    token_url: &str,
+    config: &AirthingsConfig,
+    airthings_token_cache_path: &String,
+    history: Option<&History>,
+) -> Result<AccessToken> {
+    debug!("client_id: {}", config.client_id);
+    let fetched = fetch_token(
+        token_url,
+        config.client_id.as_str(),
+        config.client_secret.as_str(),
+    )
+    .await;
+    if let Some(history) = history {
+        history.record_fetch_result("airthings", &fetched)?;
+    }
+    let access_token = fetched.context("fetch_token")?;
+    write_access_token(airthings_token_cache_path, &access_token)
+        .map_err(|e| anyhow!(format!("write failed {} {}", airthings_token_cache_path, e)))?;
+    Ok(access_token)
+}
+
+async fn list_devices(url: &str, token: &AccessToken) -> Result<()> {
+    let response = send(&Request::get(url).bearer_auth(&token.access_token))
+        .context("list devices")?
+        .error_for_status()
+        .context("list devices")?;
+    let json: serde_json::Value = response
+        .json()
+        .with_context(|| format!("list_devices: could not parse devices {}", response.body))?;
 
     println!("{}", serde_json::to_string_pretty(&json).unwrap());
     Ok(())
@@ -300,36 +415,42 @@ struct SampleData {
     temp: f64,
     time: u64,
     relay_device_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    co2: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    voc: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pressure: Option<f64>,
 }
 #[derive(Deserialize)]
 struct SampleDataKey {
     data: SampleData,
 }
-async fn get_latest_reading(device_id: &String, token: &AccessToken) -> Result<SampleData> {
-    let client = reqwest::Client::new();
-    let url = format!(
-        "https://ext-api.airthings.com/v1/devices/{}/latest-samples",
-        device_id
-    );
-    let response = client
-        .get(&url) // assuming this is the correct endpoint
-        .bearer_auth(&token.access_token) // set the authorization header
-        .send()
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to send request to get latest reading: {}", e))?;
+async fn get_latest_reading(
+    devices_url: &str,
+    device_id: &String,
+    token: &AccessToken,
+) -> Result<(String, u16, SampleData)> {
+    let url = format!("{}/{}/latest-samples", devices_url, device_id);
+    let response = send(&Request::get(&url).bearer_auth(&token.access_token))
+        .context("Failed to send request to get latest reading")?;
 
-    println!("latest: {:?}", response);
-    let text = response
-        .text()
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to read request to get latest reading: {}", e))?;
-    println!("reading: {}", text);
+    let status = response.status;
+    debug!("latest: {} {}", status, response.body);
 
+    if status == 401 || status == 403 {
+        let e = serde_json::from_str::<Value>(&response.body)
+            .ok()
+            .and_then(|json| check_json_errors(&json).err())
+            .unwrap_or_else(|| anyhow!("latest reading status {}: {}", status, response.body));
+        return Err(e.context(Failure::Auth));
+    }
+    let text = response.error_for_status()?.body;
     let json_data = serde_json::from_str::<Value>(&text)
-        .map_err(|e| anyhow::anyhow!("Failed to parse response to JSON: {} {} ", text, e))?;
+        .with_context(|| format!("Failed to parse response to JSON: {}", text))?;
 
     check_json_errors(&json_data)?;
     let sample: SampleDataKey = serde_json::from_value(json_data)
-        .map_err(|e| anyhow::anyhow!("could not parse json into Reading : {} {}", url, e))?;
-    Ok(sample.data)
+        .with_context(|| format!("could not parse json into Reading : {}", url))?;
+    Ok((url, status, sample.data))
 }
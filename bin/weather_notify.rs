@@ -1,52 +1,76 @@
 use anyhow::anyhow;
 use anyhow::{Context, Result};
-use chrono::Local;
+use chrono::{DateTime, Utc};
 use clap::{command, value_parser, Arg};
 use iloveair::airthings_radon::celsius_to_fahrenheit;
 use iloveair::airthings_radon::Indoor;
 use iloveair::audit::read_to_string_with_shellexpand;
-use iloveair::pretty::PrettyBool;
-use iloveair::weather::{load_weather_response, weather_humidity, weather_tempurature};
+use iloveair::cache::unwrap_payload;
+use iloveair::cli::{exit, init_logging, logging_args, Failure};
+use iloveair::decision::{
+    decide_at, save_decision, AirQuality, Conditions, IndoorAir, Inputs, Thresholds,
+};
+use iloveair::history::{parse_time, History};
+use iloveair::pollution::{load_pollution_response, pollution_aqi, pollution_time};
+use iloveair::rules::{read_rules_json, RuleSet};
+use iloveair::template::render;
+use iloveair::weather::{
+    load_weather_response, weather_conditions, weather_humidity, weather_tempurature, weather_time,
+};
 use std::fs::OpenOptions;
 use std::io::Write;
+use std::process::ExitCode;
+use tracing::{error, info};
 
-static DOWN: &str = "↓";
-static UP: &str = "↗";
-static EQ: &str = "=";
-
-struct IndoorSettings {
-    max_humidity: u64,
-    min_temp: f64,
-    max_temp: f64,
-}
-#[derive(Clone, Debug)]
-struct HumidityTemp {
-    humidity: u64,
-    temp: f64,
-}
-
-fn read_indoor_json(indoor_cache_path: &String) -> Result<HumidityTemp> {
+fn read_indoor_json(indoor_cache_path: &String) -> Result<(Conditions, IndoorAir)> {
     let contents = read_to_string_with_shellexpand(indoor_cache_path).with_context(|| {
         format!(
             "load_weather_response: could not read {}",
             indoor_cache_path
         )
     })?;
-    let indoor: Indoor = serde_json::from_str(&contents).with_context(|| {
+    let indoor_json: serde_json::Value = serde_json::from_str(&contents).with_context(|| {
         format!(
             "load_weather_response: could not parse {}",
             indoor_cache_path
         )
     })?;
+    let indoor: Indoor =
+        serde_json::from_value(unwrap_payload(indoor_json)).with_context(|| {
+            format!(
+                "load_weather_response: could not parse {}",
+                indoor_cache_path
+            )
+        })?;
     let indoor_temp_celsius = indoor.temp;
     let humidity = indoor.humidity;
     let indoor_temp = celsius_to_fahrenheit(indoor_temp_celsius);
-    Ok(HumidityTemp {
-        humidity: humidity as u64,
-        temp: indoor_temp,
+    Ok((
+        Conditions {
+            humidity: humidity as u64,
+            temp: indoor_temp,
+            time: Some(indoor.time),
+        },
+        IndoorAir {
+            co2: indoor.co2,
+            voc: indoor.voc,
+        },
+    ))
+}
+fn read_pollution_json(pollution_cache_path: &String) -> Result<AirQuality> {
+    let pollution_json = load_pollution_response(shellexpand::full(pollution_cache_path)?.as_ref())
+        .with_context(|| {
+            format!(
+                "read_pollution_json: could not load {}",
+                pollution_cache_path
+            )
+        })?;
+    Ok(AirQuality {
+        aqi: pollution_aqi(&pollution_json)?,
+        time: pollution_time(&pollution_json),
     })
 }
-fn main() {
+fn main() -> ExitCode {
     let command = command!()
         .version("0.9")
         .arg(
@@ -96,6 +120,36 @@ fn main() {
                 .required(true)
                 .help("~/.cache/iloveair/Indoor.txt"),
         )
+        .arg(
+            Arg::new("json_out_path")
+                .long("json-out")
+                .value_name("FILE")
+                .required(false)
+                .help("~/.cache/iloveair/decision.json"),
+        )
+        .arg(
+            Arg::new("pollution_cache")
+                .short('p')
+                .long("pollution")
+                .value_name("FILE")
+                .required(false)
+                .help("~/.cache/iloveair/pollution.json, makes `aqi` available to rules"),
+        )
+        .arg(
+            Arg::new("rules_config")
+                .short('r')
+                .long("rules")
+                .value_name("FILE")
+                .required(false)
+                .help("~/.config/iloveair/rules.json, defaults to the builtin humidity and temperature rules"),
+        )
+        .arg(
+            Arg::new("template")
+                .long("template")
+                .value_name("NAME|FILE")
+                .default_value("text")
+                .help("text, plain, sms, markdown, html or a handlebars file"),
+        )
         .arg(
             Arg::new("max_temp")
                 .value_parser(value_parser!(f64))
@@ -103,8 +157,23 @@ fn main() {
                 .value_name("VALUE")
                 .default_value("84.0")
                 .help("Maximum allowable temperature"),
+        )
+        .arg(
+            Arg::new("history_db")
+                .long("db")
+                .value_name("FILE")
+                .required(false)
+                .help("record the decision in the history database ~/.local/share/iloveair/history.db"),
+        )
+        .arg(
+            Arg::new("now")
+                .long("now")
+                .value_name("TIME")
+                .required(false)
+                .help("decide as if it were TIME, RFC 3339 or YYYY-MM-DD, to replay a recorded day"),
         );
-    let matches = command.get_matches();
+    let matches = logging_args(command).get_matches();
+    init_logging(&matches);
 
     let Some(weather_cache_path) = matches.get_one::<String>("weather_cache") else {
         // this else block is unreachable because the argument is required.
@@ -131,126 +200,140 @@ fn main() {
         // This else block is unreachable because of the default value.
         unreachable!();
     };
-    let indoor_settings = IndoorSettings {
+    let json_out_path = matches.get_one::<String>("json_out_path");
+    let pollution_cache_path = matches.get_one::<String>("pollution_cache");
+    let rules_config_path = matches.get_one::<String>("rules_config");
+    let history_db_path = matches.get_one::<String>("history_db");
+    let Some(template) = matches.get_one::<String>("template") else {
+        // This else block is unreachable because of the default value.
+        unreachable!();
+    };
+    let thresholds = Thresholds {
         max_humidity: *max_humidity,
         min_temp: *min_temp,
         max_temp: *max_temp,
     };
     let is_dry_run = matches.get_flag("dry_run");
-    match app_main(
-        weather_cache_path,
+    let input_paths = InputPaths {
+        weather_json_path: weather_cache_path,
         indoor_cache_path,
-        is_dry_run,
-        indoor_settings,
+        pollution_cache_path,
+    };
+    let outputs = Outputs {
         text_out_path,
-    ) {
-        Ok(_) => (),
-        Err(e) => println!("Error: {}", e),
-    }
+        json_out_path,
+        history_db_path,
+        template,
+    };
+    let now = match matches.get_one::<String>("now") {
+        Some(now) => match parse_time(now, Utc::now()) {
+            Ok(now) => DateTime::from_timestamp(now, 0).unwrap_or_else(Utc::now),
+            Err(e) => return exit(Err(e)),
+        },
+        None => Utc::now(),
+    };
+    exit(app_main(
+        input_paths,
+        rules_config_path,
+        thresholds,
+        outputs,
+        is_dry_run,
+        now,
+    ))
+}
+struct InputPaths<'a> {
+    weather_json_path: &'a String,
+    indoor_cache_path: &'a String,
+    pollution_cache_path: Option<&'a String>,
+}
+struct Outputs<'a> {
+    text_out_path: &'a String,
+    json_out_path: Option<&'a String>,
+    history_db_path: Option<&'a String>,
+    template: &'a str,
 }
 fn app_main(
-    weather_json_path: &String,
-    indoor_cache_path: &String,
+    input_paths: InputPaths,
+    maybe_rules_config_path: Option<&String>,
+    thresholds: Thresholds,
+    outputs: Outputs,
     is_dry_run: bool,
-    indoor_settings: IndoorSettings,
-    text_out_path: &String,
+    now: DateTime<Utc>,
 ) -> Result<()> {
-    let indoor = read_indoor_json(indoor_cache_path)?;
+    let weather_json_path = input_paths.weather_json_path;
+    let rule_set = match maybe_rules_config_path {
+        Some(rules_config_path) => read_rules_json(rules_config_path).context(Failure::Config)?,
+        None => RuleSet::default(),
+    };
+    let (indoor, indoor_air) = read_indoor_json(input_paths.indoor_cache_path)?;
     let weather_json = load_weather_response(weather_json_path).with_context(|| {
         anyhow!(
             "load_weather_response: could not load {}",
             weather_json_path
         )
     })?;
-    let outdoor_humidity = weather_humidity(&weather_json).with_context(|| {
-        anyhow!(
-            "load_weather_response: could parse humidity {}",
-            weather_json_path
-        )
-    })?;
-    let outdoor_temp = weather_tempurature(&weather_json).with_context(|| {
-        anyhow!(
-            "load_weather_response: could parse temperature {}",
-            weather_json_path
-        )
-    })?;
-    let outdoor = HumidityTemp {
+    let outdoor_humidity = weather_humidity(&weather_json)
+        .with_context(|| {
+            anyhow!(
+                "load_weather_response: could parse humidity {}",
+                weather_json_path
+            )
+        })
+        .context(Failure::Parse)?;
+    let outdoor_temp = weather_tempurature(&weather_json)
+        .with_context(|| {
+            anyhow!(
+                "load_weather_response: could parse temperature {}",
+                weather_json_path
+            )
+        })
+        .context(Failure::Parse)?;
+    let outdoor = Conditions {
         humidity: outdoor_humidity,
         temp: outdoor_temp,
+        time: weather_time(&weather_json),
     };
-    let can_let_in_humidify =
-        outdoor.humidity <= indoor.humidity || outdoor.humidity <= indoor_settings.max_humidity;
-    let can_let_in_temperature =
-        outdoor.temp >= indoor_settings.min_temp && outdoor.temp <= indoor_settings.max_temp;
-    let window_should_be_open = can_let_in_humidify && can_let_in_temperature;
-    print_report(
-        indoor.clone(),
-        outdoor.clone(),
-        can_let_in_humidify,
-        can_let_in_temperature,
-        window_should_be_open,
-        text_out_path,
-        is_dry_run,
-    );
-    Ok(())
-}
-fn updown<T: PartialOrd + ToString>(fst: T, snd: T) -> String {
-    if let Some(o) = fst.partial_cmp(&snd) {
-        match o {
-            std::cmp::Ordering::Less => DOWN.into(),
-            std::cmp::Ordering::Greater => UP.into(),
-            std::cmp::Ordering::Equal => EQ.into(),
+    let pollution = match input_paths.pollution_cache_path {
+        Some(pollution_cache_path) => Some(read_pollution_json(pollution_cache_path)?),
+        None => None,
+    };
+    let decision = decide_at(
+        Inputs {
+            indoor,
+            outdoor,
+            pollution,
+            weather: Some(weather_conditions(&weather_json)),
+            indoor_air: Some(indoor_air),
+        },
+        thresholds,
+        &rule_set,
+        now,
+    )?;
+    if let Some(json_out_path) = outputs.json_out_path {
+        if !is_dry_run {
+            save_decision(json_out_path, &decision)?;
+            info!("wrote: {}", json_out_path);
         }
-    } else {
-        "?".into()
     }
+    if let Some(history_db_path) = outputs.history_db_path {
+        if !is_dry_run {
+            History::open(history_db_path)?.record_decision(&decision)?;
+        }
+    }
+    let report = render(outputs.template, &decision)?;
+    print_report(&report, outputs.text_out_path, is_dry_run);
+    if decision.is_unknown() {
+        // the report is still written, the exit code tells cron and systemd the data is stale
+        let reasons: Vec<&str> = decision
+            .stale_inputs
+            .iter()
+            .map(|stale| stale.reason.as_str())
+            .collect();
+        return Err(anyhow!("{}", reasons.join(", ")).context(Failure::Stale));
+    }
+    Ok(())
 }
-fn print_report(
-    indoor: HumidityTemp,
-    outdoor: HumidityTemp,
-    can_let_in_humidify: bool,
-    can_let_in_temperature: bool,
-    window_should_be_open: bool,
-    text_out_path: &String,
-    is_dry_run: bool,
-) {
-    let now = Local::now().naive_local(); // Get current date and time in naive format
-    let mut report = String::new();
-
-    report.push_str(&format!("Time: {}\n", now.format("%A %Y-%m-%d %I:%M %p")));
-    report.push_str(&format!(
-        "window_should_be_open: 🪟{}\n",
-        PrettyBool::new(window_should_be_open)
-    ));
-    report.push_str(&format!(
-        "indoor temp: 🏠{} {}🌡️\n",
-        updown(indoor.temp, outdoor.temp),
-        indoor.temp
-    ));
-    report.push_str(&format!(
-        "outdoor temp: 🌳{} {}🌡️\n",
-        updown(outdoor.temp, indoor.temp),
-        outdoor.temp
-    ));
-    report.push_str(&format!(
-        "Indoor humidity: 🏠{} {}💧\n",
-        updown(indoor.humidity, outdoor.humidity),
-        indoor.humidity
-    ));
-    report.push_str(&format!(
-        "outdoor humidity: 🌳 {} {}💧\n",
-        updown(outdoor.humidity, indoor.humidity),
-        outdoor.humidity
-    ));
-    report.push_str(&format!(
-        "can_let_in_humidify: 💧{}\n",
-        PrettyBool::new(can_let_in_humidify)
-    ));
-    report.push_str(&format!(
-        "can_let_in_temperature: 🌡️{}\n",
-        PrettyBool::new(can_let_in_temperature)
-    ));
-
+fn print_report(report: &str, text_out_path: &String, is_dry_run: bool) {
     // Print to stdout
     println!("{}", report);
 
@@ -262,10 +345,10 @@ fn print_report(
             .open(text_out_path)
         {
             if let Err(e) = writeln!(file, "{}", report) {
-                eprintln!("Failed to write to file: {}", e);
+                error!("Failed to write to file: {}", e);
             }
         } else {
-            eprintln!("Failed to open file: {}", text_out_path);
+            error!("Failed to open file: {}", text_out_path);
         }
     }
 }
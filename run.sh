@@ -23,6 +23,7 @@ ${CAT} $CACHE_INDOOR
 #cargo run --bin weather_notify --dry-run --pushover $CONFIG_PUSHOVER --weather $CACHE_WEATHER --indoor $CACHE_INDOOR --window $CACHE_WINDOW
 #set -e
 #/home/sean/git/python/venv/bin/python python/notion_notify.py --name Rain "~/git/python/venv/bin/python python/save_rain_data.py"
+#cargo run --bin getrain -- --config ~/.config/iloveair/rain.json
 
 # original
 #/home/sean/git/python/venv/bin/python python/notion_notify.py --name Indoor "~/.cargo/bin/weather_notify --dry-run --pushover ${CONFIG_PUSHOVER} --weather ${CACHE_WEATHER} --indoor ${CACHE_INDOOR} --window ${CACHE_WINDOW}"
@@ -10,8 +10,15 @@ pub struct Indoor {
     pub humidity: f64,
     radon_short_term_avg: f64,
     pub temp: f64,
-    time: i64,
+    pub time: i64,
     relay_device_type: String,
+    // only reported by devices with the sensor, e.g. Wave Plus
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub co2: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voc: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<f64>,
 }
 pub fn celsius_to_fahrenheit(celsius: f64) -> f64 {
     celsius * 9.0 / 5.0 + 32.0
@@ -44,3 +51,24 @@ fn test() {
     let serialized_data = serde_json::to_string(&indoor).unwrap();
     println!("{}", serialized_data);
 }
+
+#[test]
+fn test_wave_plus() {
+    let data = r#"
+    {
+        "battery": 99,
+        "co2": 1042.0,
+        "humidity": 61.0,
+        "pressure": 1003.2,
+        "radonShortTermAvg": 35.0,
+        "temp": 21.5,
+        "time": 1694880270,
+        "voc": 180.0,
+        "relayDeviceType": "hub"
+    }
+    "#;
+
+    let indoor: Indoor = serde_json::from_str(data).unwrap();
+    assert_eq!(indoor.co2, Some(1042.0));
+    assert_eq!(indoor.voc, Some(180.0));
+}
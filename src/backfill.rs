@@ -0,0 +1,223 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::DateTime;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::cache::redact_url;
+use crate::cli::Failure;
+use crate::config::WeatherConfig;
+use crate::history::History;
+use crate::http::{self, Client, Request};
+
+// Past outdoor conditions for `iloveair backfill`, so the first indoor readings have something
+// to be compared against.
+//
+// Pollution comes from the air pollution history API, available from 2020-11-27:
+// https://openweathermap.org/api/air-pollution#history
+// Weather comes from the hourly history API, a paid subscription on its own host:
+// https://openweathermap.org/history
+//
+// Both are fetched one WINDOW at a time and every hour is recorded like a live fetch, so the
+// rows land in the same weather and pollution tables and duplicates are ignored. After each
+// window the progress is saved in backfills, keyed by source and --from, and a rerun with the
+// same --from picks up where an interrupted one stopped. Backfill requests are not counted in
+// fetches, a failed backfill is not a data source being down.
+
+/// seconds of history asked for per request, OWM returns at most a week of hourly weather
+pub const WINDOW: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Weather,
+    Pollution,
+}
+
+impl std::str::FromStr for Source {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Source> {
+        match s {
+            "weather" => Ok(Source::Weather),
+            "pollution" => Ok(Source::Pollution),
+            _ => bail!(
+                "unknown backfill source {}, expected weather or pollution",
+                s
+            ),
+        }
+    }
+}
+
+impl Source {
+    /// key in backfills
+    pub fn name(&self) -> &'static str {
+        match self {
+            Source::Weather => "owm_weather_history",
+            Source::Pollution => "owm_pollution_history",
+        }
+    }
+
+    fn url(&self, config: &WeatherConfig, start: i64, end: i64) -> Result<String> {
+        let url = match self {
+            Source::Weather => format!(
+                "{}?lat={}&lon={}&type=hour&start={}&end={}&units=imperial&appid={}",
+                http::endpoint(
+                    &config.history_base_url,
+                    "/data/2.5/history/city",
+                    config.allow_http
+                )?,
+                config.latitude,
+                config.longitude,
+                start,
+                end,
+                config.api_key
+            ),
+            Source::Pollution => format!(
+                "{}?lat={}&lon={}&start={}&end={}&appid={}",
+                http::endpoint(
+                    &config.base_url,
+                    "/data/2.5/air_pollution/history",
+                    config.allow_http
+                )?,
+                config.latitude,
+                config.longitude,
+                start,
+                end,
+                config.api_key
+            ),
+        };
+        Ok(url)
+    }
+
+    /// records every hour in a history response, returns how many there were
+    fn record(&self, config: &WeatherConfig, history: &History, response: &Value) -> Result<usize> {
+        let Some(list) = response["list"].as_array() else {
+            return Err(anyhow!("record: {} response has no list", self.name()))
+                .context(Failure::Parse);
+        };
+        for item in list {
+            match self {
+                // hourly weather items are shaped like a current weather response without the
+                // name, which live fetches record as the location
+                Source::Weather => {
+                    let mut weather_json = item.clone();
+                    weather_json["name"] = json!(config.city);
+                    history.record_weather(&weather_json)?;
+                }
+                // one air pollution response per hour, as getpollution would have fetched it
+                Source::Pollution => history.record_pollution(&json!({
+                    "coord": response["coord"],
+                    "list": [item],
+                }))?,
+            }
+        }
+        Ok(list.len())
+    }
+}
+
+pub struct RateLimit {
+    /// pause between requests
+    pub interval: Duration,
+    /// how often a 429 or 5xx is retried before giving up
+    pub retries: u32,
+    /// first wait after a 429 or 5xx, doubled on every retry
+    pub backoff: Duration,
+}
+
+impl RateLimit {
+    /// OWM free plans allow 60 calls a minute
+    pub fn per_minute(requests: u32) -> RateLimit {
+        RateLimit {
+            interval: Duration::from_secs(60) / requests.max(1),
+            retries: 5,
+            backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub requests: usize,
+    pub records: usize,
+}
+
+/// fetches `source` from `from` to `to`, unix seconds, resuming a previous run with the same
+/// `from`. `sleep` waits out the rate limit, tests pass one that only takes note.
+#[allow(clippy::too_many_arguments)]
+pub fn backfill(
+    client: &Client,
+    config: &WeatherConfig,
+    history: &History,
+    source: Source,
+    from: i64,
+    to: i64,
+    rate: &RateLimit,
+    sleep: &mut dyn FnMut(Duration),
+) -> Result<Summary> {
+    let mut start = match history.backfill_progress(source.name(), from)? {
+        Some(done_until) => {
+            info!(
+                "backfill: {} resuming at {}",
+                source.name(),
+                format_time(done_until)
+            );
+            done_until.max(from)
+        }
+        None => from,
+    };
+    let mut summary = Summary {
+        requests: 0,
+        records: 0,
+    };
+    while start < to {
+        if summary.requests > 0 {
+            sleep(rate.interval);
+        }
+        let end = (start + WINDOW).min(to);
+        let response = fetch(client, &source.url(config, start, end)?, rate, sleep)?;
+        summary.requests += 1;
+        let records = source.record(config, history, &response)?;
+        summary.records += records;
+        history.record_backfill_progress(source.name(), from, end)?;
+        info!(
+            "backfill: {} {} to {}, {} records",
+            source.name(),
+            format_time(start),
+            format_time(end),
+            records
+        );
+        start = end;
+    }
+    Ok(summary)
+}
+
+fn fetch(
+    client: &Client,
+    url: &str,
+    rate: &RateLimit,
+    sleep: &mut dyn FnMut(Duration),
+) -> Result<Value> {
+    let mut attempt = 0;
+    loop {
+        let response = client.send(&Request::get(url))?;
+        if (response.status == 429 || response.status >= 500) && attempt < rate.retries {
+            let wait = rate.backoff * 2u32.pow(attempt);
+            warn!(
+                "backfill: {} returned {}, retrying in {}s",
+                redact_url(url),
+                response.status,
+                wait.as_secs()
+            );
+            sleep(wait);
+            attempt += 1;
+            continue;
+        }
+        return response.error_for_status()?.json();
+    }
+}
+
+fn format_time(time: i64) -> String {
+    DateTime::from_timestamp(time, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| time.to_string())
+}
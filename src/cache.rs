@@ -0,0 +1,296 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Cached API responses under ~/.cache/iloveair. Each file is an envelope with when and where the
+// payload came from:
+//
+//   {"fetched_at": "2024-06-01T12:00:00Z", "url": "https://...", "status": 200, "payload": {...}}
+//
+// Files written before the envelope are plain payloads, they are still read and their
+// modification time is used as fetched_at.
+
+// source, minutes a response is fresh for
+static TTL_MINUTES: &[(&str, i64)] = &[
+    ("owm_weather", 10),
+    // NWS stations report about once an hour
+    ("nws_weather", 20),
+    // Open-Meteo's current conditions are 15 minute values
+    ("open_meteo_weather", 15),
+    // station pages refresh every minute or so
+    ("station_weather", 5),
+    ("owm_pollution", 10),
+    ("airthings", 10),
+];
+
+/// how long a response from `source` is reused before fetching again, 10 minutes if unknown
+pub fn ttl(source: &str) -> Duration {
+    let minutes = TTL_MINUTES
+        .iter()
+        .find(|(name, _)| *name == source)
+        .map(|(_, minutes)| *minutes)
+        .unwrap_or(10);
+    Duration::minutes(minutes)
+}
+
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry<T> {
+    pub fetched_at: DateTime<Utc>,
+    // query parameters named like secrets are redacted
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub status: Option<u16>,
+    pub payload: T,
+}
+
+/// blanks `appid`, `api_key`, `token` and similar query parameters before a URL is saved
+pub fn redact_url(url: &str) -> String {
+    let Some((base, query)) = url.split_once('?') else {
+        return url.into();
+    };
+    let params: Vec<String> = query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((key, _))
+                if ["appid", "api_key", "apikey", "key", "token"]
+                    .contains(&key.to_ascii_lowercase().as_str()) =>
+            {
+                format!("{}=REDACTED", key)
+            }
+            _ => param.into(),
+        })
+        .collect();
+    format!("{}?{}", base, params.join("&"))
+}
+
+fn is_envelope(json: &Value) -> bool {
+    json.get("fetched_at").is_some() && json.get("payload").is_some()
+}
+
+/// the payload of a cache file, with or without the envelope
+pub fn unwrap_payload(json: Value) -> Value {
+    match json {
+        Value::Object(mut object)
+            if object.contains_key("fetched_at") && object.contains_key("payload") =>
+        {
+            object.remove("payload").unwrap_or_default()
+        }
+        json => json,
+    }
+}
+
+pub struct Cache<C: Clock = SystemClock> {
+    path: PathBuf,
+    ttl: Duration,
+    clock: C,
+}
+
+impl Cache<SystemClock> {
+    pub fn new(path: &str, ttl: Duration) -> Cache<SystemClock> {
+        Cache::with_clock(path, ttl, SystemClock)
+    }
+}
+
+impl<C: Clock> Cache<C> {
+    pub fn with_clock(path: &str, ttl: Duration, clock: C) -> Cache<C> {
+        Cache {
+            path: PathBuf::from(shellexpand::tilde(path).into_owned()),
+            ttl,
+            clock,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// when the cached payload was fetched, None if there is no readable cache file
+    pub fn fetched_at(&self) -> Option<DateTime<Utc>> {
+        let contents = fs::read_to_string(&self.path).ok()?;
+        let json: Value = serde_json::from_str(&contents).ok()?;
+        if is_envelope(&json) {
+            return serde_json::from_value(json["fetched_at"].clone()).ok();
+        }
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        Some(modified.into())
+    }
+
+    pub fn age(&self) -> Option<Duration> {
+        self.fetched_at()
+            .map(|fetched_at| self.clock.now() - fetched_at)
+    }
+
+    /// fresh means fetched less than the TTL ago, a file from the future counts as stale
+    pub fn is_fresh(&self) -> bool {
+        self.age()
+            .is_some_and(|age| age >= Duration::zero() && age < self.ttl)
+    }
+
+    pub fn read<T: DeserializeOwned>(&self) -> Result<Entry<T>> {
+        let contents = fs::read_to_string(&self.path)
+            .with_context(|| format!("cache read: could not read {:?}", self.path))?;
+        let json: Value = serde_json::from_str(&contents)
+            .with_context(|| format!("cache read: could not parse {:?}", self.path))?;
+        let fetched_at = self.fetched_at().unwrap_or_else(|| self.clock.now());
+        let entry = if is_envelope(&json) {
+            serde_json::from_value(json)
+        } else {
+            serde_json::from_value(json).map(|payload| Entry {
+                fetched_at,
+                url: None,
+                status: None,
+                payload,
+            })
+        };
+        entry.with_context(|| format!("cache read: unexpected contents in {:?}", self.path))
+    }
+
+    /// writes the payload in an envelope to a temp file and renames it over the cache file, so
+    /// readers never see a partial file
+    pub fn write<T: Serialize>(
+        &self,
+        payload: &T,
+        url: Option<&str>,
+        status: Option<u16>,
+    ) -> Result<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("cache write: could not create {:?}", parent))?;
+        }
+        let entry = Entry {
+            fetched_at: self.clock.now(),
+            url: url.map(redact_url),
+            status,
+            payload,
+        };
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&entry)?)
+            .with_context(|| format!("cache write: could not write {:?}", tmp_path))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("cache write: could not rename to {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::cell::Cell;
+
+    struct FakeClock(Cell<DateTime<Utc>>);
+
+    impl FakeClock {
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    impl Clock for &FakeClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.0.get()
+        }
+    }
+
+    fn temp_path(name: &str) -> String {
+        let dir =
+            std::env::temp_dir().join(format!("iloveair_cache_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("weather.json").to_str().unwrap().into()
+    }
+
+    #[test]
+    fn test_ttl_with_fake_clock() {
+        let path = temp_path("ttl");
+        let clock = FakeClock(Cell::new(DateTime::from_timestamp(1694880000, 0).unwrap()));
+        let cache = Cache::with_clock(&path, ttl("owm_weather"), &clock);
+        assert!(!cache.is_fresh());
+        cache
+            .write(
+                &json!({"dt": 1694880000}),
+                Some("https://api.openweathermap.org/data/2.5/weather?q=Boston&appid=secret"),
+                Some(200),
+            )
+            .unwrap();
+        assert!(cache.is_fresh());
+        clock.advance(Duration::minutes(9));
+        assert!(cache.is_fresh());
+        clock.advance(Duration::minutes(1));
+        assert!(!cache.is_fresh());
+        // clock went backwards
+        clock.advance(Duration::minutes(-20));
+        assert!(!cache.is_fresh());
+
+        let entry: Entry<Value> = cache.read().unwrap();
+        assert_eq!(entry.payload["dt"], 1694880000);
+        assert_eq!(entry.status, Some(200));
+        assert_eq!(
+            entry.url.as_deref(),
+            Some("https://api.openweathermap.org/data/2.5/weather?q=Boston&appid=REDACTED")
+        );
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_weather_provider_ttls() {
+        // every WeatherProvider name, a missing one would quietly get the default
+        for source in [
+            "owm_weather",
+            "nws_weather",
+            "open_meteo_weather",
+            "station_weather",
+        ] {
+            assert!(
+                TTL_MINUTES.iter().any(|(name, _)| *name == source),
+                "{}",
+                source
+            );
+        }
+        assert_eq!(ttl("nws_weather"), Duration::minutes(20));
+    }
+
+    #[test]
+    fn test_plain_file_uses_modification_time() {
+        let path = temp_path("plain");
+        fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        fs::write(&path, r#"{"dt": 1694880000}"#).unwrap();
+        let clock = FakeClock(Cell::new(Utc::now()));
+        let cache = Cache::with_clock(&path, Duration::minutes(10), &clock);
+        assert!(cache.is_fresh());
+        let entry: Entry<Value> = cache.read().unwrap();
+        assert_eq!(entry.payload["dt"], 1694880000);
+        assert_eq!(entry.url, None);
+        clock.advance(Duration::hours(1));
+        assert!(!cache.is_fresh());
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_unwrap_payload() {
+        let payload = json!({"main": {"temp": 61.5}});
+        assert_eq!(unwrap_payload(payload.clone()), payload);
+        assert_eq!(
+            unwrap_payload(json!({"fetched_at": "2024-06-01T12:00:00Z", "payload": payload})),
+            payload
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use std::fmt;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+
+use crate::http::{self, Mode};
+
+// Shared plumbing for the binaries: logging to stderr and an exit code per failure class, so
+// systemd and cron can tell a failed run from a successful one. The codes follow sysexits.h:
+//
+//   0 ok, 1 other, 65 parse, 69 network, 75 stale data, 77 auth, 78 config
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    Config,
+    Network,
+    Auth,
+    Parse,
+    Stale,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::Parse => 65,
+            Failure::Network => 69,
+            Failure::Stale => 75,
+            Failure::Auth => 77,
+            Failure::Config => 78,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Failure::Config => "config error",
+            Failure::Network => "network error",
+            Failure::Auth => "auth error",
+            Failure::Parse => "parse error",
+            Failure::Stale => "stale data",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// the failure class of an error, from a `.context(Failure::..)` marker if there is one,
+/// otherwise guessed from the underlying reqwest or serde_json error
+pub fn failure_class(e: &anyhow::Error) -> Option<Failure> {
+    if let Some(failure) = e.downcast_ref::<Failure>() {
+        return Some(*failure);
+    }
+    e.chain().find_map(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return Some(match e.status().map(|status| status.as_u16()) {
+                Some(401) | Some(403) => Failure::Auth,
+                _ if e.is_decode() => Failure::Parse,
+                _ => Failure::Network,
+            });
+        }
+        if cause.downcast_ref::<serde_json::Error>().is_some() {
+            return Some(Failure::Parse);
+        }
+        None
+    })
+}
+
+pub fn exit_code(e: &anyhow::Error) -> u8 {
+    failure_class(e).map(Failure::exit_code).unwrap_or(1)
+}
+
+/// adds --verbose, --quiet and --log-format to a command
+pub fn logging_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::Count)
+                .global(true)
+                .help("log more, -v for debug and -vv for trace"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .num_args(0)
+                .global(true)
+                .conflicts_with("verbose")
+                .help("only log errors"),
+        )
+        .arg(
+            Arg::new("log_format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true)
+                .help("log to stderr as text or as json lines for journald"),
+        )
+}
+
+/// adds --record and --replay to a command that makes HTTP requests
+pub fn http_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .value_name("DIR")
+                .global(true)
+                .conflicts_with("replay")
+                .help("save every HTTP request and response to DIR"),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .value_name("DIR")
+                .global(true)
+                .help("answer HTTP requests from the responses saved in DIR by --record"),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .value_parser(value_parser!(u64))
+                .default_value("30")
+                .global(true)
+                .help("give up on an HTTP request after SECONDS"),
+        )
+}
+
+/// sets up the process wide HTTP client from --record, --replay and --timeout
+pub fn init_http(matches: &ArgMatches) {
+    let expand = |dir: &String| PathBuf::from(shellexpand::tilde(dir).into_owned());
+    let mode = if let Some(dir) = matches.get_one::<String>("record") {
+        Mode::Record(expand(dir))
+    } else if let Some(dir) = matches.get_one::<String>("replay") {
+        Mode::Replay(expand(dir))
+    } else {
+        Mode::Live
+    };
+    let timeout = matches.get_one::<u64>("timeout").copied().unwrap_or(30);
+    http::init(mode, Duration::from_secs(timeout));
+}
+
+fn level(verbose: u8, is_quiet: bool) -> LevelFilter {
+    match (is_quiet, verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    }
+}
+
+/// logs to stderr at the level picked by --verbose and --quiet
+pub fn init_logging(matches: &ArgMatches) {
+    let verbose = matches.get_count("verbose");
+    let is_quiet = matches.get_flag("quiet");
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level(verbose, is_quiet))
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_target(false);
+    let is_json = matches
+        .get_one::<String>("log_format")
+        .is_some_and(|format| format == "json");
+    if is_json {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+/// logs the error, if any, and turns it into the process exit code
+pub fn exit(result: anyhow::Result<()>) -> ExitCode {
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            // {:#} is the error and its causes, "config error: could not read config ..: .."
+            let code = exit_code(&e);
+            tracing::error!(code, "{:#}", e);
+            ExitCode::from(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_failure_class() {
+        let e = anyhow!("No such file")
+            .context("could not read config ~/.config/iloveair/openweathermap.json")
+            .context(Failure::Config);
+        assert_eq!(failure_class(&e), Some(Failure::Config));
+        assert_eq!(exit_code(&e), 78);
+        assert_eq!(
+            format!("{:#}", e),
+            "config error: could not read config ~/.config/iloveair/openweathermap.json: No such file"
+        );
+
+        let parse: anyhow::Result<serde_json::Value> =
+            serde_json::from_str("{").context("could not parse weather.json");
+        assert_eq!(exit_code(&parse.unwrap_err()), 65);
+
+        // an explicit marker wins over the underlying error
+        let stale = serde_json::from_str::<serde_json::Value>("{")
+            .context(Failure::Stale)
+            .unwrap_err();
+        assert_eq!(failure_class(&stale), Some(Failure::Stale));
+
+        assert_eq!(exit_code(&anyhow!("something else")), 1);
+    }
+
+    #[test]
+    fn test_logging_args() {
+        let command = logging_args(Command::new("test"));
+        let matches = command.clone().get_matches_from(["test", "-vv"]);
+        assert_eq!(
+            level(matches.get_count("verbose"), matches.get_flag("quiet")),
+            LevelFilter::TRACE
+        );
+        let matches = command.clone().get_matches_from(["test", "--quiet"]);
+        assert_eq!(
+            level(matches.get_count("verbose"), matches.get_flag("quiet")),
+            LevelFilter::ERROR
+        );
+        assert!(command
+            .try_get_matches_from(["test", "--log-format", "xml"])
+            .is_err());
+    }
+}
@@ -2,11 +2,28 @@ use crate::audit::read_to_string_with_shellexpand;
 use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 
+// Base URLs default to the real services over https. They can point at a caching proxy or a
+// local mock server, plain http needs "allow_http": true.
+
 #[derive(Serialize, Deserialize)]
 pub struct AirthingsConfig {
     pub client_id: String,
     pub client_secret: String,
     pub device_id: String,
+    #[serde(default = "default_airthings_accounts_url")]
+    pub accounts_url: String,
+    #[serde(default = "default_airthings_api_url")]
+    pub api_url: String,
+    #[serde(default)]
+    pub allow_http: bool,
+}
+
+fn default_airthings_accounts_url() -> String {
+    "https://accounts-api.airthings.com".into()
+}
+
+fn default_airthings_api_url() -> String {
+    "https://ext-api.airthings.com".into()
 }
 
 #[derive(Serialize, Deserialize)]
@@ -16,7 +33,100 @@ pub struct WeatherConfig {
     pub country: String,
     pub latitude: String,
     pub longitude: String,
+    #[serde(default = "default_owm_base_url")]
+    pub base_url: String,
+    /// hourly weather for `iloveair backfill`, a separate host and subscription at OWM
+    #[serde(default = "default_owm_history_base_url")]
+    pub history_base_url: String,
+    #[serde(default)]
+    pub allow_http: bool,
+    /// where getweather gets the outdoor reading, the first that answers wins:
+    /// openweathermap, nws, open_meteo or station
+    #[serde(default = "default_weather_providers")]
+    pub providers: Vec<String>,
+    #[serde(default)]
+    pub nws: Option<NwsConfig>,
+    #[serde(default)]
+    pub open_meteo: OpenMeteoConfig,
+    #[serde(default)]
+    pub station: Option<StationConfig>,
+}
+
+/// US National Weather Service observations, https://www.weather.gov/documentation/services-web-api
+#[derive(Serialize, Deserialize)]
+pub struct NwsConfig {
+    /// observation station id, e.g. KBOS, listed by https://api.weather.gov/points/{lat},{lon}/stations
+    pub station: String,
+    /// api.weather.gov asks for a contact, e.g. "(iloveair, you@example.com)"
+    pub user_agent: String,
+    #[serde(default = "default_nws_base_url")]
+    pub base_url: String,
+}
+
+/// Open-Meteo at the configured latitude and longitude, no key needed
+#[derive(Serialize, Deserialize)]
+pub struct OpenMeteoConfig {
+    #[serde(default = "default_open_meteo_base_url")]
+    pub base_url: String,
+}
+
+impl Default for OpenMeteoConfig {
+    fn default() -> OpenMeteoConfig {
+        OpenMeteoConfig {
+            base_url: default_open_meteo_base_url(),
+        }
+    }
+}
+
+/// a nearby weather station's web page, read with CSS selectors or XPaths
+#[derive(Serialize, Deserialize)]
+pub struct StationConfig {
+    pub url: String,
+    /// selects the text holding the temperature, the first number in it is used
+    pub temperature: String,
+    /// F or C
+    #[serde(default = "default_station_temperature_unit")]
+    pub temperature_unit: String,
+    /// selects the text holding the relative humidity
+    pub humidity: String,
+    /// location recorded in the history, defaults to the city
+    #[serde(default)]
+    pub name: Option<String>,
+    /// selects the text holding the time of the reading, so a page that stopped updating is stale
+    #[serde(default)]
+    pub time: Option<String>,
+    /// chrono format of that time in local time, e.g. "%m/%d/%Y %I:%M %p", text before and
+    /// after it is ignored
+    #[serde(default)]
+    pub time_format: Option<String>,
+    #[serde(default)]
+    pub allow_http: bool,
+}
+
+fn default_station_temperature_unit() -> String {
+    "F".into()
+}
+
+fn default_weather_providers() -> Vec<String> {
+    vec!["openweathermap".into()]
+}
+
+fn default_nws_base_url() -> String {
+    "https://api.weather.gov".into()
+}
+
+fn default_open_meteo_base_url() -> String {
+    "https://api.open-meteo.com".into()
+}
+
+fn default_owm_base_url() -> String {
+    "https://api.openweathermap.org".into()
+}
+
+fn default_owm_history_base_url() -> String {
+    "https://history.openweathermap.org".into()
 }
+
 pub fn read_airthings_config(filename: &str) -> Result<AirthingsConfig> {
     let contents = read_to_string_with_shellexpand(&filename.to_string())?;
 
@@ -33,12 +143,31 @@ pub fn read_weather_config(filename: &str) -> Result<WeatherConfig> {
 
     Ok(config)
 }
-pub fn file_older_than_minutes(path: &str, minutes: u64) -> bool {
-    if !std::path::Path::new(path).exists() {
-        return false;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base_url_defaults() {
+        let config: WeatherConfig = serde_json::from_str(
+            r#"{"api_key": "", "city": "Boston", "country": "US", "latitude": "42.36", "longitude": "-71.06"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.base_url, "https://api.openweathermap.org");
+        assert_eq!(
+            config.history_base_url,
+            "https://history.openweathermap.org"
+        );
+        assert!(!config.allow_http);
+        assert_eq!(config.providers, vec!["openweathermap"]);
+        assert_eq!(config.open_meteo.base_url, "https://api.open-meteo.com");
+        let config: AirthingsConfig = serde_json::from_str(
+            r#"{"client_id": "", "client_secret": "", "device_id": "", "api_url": "http://127.0.0.1:8080", "allow_http": true}"#,
+        )
+        .unwrap();
+        assert_eq!(config.accounts_url, "https://accounts-api.airthings.com");
+        assert_eq!(config.api_url, "http://127.0.0.1:8080");
+        assert!(config.allow_http);
     }
-    let metadata = std::fs::metadata(path).unwrap();
-    let modified = metadata.modified().unwrap();
-    let elapsed_secs = modified.elapsed().unwrap().as_secs();
-    elapsed_secs < minutes / 60
 }
@@ -0,0 +1,387 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, Utc};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Sparkline};
+use ratatui::{Frame, Terminal, TerminalOptions, Viewport};
+use std::io::stdout;
+use std::time::{Duration, Instant};
+
+use crate::decision::{Decision, RuleOutcome};
+use crate::history::{aggregate, History, Interval};
+use crate::psychro::dew_point;
+
+// `iloveair dashboard`: current indoor and outdoor conditions side by side, the last 24
+// hours from the history database as sparklines and the latest decision with each rule.
+// Without --daemon it draws once below the prompt and exits.
+
+#[derive(Clone, Debug, Default)]
+pub struct Current {
+    pub indoor_temp: Option<f64>,
+    pub indoor_humidity: Option<f64>,
+    pub radon: Option<f64>,
+    pub co2: Option<f64>,
+    pub voc: Option<f64>,
+    pub outdoor_temp: Option<f64>,
+    pub outdoor_humidity: Option<f64>,
+    pub aqi: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Trend {
+    pub label: &'static str,
+    // hourly means, oldest first, None for hours without readings
+    pub hourly: Vec<Option<f64>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DashboardData {
+    pub loaded_at: DateTime<Utc>,
+    pub current: Current,
+    pub trends: Vec<Trend>,
+    pub decision: Option<Decision>,
+}
+
+static TRENDS: &[(&str, &str)] = &[
+    ("indoor.temp", "indoor °F"),
+    ("outdoor.temp", "outdoor °F"),
+    ("indoor.humidity", "indoor %"),
+    ("outdoor.humidity", "outdoor %"),
+    ("indoor.radon", "radon Bq/m³"),
+    ("aqi", "AQI"),
+];
+
+impl DashboardData {
+    pub fn load(history: &History, now: DateTime<Utc>) -> Result<DashboardData> {
+        let latest = |metric: &str| -> Result<Option<f64>> {
+            Ok(history.latest(metric, None)?.map(|sample| sample.value))
+        };
+        let current = Current {
+            indoor_temp: latest("indoor.temp")?,
+            indoor_humidity: latest("indoor.humidity")?,
+            radon: latest("indoor.radon")?,
+            co2: latest("indoor.co2")?,
+            voc: latest("indoor.voc")?,
+            outdoor_temp: latest("outdoor.temp")?,
+            outdoor_humidity: latest("outdoor.humidity")?,
+            aqi: latest("aqi")?,
+        };
+        let hour = 3600;
+        let start = (now.timestamp() / hour - 23) * hour;
+        let mut trends = Vec::new();
+        for (metric, label) in TRENDS {
+            let samples = history.samples(metric, None, Some(start), Some(now.timestamp()))?;
+            let mut hourly = vec![None; 24];
+            // whole UTC hours, so the timezone doesn't matter
+            for bucket in aggregate(&samples, Interval::Hourly, Some(&chrono_tz::UTC)) {
+                let i = ((bucket.time - start) / hour) as usize;
+                if i < hourly.len() {
+                    hourly[i] = Some(bucket.mean);
+                }
+            }
+            trends.push(Trend { label, hourly });
+        }
+        Ok(DashboardData {
+            loaded_at: now,
+            current,
+            trends,
+            decision: history.latest_decision()?,
+        })
+    }
+}
+
+fn value(v: Option<f64>, unit: &str) -> String {
+    match v {
+        Some(v) => format!("{:.1}{}", v, unit),
+        None => "-".into(),
+    }
+}
+
+fn conditions(title: &str, rows: Vec<(&str, String)>) -> Paragraph<'static> {
+    let lines: Vec<Line> = rows
+        .into_iter()
+        .map(|(label, v)| Line::from(vec![format!("{:<10}", label).dim(), Span::raw(v)]))
+        .collect();
+    Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(title.to_string()),
+    )
+}
+
+fn check(passed: bool) -> Span<'static> {
+    if passed {
+        "✔ ".green()
+    } else {
+        "✘ ".red()
+    }
+}
+
+fn outcome_line(outcome: &RuleOutcome, prefix: &str) -> Line<'static> {
+    Line::from(vec![
+        check(outcome.passed),
+        Span::styled(
+            format!("{}{}", prefix, outcome.name),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(format!(" {}", outcome.reason)).dim(),
+    ])
+}
+
+fn decision_lines(decision: Option<&Decision>) -> Vec<Line<'static>> {
+    let Some(decision) = decision else {
+        return vec![Line::from("no decision yet, run weather_notify --db")];
+    };
+    let (text, color) = if decision.is_unknown() {
+        ("UNKNOWN, stale data", Color::Yellow)
+    } else if decision.window_should_be_open {
+        ("OPEN the windows", Color::Green)
+    } else {
+        ("CLOSE the windows", Color::Red)
+    };
+    let mut lines = vec![Line::from(vec![
+        Span::styled(
+            text,
+            Style::default().fg(color).add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(format!(
+            "  decided {}",
+            decision.generated_at.format("%a %H:%M")
+        ))
+        .dim(),
+    ])];
+    lines.extend(decision.rules.iter().map(|rule| outcome_line(rule, "")));
+    lines.extend(
+        decision
+            .vetoes
+            .iter()
+            .filter(|veto| !veto.passed)
+            .map(|veto| outcome_line(veto, "veto ")),
+    );
+    lines.extend(
+        decision
+            .stale_inputs
+            .iter()
+            .map(|stale| Line::from(format!("⏳ stale {}: {}", stale.input, stale.reason))),
+    );
+    if let Some(ventilation) = decision.ventilation.as_ref().filter(|v| v.recommended) {
+        lines.push(Line::from(format!("air out: {}", ventilation.reason)));
+    }
+    lines
+}
+
+/// sparkline heights relative to the day's range so small swings show
+fn scaled(hourly: &[Option<f64>]) -> Vec<u64> {
+    let present = hourly.iter().flatten();
+    let min = present.clone().copied().fold(f64::INFINITY, f64::min);
+    let max = present.copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = (max - min).max(f64::EPSILON);
+    hourly
+        .iter()
+        .map(|v| match v {
+            Some(v) => 1 + ((v - min) / range * 99.0).round() as u64,
+            None => 0,
+        })
+        .collect()
+}
+
+pub fn draw(frame: &mut Frame, data: &DashboardData) {
+    let [top, middle, bottom, footer] = Layout::vertical([
+        Constraint::Length(7),
+        Constraint::Length(2 * TRENDS.len().div_ceil(2) as u16 + 2),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let [indoor, outdoor] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(top);
+    let c = &data.current;
+    let indoor_dew_point = c
+        .indoor_temp
+        .zip(c.indoor_humidity)
+        .map(|(t, h)| dew_point(t, h));
+    let outdoor_dew_point = c
+        .outdoor_temp
+        .zip(c.outdoor_humidity)
+        .map(|(t, h)| dew_point(t, h));
+    frame.render_widget(
+        conditions(
+            "Indoor",
+            vec![
+                ("temp", value(c.indoor_temp, "°F")),
+                ("humidity", value(c.indoor_humidity, "%")),
+                ("dew point", value(indoor_dew_point, "°F")),
+                ("radon", value(c.radon, " Bq/m³")),
+                (
+                    "CO2/VOC",
+                    format!("{} / {}", value(c.co2, " ppm"), value(c.voc, " ppb")),
+                ),
+            ],
+        ),
+        indoor,
+    );
+    frame.render_widget(
+        conditions(
+            "Outdoor",
+            vec![
+                ("temp", value(c.outdoor_temp, "°F")),
+                ("humidity", value(c.outdoor_humidity, "%")),
+                ("dew point", value(outdoor_dew_point, "°F")),
+                ("AQI", value(c.aqi, "")),
+            ],
+        ),
+        outdoor,
+    );
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Last 24 hours");
+    let inner = block.inner(middle);
+    frame.render_widget(block, middle);
+    let rows =
+        Layout::vertical(vec![Constraint::Length(2); data.trends.len().div_ceil(2)]).split(inner);
+    for (i, trend) in data.trends.iter().enumerate() {
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(rows[i / 2]);
+        let area = if i % 2 == 0 { left } else { right };
+        let [label, chart] =
+            Layout::horizontal([Constraint::Length(13), Constraint::Min(1)]).areas(area);
+        let last = trend.hourly.iter().rev().flatten().next().copied();
+        frame.render_widget(
+            Paragraph::new(vec![
+                Line::from(trend.label).dim(),
+                Line::from(value(last, "")),
+            ]),
+            label,
+        );
+        frame.render_widget(
+            Sparkline::default()
+                .data(scaled(&trend.hourly))
+                .style(Style::default().fg(Color::Cyan)),
+            chart,
+        );
+    }
+
+    frame.render_widget(
+        Paragraph::new(decision_lines(data.decision.as_ref())).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Recommendation"),
+        ),
+        bottom,
+    );
+    frame.render_widget(
+        Paragraph::new(format!(
+            "updated {}  q quit  r refresh",
+            data.loaded_at.with_timezone(&Local).format("%H:%M:%S")
+        ))
+        .dim(),
+        footer,
+    );
+}
+
+fn area_height(data: &DashboardData) -> u16 {
+    let decision = decision_lines(data.decision.as_ref()).len() as u16;
+    7 + 2 * TRENDS.len().div_ceil(2) as u16 + 2 + decision + 2 + 1
+}
+
+/// draws once, or with `daemon` keeps the dashboard open and reloads every `refresh`
+pub fn run(history_db_path: &str, refresh: Duration, daemon: bool) -> Result<()> {
+    let history = History::open(history_db_path)?;
+    let mut data = DashboardData::load(&history, Utc::now())?;
+    if !daemon {
+        let mut terminal = Terminal::with_options(
+            CrosstermBackend::new(stdout()),
+            TerminalOptions {
+                viewport: Viewport::Inline(area_height(&data)),
+            },
+        )
+        .map_err(|e| anyhow!("dashboard: could not open terminal {}", e))?;
+        terminal.draw(|frame| draw(frame, &data))?;
+        println!();
+        return Ok(());
+    }
+
+    let mut terminal = ratatui::init();
+    let result = (|| -> Result<()> {
+        let mut loaded = Instant::now();
+        loop {
+            terminal.draw(|frame| draw(frame, &data))?;
+            let timeout = refresh.saturating_sub(loaded.elapsed());
+            let mut reload = timeout.is_zero();
+            if event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        match key.code {
+                            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                            KeyCode::Char('r') => reload = true,
+                            _ => (),
+                        }
+                    }
+                }
+            }
+            if reload {
+                data = DashboardData::load(&history, Utc::now())?;
+                loaded = Instant::now();
+            }
+        }
+    })();
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use serde_json::json;
+
+    #[test]
+    fn test_load_and_draw() {
+        let history = History::open_in_memory().unwrap();
+        let now = Utc::now();
+        let t = now.timestamp();
+        for (ago, temp) in [(7200, 20.0), (3600, 21.0), (60, 22.0)] {
+            history
+                .record_indoor(
+                    "2930",
+                    &json!({"temp": temp, "humidity": 50.0, "radonShortTermAvg": 40.0, "time": t - ago}),
+                )
+                .unwrap();
+        }
+        history
+            .record_weather(
+                &json!({"dt": t - 60, "name": "Boston", "main": {"temp": 61.5, "humidity": 80}}),
+            )
+            .unwrap();
+
+        let data = DashboardData::load(&history, now).unwrap();
+        assert_eq!(data.current.radon, Some(40.0));
+        assert!((data.current.indoor_temp.unwrap() - 71.6).abs() < 0.01);
+        assert_eq!(data.trends[0].hourly.iter().flatten().count(), 3);
+        assert!(data.decision.is_none());
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|frame| draw(frame, &data)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("Indoor"));
+        assert!(screen.contains("61.5°F"));
+        assert!(screen.contains("no decision yet"));
+    }
+
+    #[test]
+    fn test_scaled() {
+        assert_eq!(scaled(&[Some(60.0), None, Some(70.0)]), vec![1, 0, 100]);
+    }
+}
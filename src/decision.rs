@@ -0,0 +1,363 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use crate::audit::read_to_string_with_shellexpand;
+use crate::energy::EnergyEstimate;
+use crate::psychro::{dew_point, enthalpy};
+use crate::rules::{Facts, RuleSet};
+use crate::staleness::StaleInput;
+use crate::ventilation::VentilationAdvice;
+use crate::weather::WeatherConditions;
+
+// bump when a field is removed or changes meaning so consumers can reject documents they don't understand
+pub static DECISION_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Conditions {
+    pub temp: f64,
+    pub humidity: u64,
+    // unix seconds of the reading itself (Airthings `time`, OWM `dt`)
+    pub time: Option<i64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AirQuality {
+    // OWM air quality index 1 (good) to 5 (very poor)
+    pub aqi: u64,
+    pub time: Option<i64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IndoorAir {
+    // ppm
+    pub co2: Option<f64>,
+    // ppb
+    pub voc: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Inputs {
+    pub indoor: Conditions,
+    pub outdoor: Conditions,
+    #[serde(default)]
+    pub pollution: Option<AirQuality>,
+    #[serde(default)]
+    pub weather: Option<WeatherConditions>,
+    #[serde(default)]
+    pub indoor_air: Option<IndoorAir>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Thresholds {
+    pub max_humidity: u64,
+    pub min_temp: f64,
+    pub max_temp: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RuleOutcome {
+    pub name: String,
+    pub passed: bool,
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    // outcomes of the rules inside all/any/not
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<RuleOutcome>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Decision {
+    pub version: u32,
+    pub generated_at: DateTime<Local>,
+    // schedule timezone the report times are shown in, the host's when None
+    #[serde(default)]
+    pub timezone: Option<String>,
+    pub inputs: Inputs,
+    pub thresholds: Thresholds,
+    // every value the rules could refer to
+    #[serde(default)]
+    pub facts: Facts,
+    pub rules: Vec<RuleOutcome>,
+    // a failed veto keeps the windows closed whatever the rules say
+    #[serde(default)]
+    pub vetoes: Vec<RuleOutcome>,
+    // present when rules.json has an "energy" section
+    #[serde(default)]
+    pub energy: Option<EnergyEstimate>,
+    // a short purge for indoor CO2/VOC, separate from window_should_be_open
+    #[serde(default)]
+    pub ventilation: Option<VentilationAdvice>,
+    // inputs older than their max age, the decision is unknown and the windows stay closed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stale_inputs: Vec<StaleInput>,
+    pub window_should_be_open: bool,
+}
+
+impl Decision {
+    pub fn is_unknown(&self) -> bool {
+        !self.stale_inputs.is_empty()
+    }
+
+    pub fn rule(&self, name: &str) -> Option<&RuleOutcome> {
+        self.rules.iter().find(|r| r.name == name)
+    }
+}
+
+/// the names rules refer to, e.g. `outdoor.dew_point` or `thresholds.max_temp`
+pub fn facts(inputs: &Inputs, thresholds: &Thresholds) -> Facts {
+    let mut facts = Facts::new();
+    for (prefix, conditions) in [("indoor", &inputs.indoor), ("outdoor", &inputs.outdoor)] {
+        facts.insert(format!("{}.temp", prefix), conditions.temp.into());
+        facts.insert(format!("{}.humidity", prefix), conditions.humidity.into());
+        facts.insert(
+            format!("{}.dew_point", prefix),
+            dew_point(conditions.temp, conditions.humidity as f64).into(),
+        );
+        facts.insert(
+            format!("{}.enthalpy", prefix),
+            enthalpy(conditions.temp, conditions.humidity as f64).into(),
+        );
+    }
+    facts.insert(
+        "thresholds.max_humidity".into(),
+        thresholds.max_humidity.into(),
+    );
+    facts.insert("thresholds.min_temp".into(), thresholds.min_temp.into());
+    facts.insert("thresholds.max_temp".into(), thresholds.max_temp.into());
+    if let Some(pollution) = &inputs.pollution {
+        facts.insert("aqi".into(), pollution.aqi.into());
+    }
+    if let Some(air) = &inputs.indoor_air {
+        if let Some(co2) = air.co2 {
+            facts.insert("indoor.co2".into(), co2.into());
+        }
+        if let Some(voc) = air.voc {
+            facts.insert("indoor.voc".into(), voc.into());
+        }
+    }
+    if let Some(weather) = &inputs.weather {
+        facts.insert("outdoor.rain_1h".into(), weather.rain_1h.into());
+        facts.insert("outdoor.snow_1h".into(), weather.snow_1h.into());
+        facts.insert("outdoor.wind_speed".into(), weather.wind_speed.into());
+        facts.insert("outdoor.wind_gust".into(), weather.wind_gust.into());
+        if let Some(id) = weather.condition_ids.first() {
+            facts.insert("outdoor.condition_id".into(), (*id).into());
+        }
+        facts.insert("raining".into(), weather.precipitation().into());
+        facts.insert("thunderstorm".into(), weather.thunderstorm().into());
+        facts.insert("fog".into(), weather.fog().into());
+    }
+    facts
+}
+
+pub fn decide(inputs: Inputs, thresholds: Thresholds, rule_set: &RuleSet) -> Result<Decision> {
+    decide_at(inputs, thresholds, rule_set, Utc::now())
+}
+
+pub fn decide_at(
+    inputs: Inputs,
+    thresholds: Thresholds,
+    rule_set: &RuleSet,
+    now: DateTime<Utc>,
+) -> Result<Decision> {
+    let mut facts = facts(&inputs, &thresholds);
+    let (sunrise, sunset) = inputs
+        .weather
+        .as_ref()
+        .map(|w| (w.sunrise, w.sunset))
+        .unwrap_or_default();
+    facts.extend(rule_set.schedule.facts(now, sunrise, sunset)?);
+    let mut rules = rule_set.evaluate(&facts)?;
+    let energy = rule_set.energy.as_ref().map(|settings| {
+        let (outcome, estimate) = settings.evaluate(&inputs);
+        rules.push(outcome);
+        facts.insert(
+            "energy.expected_change_per_hour".into(),
+            estimate.expected_change_per_hour.into(),
+        );
+        estimate
+    });
+    let mut vetoes = rule_set.vetoes.evaluate(&facts);
+    vetoes.extend(rule_set.schedule.evaluate(now)?);
+    let stale_inputs = rule_set.max_age.check(&inputs, now);
+    // old CO2/VOC readings are no reason to air out either
+    let ventilation = rule_set
+        .ventilation
+        .evaluate(&inputs, &vetoes)
+        .map(|advice| {
+            if stale_inputs.is_empty() {
+                return advice;
+            }
+            let stale: Vec<_> = stale_inputs.iter().map(|s| s.input.as_str()).collect();
+            VentilationAdvice {
+                recommended: false,
+                purge_minutes: 0,
+                reason: format!("stale input {}", stale.join(", ")),
+            }
+        });
+    let window_should_be_open = stale_inputs.is_empty()
+        && rules.iter().all(|r| r.passed)
+        && vetoes.iter().all(|v| v.passed);
+    Ok(Decision {
+        version: DECISION_VERSION,
+        generated_at: now.with_timezone(&Local),
+        timezone: rule_set.schedule.timezone.clone(),
+        inputs,
+        thresholds,
+        facts,
+        rules,
+        vetoes,
+        energy,
+        ventilation,
+        stale_inputs,
+        window_should_be_open,
+    })
+}
+
+pub fn save_decision(decision_json_path: &str, decision: &Decision) -> Result<()> {
+    let path = shellexpand::full(decision_json_path)?.to_string();
+    let mut fout = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .with_context(|| format!("save_decision: could not open for write {}", path))?;
+    fout.write_all(serde_json::to_string_pretty(decision)?.as_bytes())
+        .with_context(|| format!("save_decision: could not write {}", path))?;
+    Ok(())
+}
+
+pub fn load_decision(decision_json_path: &String) -> Result<Decision> {
+    let contents = read_to_string_with_shellexpand(decision_json_path)
+        .with_context(|| format!("load_decision: could not read {}", decision_json_path))?;
+    let decision: Decision = serde_json::from_str(&contents)
+        .with_context(|| format!("load_decision: could not parse {}", decision_json_path))?;
+    if decision.version > DECISION_VERSION {
+        anyhow::bail!(
+            "load_decision: {} is version {}, newer than supported {}",
+            decision_json_path,
+            decision.version,
+            DECISION_VERSION
+        );
+    }
+    Ok(decision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(outdoor_temp: f64, outdoor_humidity: u64) -> Inputs {
+        Inputs {
+            indoor: Conditions {
+                temp: 70.0,
+                humidity: 50,
+                time: Some(1694880270),
+            },
+            outdoor: Conditions {
+                temp: outdoor_temp,
+                humidity: outdoor_humidity,
+                time: Some(1694880000),
+            },
+            pollution: None,
+            weather: None,
+            indoor_air: None,
+        }
+    }
+    // shortly after the readings in inputs()
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1694880600, 0).unwrap()
+    }
+    fn thresholds() -> Thresholds {
+        Thresholds {
+            max_humidity: 60,
+            min_temp: 50.0,
+            max_temp: 84.0,
+        }
+    }
+
+    #[test]
+    fn test_decide_open() {
+        let decision =
+            decide_at(inputs(65.0, 55), thresholds(), &RuleSet::default(), now()).unwrap();
+        assert!(decision.window_should_be_open);
+        assert_eq!(decision.rules.len(), 2);
+    }
+
+    #[test]
+    fn test_decide_too_hot() {
+        let decision =
+            decide_at(inputs(90.0, 40), thresholds(), &RuleSet::default(), now()).unwrap();
+        assert!(!decision.window_should_be_open);
+        assert!(!decision.rule("can_let_in_temperature").unwrap().passed);
+        assert!(decision.rule("can_let_in_humidify").unwrap().passed);
+    }
+
+    #[test]
+    fn test_rain_vetoes_open_window() {
+        let mut inputs = inputs(65.0, 55);
+        inputs.weather = Some(WeatherConditions {
+            rain_1h: 2.0,
+            condition_ids: vec![501],
+            ..Default::default()
+        });
+        let decision = decide_at(inputs, thresholds(), &RuleSet::default(), now()).unwrap();
+        assert!(decision.rules.iter().all(|r| r.passed));
+        assert!(!decision.window_should_be_open);
+        let veto = decision.vetoes.iter().find(|v| !v.passed).unwrap();
+        assert_eq!(veto.name, "precipitation");
+    }
+
+    #[test]
+    fn test_aqi_fact_only_with_pollution() {
+        let mut inputs = inputs(65.0, 55);
+        assert!(!facts(&inputs, &thresholds()).contains_key("aqi"));
+        inputs.pollution = Some(AirQuality { aqi: 2, time: None });
+        assert_eq!(facts(&inputs, &thresholds())["aqi"], 2u64.into());
+    }
+
+    #[test]
+    fn test_stale_input_is_unknown() {
+        let day_later = DateTime::from_timestamp(1694880000 + 86400, 0).unwrap();
+        let decision = decide_at(
+            inputs(65.0, 55),
+            thresholds(),
+            &RuleSet::default(),
+            day_later,
+        )
+        .unwrap();
+        assert!(decision.is_unknown());
+        assert!(!decision.window_should_be_open);
+        assert!(decision.rules.iter().all(|r| r.passed));
+        assert_eq!(decision.stale_inputs[0].input, "indoor");
+        assert_eq!(decision.stale_inputs[1].input, "outdoor");
+
+        // stuffy but the reading is a day old
+        let mut stuffy = inputs(65.0, 55);
+        stuffy.indoor_air = Some(IndoorAir {
+            co2: Some(1400.0),
+            voc: Some(100.0),
+        });
+        let fresh = decide_at(stuffy.clone(), thresholds(), &RuleSet::default(), now()).unwrap();
+        assert!(fresh.ventilation.unwrap().recommended);
+        let decision = decide_at(stuffy, thresholds(), &RuleSet::default(), day_later).unwrap();
+        let ventilation = decision.ventilation.unwrap();
+        assert!(!ventilation.recommended);
+        assert_eq!(ventilation.purge_minutes, 0);
+        assert_eq!(ventilation.reason, "stale input indoor, outdoor");
+    }
+
+    #[test]
+    fn test_decision_round_trip() {
+        let decision =
+            decide_at(inputs(65.0, 55), thresholds(), &RuleSet::default(), now()).unwrap();
+        let json = serde_json::to_string(&decision).unwrap();
+        let back: Decision = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.version, DECISION_VERSION);
+        assert_eq!(back.inputs.outdoor.time, Some(1694880000));
+    }
+}
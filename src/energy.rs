@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+
+use crate::decision::{Inputs, RuleOutcome};
+use crate::psychro::enthalpy;
+
+// Passive cooling/heating under "energy" in rules.json:
+//
+// "energy": {
+//   "setpoint": 72.0,
+//   "season": "cooling",
+//   "ventilation": { "air_changes_per_hour": 4.0, "thermal_mass_factor": 10.0 }
+// }
+//
+// When configured, the windows should only be open when outside air moves the indoor
+// temperature toward the setpoint. Cooling compares enthalpy, not just temperature, so
+// cool muggy air that would load the house with moisture doesn't count as cooling.
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Season {
+    Cooling,
+    Heating,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VentilationModel {
+    // outdoor air exchanges per hour with the windows open
+    pub air_changes_per_hour: f64,
+    // heat capacity of the house (walls, furniture) relative to the air inside it
+    pub thermal_mass_factor: f64,
+}
+
+impl Default for VentilationModel {
+    fn default() -> Self {
+        VentilationModel {
+            air_changes_per_hour: 4.0,
+            thermal_mass_factor: 10.0,
+        }
+    }
+}
+
+impl VentilationModel {
+    /// °F the indoor temperature moves in an hour with the windows open,
+    /// treating the house as one well mixed volume
+    pub fn change_per_hour(&self, indoor_temp: f64, outdoor_temp: f64) -> f64 {
+        let rate = self.air_changes_per_hour / self.thermal_mass_factor.max(1.0);
+        (outdoor_temp - indoor_temp) * (1.0 - (-rate).exp())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EnergySettings {
+    pub setpoint: f64,
+    pub season: Season,
+    #[serde(default)]
+    pub ventilation: VentilationModel,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EnergyEstimate {
+    pub season: Season,
+    pub setpoint: f64,
+    // kJ/kg
+    pub indoor_enthalpy: f64,
+    pub outdoor_enthalpy: f64,
+    pub expected_change_per_hour: f64,
+    pub expected_temp_in_one_hour: f64,
+}
+
+fn round1(n: f64) -> f64 {
+    (n * 10.0).round() / 10.0
+}
+
+impl EnergySettings {
+    pub fn evaluate(&self, inputs: &Inputs) -> (RuleOutcome, EnergyEstimate) {
+        let indoor = &inputs.indoor;
+        let outdoor = &inputs.outdoor;
+        let indoor_enthalpy = enthalpy(indoor.temp, indoor.humidity as f64);
+        let outdoor_enthalpy = enthalpy(outdoor.temp, outdoor.humidity as f64);
+        let change = self.ventilation.change_per_hour(indoor.temp, outdoor.temp);
+        let (name, passed, reason) = match self.season {
+            Season::Cooling => (
+                "passive_cooling",
+                indoor.temp > self.setpoint
+                    && outdoor.temp < indoor.temp
+                    && outdoor_enthalpy < indoor_enthalpy,
+                format!(
+                    "cool to {}: indoor {} outdoor {}, enthalpy indoor {:.1} outdoor {:.1} kJ/kg",
+                    self.setpoint, indoor.temp, outdoor.temp, indoor_enthalpy, outdoor_enthalpy
+                ),
+            ),
+            Season::Heating => (
+                "passive_heating",
+                indoor.temp < self.setpoint && outdoor.temp > indoor.temp,
+                format!(
+                    "heat to {}: indoor {} outdoor {}",
+                    self.setpoint, indoor.temp, outdoor.temp
+                ),
+            ),
+        };
+        let outcome = RuleOutcome {
+            name: name.into(),
+            passed,
+            reason,
+            icon: Some("♻️".into()),
+            children: vec![],
+        };
+        let estimate = EnergyEstimate {
+            season: self.season,
+            setpoint: self.setpoint,
+            indoor_enthalpy: round1(indoor_enthalpy),
+            outdoor_enthalpy: round1(outdoor_enthalpy),
+            expected_change_per_hour: round1(change),
+            expected_temp_in_one_hour: round1(indoor.temp + change),
+        };
+        (outcome, estimate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decision::Conditions;
+
+    fn inputs(indoor: (f64, u64), outdoor: (f64, u64)) -> Inputs {
+        Inputs {
+            indoor: Conditions {
+                temp: indoor.0,
+                humidity: indoor.1,
+                time: None,
+            },
+            outdoor: Conditions {
+                temp: outdoor.0,
+                humidity: outdoor.1,
+                time: None,
+            },
+            pollution: None,
+            weather: None,
+            indoor_air: None,
+        }
+    }
+
+    fn settings(season: Season) -> EnergySettings {
+        EnergySettings {
+            setpoint: 72.0,
+            season,
+            ventilation: VentilationModel::default(),
+        }
+    }
+
+    #[test]
+    fn test_cooling() {
+        let (outcome, estimate) =
+            settings(Season::Cooling).evaluate(&inputs((78.0, 50), (68.0, 50)));
+        assert!(outcome.passed);
+        assert!(estimate.expected_change_per_hour < 0.0);
+        assert!(estimate.expected_temp_in_one_hour > 68.0);
+    }
+
+    #[test]
+    fn test_muggy_air_is_not_cooling() {
+        let (outcome, _) = settings(Season::Cooling).evaluate(&inputs((78.0, 40), (74.0, 95)));
+        assert!(!outcome.passed);
+    }
+
+    #[test]
+    fn test_cooling_below_setpoint() {
+        let (outcome, _) = settings(Season::Cooling).evaluate(&inputs((70.0, 50), (60.0, 50)));
+        assert!(!outcome.passed);
+    }
+
+    #[test]
+    fn test_heating() {
+        let (outcome, estimate) =
+            settings(Season::Heating).evaluate(&inputs((66.0, 40), (75.0, 60)));
+        assert!(outcome.passed);
+        assert_eq!(outcome.name, "passive_heating");
+        assert!(estimate.expected_change_per_hour > 0.0);
+    }
+}
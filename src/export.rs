@@ -0,0 +1,340 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use std::io::Write;
+
+use crate::history::{Aggregate, Sample};
+
+// Writes history rows as an aligned table, CSV, JSON lines or Parquet (with the parquet
+// feature). Times are RFC 3339 UTC in the text formats and timestamps in Parquet.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Table,
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "table" => Ok(Format::Table),
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::Jsonl),
+            "parquet" => Ok(Format::Parquet),
+            _ => bail!("format: expected table, csv, jsonl or parquet, got {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    // unix seconds
+    Time(i64),
+    Text(Option<String>),
+    Int(i64),
+    Float(f64),
+}
+
+impl Cell {
+    fn to_text(&self) -> String {
+        match self {
+            Cell::Time(t) => DateTime::<Utc>::from_timestamp(*t, 0)
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_default(),
+            Cell::Text(s) => s.clone().unwrap_or_default(),
+            Cell::Int(n) => n.to_string(),
+            Cell::Float(n) => n.to_string(),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Cell::Time(_) => self.to_text().into(),
+            Cell::Text(s) => s.clone().into(),
+            Cell::Int(n) => (*n).into(),
+            Cell::Float(n) => (*n).into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Table {
+    pub columns: Vec<&'static str>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub fn samples(metric: &str, samples: &[Sample]) -> Table {
+        Table {
+            columns: vec!["time", "metric", "device", "value"],
+            rows: samples
+                .iter()
+                .map(|s| {
+                    vec![
+                        Cell::Time(s.time),
+                        Cell::Text(Some(metric.into())),
+                        Cell::Text(s.device.clone()),
+                        Cell::Float(s.value),
+                    ]
+                })
+                .collect(),
+        }
+    }
+
+    pub fn aggregates(metric: &str, aggregates: &[Aggregate]) -> Table {
+        Table {
+            columns: vec!["time", "metric", "device", "count", "min", "mean", "max"],
+            rows: aggregates
+                .iter()
+                .map(|a| {
+                    vec![
+                        Cell::Time(a.time),
+                        Cell::Text(Some(metric.into())),
+                        Cell::Text(a.device.clone()),
+                        Cell::Int(a.count as i64),
+                        Cell::Float(a.min),
+                        Cell::Float((a.mean * 100.0).round() / 100.0),
+                        Cell::Float(a.max),
+                    ]
+                })
+                .collect(),
+        }
+    }
+
+    /// rows of tables with the same columns, e.g. one per metric
+    pub fn extend(&mut self, other: Table) {
+        if self.columns.is_empty() {
+            self.columns = other.columns;
+        }
+        self.rows.extend(other.rows);
+    }
+
+    pub fn write<W: Write + Send>(&self, format: Format, out: W) -> Result<()> {
+        match format {
+            Format::Table => self.write_table(out),
+            Format::Csv => self.write_csv(out),
+            Format::Jsonl => self.write_jsonl(out),
+            Format::Parquet => self.write_parquet(out),
+        }
+    }
+
+    fn write_table<W: Write>(&self, mut out: W) -> Result<()> {
+        let text: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(Cell::to_text).collect())
+            .collect();
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                text.iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([column.len()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let line = |cells: Vec<&str>| {
+            cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+        writeln!(out, "{}", line(self.columns.clone()))?;
+        for row in &text {
+            writeln!(out, "{}", line(row.iter().map(String::as_str).collect()))?;
+        }
+        Ok(())
+    }
+
+    fn write_csv<W: Write>(&self, mut out: W) -> Result<()> {
+        let escape = |s: String| {
+            if s.contains([',', '"', '\n']) {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            } else {
+                s
+            }
+        };
+        writeln!(out, "{}", self.columns.join(","))?;
+        for row in &self.rows {
+            let cells: Vec<String> = row.iter().map(|c| escape(c.to_text())).collect();
+            writeln!(out, "{}", cells.join(","))?;
+        }
+        Ok(())
+    }
+
+    fn write_jsonl<W: Write>(&self, mut out: W) -> Result<()> {
+        for row in &self.rows {
+            let object: serde_json::Map<String, serde_json::Value> = self
+                .columns
+                .iter()
+                .zip(row)
+                .map(|(column, cell)| (column.to_string(), cell.to_json()))
+                .collect();
+            writeln!(out, "{}", serde_json::Value::Object(object))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    fn write_parquet<W: Write + Send>(&self, _out: W) -> Result<()> {
+        bail!("write_parquet: built without the parquet feature")
+    }
+
+    #[cfg(feature = "parquet")]
+    fn write_parquet<W: Write + Send>(&self, out: W) -> Result<()> {
+        use anyhow::Context;
+        use parquet::basic::Compression;
+        use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+        use parquet::file::properties::WriterProperties;
+        use parquet::file::writer::SerializedFileWriter;
+        use parquet::schema::parser::parse_message_type;
+        use std::sync::Arc;
+
+        let Some(first) = self.rows.first() else {
+            bail!("write_parquet: nothing to write");
+        };
+        let fields: Vec<String> = self
+            .columns
+            .iter()
+            .zip(first)
+            .map(|(column, cell)| match cell {
+                Cell::Time(_) => format!("REQUIRED INT64 {} (TIMESTAMP(MILLIS,true));", column),
+                Cell::Text(_) => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", column),
+                Cell::Int(_) => format!("REQUIRED INT64 {};", column),
+                Cell::Float(_) => format!("REQUIRED DOUBLE {};", column),
+            })
+            .collect();
+        let schema = parse_message_type(&format!("message history {{ {} }}", fields.join(" ")))
+            .context("write_parquet: bad schema")?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::UNCOMPRESSED)
+            .build();
+        let mut writer = SerializedFileWriter::new(out, Arc::new(schema), Arc::new(properties))
+            .context("write_parquet: could not start file")?;
+        let mut row_group = writer.next_row_group()?;
+        let mut i = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            let cells = self.rows.iter().map(|row| &row[i]);
+            match first[i] {
+                Cell::Time(_) | Cell::Int(_) => {
+                    let values: Vec<i64> = cells
+                        .map(|cell| match cell {
+                            Cell::Time(t) => t * 1000,
+                            Cell::Int(n) => *n,
+                            _ => 0,
+                        })
+                        .collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&values, None, None)?;
+                }
+                Cell::Float(_) => {
+                    let values: Vec<f64> = cells
+                        .map(|cell| match cell {
+                            Cell::Float(n) => *n,
+                            _ => f64::NAN,
+                        })
+                        .collect();
+                    column
+                        .typed::<DoubleType>()
+                        .write_batch(&values, None, None)?;
+                }
+                Cell::Text(_) => {
+                    let mut values = Vec::new();
+                    let mut definition_levels = Vec::new();
+                    for cell in cells {
+                        match cell {
+                            Cell::Text(Some(s)) => {
+                                values.push(ByteArray::from(s.as_str()));
+                                definition_levels.push(1);
+                            }
+                            _ => definition_levels.push(0),
+                        }
+                    }
+                    column.typed::<ByteArrayType>().write_batch(
+                        &values,
+                        Some(&definition_levels),
+                        None,
+                    )?;
+                }
+            }
+            column.close()?;
+            i += 1;
+        }
+        row_group.close()?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        Table::samples(
+            "indoor.temp",
+            &[
+                Sample {
+                    time: 1694880000,
+                    device: Some("2930".into()),
+                    value: 70.5,
+                },
+                Sample {
+                    time: 1694883600,
+                    device: None,
+                    value: 71.0,
+                },
+            ],
+        )
+    }
+
+    fn written(format: Format) -> String {
+        let mut out = Vec::new();
+        table().write(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(
+            written(Format::Csv),
+            "time,metric,device,value\n\
+             2023-09-16T16:00:00+00:00,indoor.temp,2930,70.5\n\
+             2023-09-16T17:00:00+00:00,indoor.temp,,71\n"
+        );
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let first = written(Format::Jsonl).lines().next().unwrap().to_string();
+        let row: serde_json::Value = serde_json::from_str(&first).unwrap();
+        assert_eq!(row["device"], "2930");
+        assert_eq!(row["value"], 70.5);
+    }
+
+    #[test]
+    fn test_table() {
+        let text = written(Format::Table);
+        assert!(text.starts_with("time                       metric       device  value\n"));
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet() {
+        let mut out = Vec::new();
+        table().write(Format::Parquet, &mut out).unwrap();
+        assert_eq!(&out[..4], b"PAR1");
+        assert_eq!(&out[out.len() - 4..], b"PAR1");
+    }
+}
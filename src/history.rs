@@ -0,0 +1,919 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+use crate::airthings_radon::celsius_to_fahrenheit;
+use crate::decision::Decision;
+use crate::pollution::{pollution_aqi, pollution_time};
+use crate::rain::{DailyClimate, Precipitation};
+use crate::weather::weather_time;
+
+// History database, ~/.local/share/iloveair/history.db by convention.
+//
+// Every binary takes --db and records what it fetched or decided: getweather into weather,
+// getpollution into pollution, read_waveplus into indoor, weather_notify into decisions,
+// pushover_notify into notifications and getrain a day's high, low and precipitation into
+// climate. `iloveair backfill` adds past OWM weather and pollution to the same tables and keeps
+// its progress in backfills. Each call to Airthings, OWM, NWS and Pushover is also counted in
+// fetches, successful or not. Each row keeps the original JSON next to the columns the reports
+// use. `time` is the reading's own timestamp (OWM dt, Airthings time), `fetched_at` when it was
+// stored, both unix seconds. Temperatures are °F like the reports.
+//
+// Schema changes are appended to MIGRATIONS, never edited; PRAGMA user_version records how
+// many have been applied.
+
+static MIGRATIONS: &[&str] = &[
+    r#"
+CREATE TABLE weather (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    fetched_at INTEGER NOT NULL,
+    location TEXT,
+    temp REAL,
+    humidity REAL,
+    json TEXT NOT NULL,
+    UNIQUE (time, location)
+);
+CREATE TABLE pollution (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    fetched_at INTEGER NOT NULL,
+    aqi INTEGER,
+    json TEXT NOT NULL,
+    UNIQUE (time)
+);
+CREATE TABLE indoor (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    fetched_at INTEGER NOT NULL,
+    device TEXT NOT NULL,
+    temp REAL,
+    humidity REAL,
+    radon REAL,
+    co2 REAL,
+    voc REAL,
+    pressure REAL,
+    battery REAL,
+    json TEXT NOT NULL,
+    UNIQUE (time, device)
+);
+CREATE TABLE decisions (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    window_should_be_open INTEGER NOT NULL,
+    json TEXT NOT NULL
+);
+CREATE TABLE notifications (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    channel TEXT NOT NULL,
+    message TEXT NOT NULL,
+    dry_run INTEGER NOT NULL
+);
+CREATE INDEX weather_time ON weather (time);
+CREATE INDEX pollution_time ON pollution (time);
+CREATE INDEX indoor_time ON indoor (time);
+CREATE INDEX decisions_time ON decisions (time);
+"#,
+    r#"
+CREATE TABLE fetches (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    source TEXT NOT NULL,
+    success INTEGER NOT NULL,
+    error TEXT
+);
+CREATE INDEX fetches_source ON fetches (source, time);
+"#,
+    r#"
+CREATE TABLE backfills (
+    source TEXT NOT NULL,
+    start INTEGER NOT NULL,
+    done_until INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (source, start)
+);
+"#,
+    r#"
+CREATE TABLE climate (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    fetched_at INTEGER NOT NULL,
+    date TEXT NOT NULL UNIQUE,
+    high REAL,
+    low REAL,
+    precipitation REAL,
+    trace INTEGER NOT NULL,
+    json TEXT NOT NULL
+);
+CREATE INDEX climate_time ON climate (time);
+"#,
+];
+
+// metric name, table, column
+static METRICS: &[(&str, &str, &str)] = &[
+    ("indoor.temp", "indoor", "temp"),
+    ("indoor.humidity", "indoor", "humidity"),
+    ("indoor.radon", "indoor", "radon"),
+    ("indoor.co2", "indoor", "co2"),
+    ("indoor.voc", "indoor", "voc"),
+    ("indoor.pressure", "indoor", "pressure"),
+    ("indoor.battery", "indoor", "battery"),
+    ("outdoor.temp", "weather", "temp"),
+    ("outdoor.humidity", "weather", "humidity"),
+    ("aqi", "pollution", "aqi"),
+    ("climate.high", "climate", "high"),
+    ("climate.low", "climate", "low"),
+    ("precipitation", "climate", "precipitation"),
+    (
+        "window_should_be_open",
+        "decisions",
+        "window_should_be_open",
+    ),
+];
+
+pub fn metric_names() -> Vec<&'static str> {
+    METRICS.iter().map(|(name, _, _)| *name).collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub time: i64,
+    // indoor device id or weather location
+    pub device: Option<String>,
+    pub value: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Notification {
+    pub time: i64,
+    pub channel: String,
+    pub message: String,
+    pub dry_run: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FetchStats {
+    // airthings, owm_weather, nws_weather, open_meteo_weather, owm_pollution or pushover
+    pub source: String,
+    pub successes: u64,
+    pub failures: u64,
+    pub last_success: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+// failed fetches from a source since its last success
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FailureStreak {
+    pub source: String,
+    pub failures: u64,
+    // time of the first failure
+    pub since: i64,
+    pub last_error: Option<String>,
+}
+
+enum Select {
+    All,
+    Newest,
+    NewestPerDevice,
+}
+
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    pub fn open(history_db_path: &str) -> Result<History> {
+        let path = shellexpand::tilde(history_db_path).into_owned();
+        if let Some(dir) = Path::new(&path).parent() {
+            if !dir.as_os_str().is_empty() {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("History::open: could not create {:?}", dir))?;
+            }
+        }
+        let conn = Connection::open(&path)
+            .with_context(|| format!("History::open: could not open {}", path))?;
+        History::migrate(conn).with_context(|| format!("History::open: {}", path))
+    }
+
+    pub fn open_in_memory() -> Result<History> {
+        History::migrate(Connection::open_in_memory()?)
+    }
+
+    fn migrate(mut conn: Connection) -> Result<History> {
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            bail!(
+                "history schema version {} is newer than supported {}",
+                version,
+                MIGRATIONS.len()
+            );
+        }
+        for (n, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)
+                .with_context(|| format!("migrate: migration {} failed", n + 1))?;
+            tx.pragma_update(None, "user_version", n + 1)?;
+            tx.commit()?;
+        }
+        Ok(History { conn })
+    }
+
+    pub fn schema_version(&self) -> Result<usize> {
+        Ok(self
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))?)
+    }
+
+    /// an OWM current weather response, as written by getweather
+    pub fn record_weather(&self, weather_json: &Value) -> Result<()> {
+        let now = Utc::now().timestamp();
+        self.conn
+            .execute(
+                "INSERT OR IGNORE INTO weather (time, fetched_at, location, temp, humidity, json)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    weather_time(weather_json).unwrap_or(now),
+                    now,
+                    weather_json["name"].as_str(),
+                    weather_json["main"]["temp"].as_f64(),
+                    weather_json["main"]["humidity"].as_f64(),
+                    weather_json.to_string(),
+                ],
+            )
+            .context("record_weather: could not insert")?;
+        Ok(())
+    }
+
+    /// a day's high, low and precipitation from getrain, a later report for the same day replaces
+    /// it, `time` is local midnight starting the day
+    pub fn record_climate(&self, climate: &DailyClimate) -> Result<()> {
+        let Some(midnight) = Local
+            .from_local_datetime(&climate.date.and_time(NaiveTime::MIN))
+            .earliest()
+        else {
+            bail!("record_climate: no local midnight on {}", climate.date);
+        };
+        self.conn
+            .execute(
+                "INSERT INTO climate (time, fetched_at, date, high, low, precipitation, trace, json)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                 ON CONFLICT (date) DO UPDATE
+                 SET fetched_at = excluded.fetched_at, high = excluded.high, low = excluded.low,
+                     precipitation = excluded.precipitation, trace = excluded.trace,
+                     json = excluded.json",
+                params![
+                    midnight.timestamp(),
+                    Utc::now().timestamp(),
+                    climate.date.to_string(),
+                    climate.high,
+                    climate.low,
+                    climate.precipitation.inches(),
+                    climate.precipitation == Precipitation::Trace,
+                    serde_json::to_string(climate)?,
+                ],
+            )
+            .context("record_climate: could not upsert")?;
+        Ok(())
+    }
+
+    /// an OWM air pollution response, as written by getpollution
+    pub fn record_pollution(&self, pollution_json: &Value) -> Result<()> {
+        let now = Utc::now().timestamp();
+        self.conn
+            .execute(
+                "INSERT OR IGNORE INTO pollution (time, fetched_at, aqi, json)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    pollution_time(pollution_json).unwrap_or(now),
+                    now,
+                    pollution_aqi(pollution_json).ok(),
+                    pollution_json.to_string(),
+                ],
+            )
+            .context("record_pollution: could not insert")?;
+        Ok(())
+    }
+
+    /// an Airthings latest-samples reading (°C), as written by read_waveplus
+    pub fn record_indoor(&self, device: &str, indoor_json: &Value) -> Result<()> {
+        let now = Utc::now().timestamp();
+        self.conn
+            .execute(
+                "INSERT OR IGNORE INTO indoor
+                 (time, fetched_at, device, temp, humidity, radon, co2, voc, pressure, battery, json)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    indoor_json["time"].as_i64().unwrap_or(now),
+                    now,
+                    device,
+                    indoor_json["temp"].as_f64().map(celsius_to_fahrenheit),
+                    indoor_json["humidity"].as_f64(),
+                    indoor_json["radonShortTermAvg"].as_f64(),
+                    indoor_json["co2"].as_f64(),
+                    indoor_json["voc"].as_f64(),
+                    indoor_json["pressure"].as_f64(),
+                    indoor_json["battery"].as_f64(),
+                    indoor_json.to_string(),
+                ],
+            )
+            .context("record_indoor: could not insert")?;
+        Ok(())
+    }
+
+    pub fn record_decision(&self, decision: &Decision) -> Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO decisions (time, window_should_be_open, json) VALUES (?1, ?2, ?3)",
+                params![
+                    decision.generated_at.timestamp(),
+                    decision.window_should_be_open,
+                    serde_json::to_string(decision)?,
+                ],
+            )
+            .context("record_decision: could not insert")?;
+        Ok(())
+    }
+
+    pub fn record_notification(&self, channel: &str, message: &str, dry_run: bool) -> Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO notifications (time, channel, message, dry_run)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![Utc::now().timestamp(), channel, message, dry_run],
+            )
+            .context("record_notification: could not insert")?;
+        Ok(())
+    }
+
+    /// one call to an external API, `error` is None when it succeeded
+    pub fn record_fetch(&self, source: &str, error: Option<&str>) -> Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO fetches (time, source, success, error) VALUES (?1, ?2, ?3, ?4)",
+                params![Utc::now().timestamp(), source, error.is_none(), error],
+            )
+            .context("record_fetch: could not insert")?;
+        Ok(())
+    }
+
+    pub fn record_fetch_result<T, E: std::fmt::Display>(
+        &self,
+        source: &str,
+        result: &std::result::Result<T, E>,
+    ) -> Result<()> {
+        self.record_fetch(
+            source,
+            // {:#} so an anyhow error keeps its causes
+            result.as_ref().err().map(|e| format!("{:#}", e)).as_deref(),
+        )
+    }
+
+    pub fn fetch_stats(&self) -> Result<Vec<FetchStats>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT source, sum(success), sum(1 - success),
+             max(CASE WHEN success THEN time END),
+             (SELECT error FROM fetches f WHERE f.source = fetches.source AND NOT success
+              ORDER BY time DESC, id DESC LIMIT 1)
+             FROM fetches GROUP BY source ORDER BY source",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(FetchStats {
+                source: row.get(0)?,
+                successes: row.get(1)?,
+                failures: row.get(2)?,
+                last_success: row.get(3)?,
+                last_error: row.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// how far a backfill of `source` starting at `start` got, None when it never ran
+    pub fn backfill_progress(&self, source: &str, start: i64) -> Result<Option<i64>> {
+        self.conn
+            .query_row(
+                "SELECT done_until FROM backfills WHERE source = ?1 AND start = ?2",
+                params![source, start],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("backfill_progress: could not query")
+    }
+
+    pub fn record_backfill_progress(
+        &self,
+        source: &str,
+        start: i64,
+        done_until: i64,
+    ) -> Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO backfills (source, start, done_until, updated_at)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (source, start) DO UPDATE
+                 SET done_until = excluded.done_until, updated_at = excluded.updated_at",
+                params![source, start, done_until, Utc::now().timestamp()],
+            )
+            .context("record_backfill_progress: could not upsert")?;
+        Ok(())
+    }
+
+    /// sources whose latest fetches failed, with how many failed in a row
+    pub fn failure_streaks(&self) -> Result<Vec<FailureStreak>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT source, count(*), min(time),
+             (SELECT error FROM fetches e WHERE e.source = f.source
+              ORDER BY time DESC, id DESC LIMIT 1)
+             FROM fetches f
+             WHERE NOT success AND id > coalesce(
+               (SELECT max(id) FROM fetches s WHERE s.source = f.source AND s.success), 0)
+             GROUP BY source ORDER BY source",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(FailureStreak {
+                source: row.get(0)?,
+                failures: row.get(1)?,
+                since: row.get(2)?,
+                last_error: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// readings of `metric` (see metric_names) between from and to inclusive, oldest first
+    pub fn samples(
+        &self,
+        metric: &str,
+        device: Option<&str>,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<Sample>> {
+        self.query_samples(metric, device, from, to, Select::All)
+    }
+
+    /// newest reading of `metric` from each device or location
+    pub fn latest_per_device(&self, metric: &str) -> Result<Vec<Sample>> {
+        self.query_samples(metric, None, None, None, Select::NewestPerDevice)
+    }
+
+    /// newest reading of `metric`
+    pub fn latest(&self, metric: &str, device: Option<&str>) -> Result<Option<Sample>> {
+        Ok(self
+            .query_samples(metric, device, None, None, Select::Newest)?
+            .pop())
+    }
+
+    fn query_samples(
+        &self,
+        metric: &str,
+        device: Option<&str>,
+        from: Option<i64>,
+        to: Option<i64>,
+        select: Select,
+    ) -> Result<Vec<Sample>> {
+        let Some((_, table, column)) = METRICS.iter().find(|(name, _, _)| *name == metric) else {
+            bail!(
+                "samples: unknown metric {}, expected one of {}",
+                metric,
+                metric_names().join(", ")
+            );
+        };
+        let device_column = match *table {
+            "indoor" => "device",
+            "weather" => "location",
+            _ => "NULL",
+        };
+        let sql = format!(
+            "SELECT time, {device_column}, {column} FROM {table}
+             WHERE {column} IS NOT NULL AND time >= ?1 AND time <= ?2
+             AND (?3 IS NULL OR {device_column} = ?3)
+             {order}",
+            order = match select {
+                Select::All => "ORDER BY time".to_string(),
+                Select::Newest => "ORDER BY time DESC LIMIT 1".to_string(),
+                Select::NewestPerDevice => {
+                    let same_device = if device_column == "NULL" {
+                        String::new()
+                    } else {
+                        format!("AND newest.{device_column} IS {table}.{device_column}")
+                    };
+                    format!(
+                        "AND time = (SELECT max(time) FROM {table} newest
+                         WHERE newest.{column} IS NOT NULL {same_device})
+                         ORDER BY 2"
+                    )
+                }
+            }
+        );
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let rows = stmt.query_map(
+            params![from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX), device],
+            |row| {
+                Ok(Sample {
+                    time: row.get(0)?,
+                    device: row.get(1)?,
+                    value: row.get(2)?,
+                })
+            },
+        )?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()
+            .with_context(|| format!("samples: could not read {}", metric))
+    }
+
+    /// the original JSON of the newest row in weather, pollution or indoor
+    pub fn latest_json(&self, table: &str) -> Result<Option<Value>> {
+        if !["weather", "pollution", "indoor"].contains(&table) {
+            bail!("latest_json: unknown table {}", table);
+        }
+        let json: Option<String> = self
+            .conn
+            .query_row(
+                &format!("SELECT json FROM {table} ORDER BY time DESC, id DESC LIMIT 1"),
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        json.map(|json| serde_json::from_str(&json).map_err(|e| e.into()))
+            .transpose()
+    }
+
+    /// the original JSON of every weather, pollution or indoor row in a time range, with the
+    /// device or location
+    pub fn json_rows(
+        &self,
+        table: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<(Option<String>, Value)>> {
+        let device = match table {
+            "weather" => "location",
+            "indoor" => "device",
+            "pollution" => "NULL",
+            _ => bail!("json_rows: unknown table {}", table),
+        };
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT {device}, json FROM {table} WHERE time >= ?1 AND time <= ?2 ORDER BY time, id"
+        ))?;
+        let rows = stmt.query_map(
+            params![from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX)],
+            |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, String>(1)?)),
+        )?;
+        let mut json_rows = Vec::new();
+        for row in rows {
+            let (device, json) = row?;
+            let json = serde_json::from_str(&json)
+                .with_context(|| format!("json_rows: could not parse {} json", table))?;
+            json_rows.push((device, json));
+        }
+        Ok(json_rows)
+    }
+
+    pub fn latest_decision(&self) -> Result<Option<Decision>> {
+        Ok(self.decisions(None, None)?.pop())
+    }
+
+    pub fn decisions(&self, from: Option<i64>, to: Option<i64>) -> Result<Vec<Decision>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT json FROM decisions WHERE time >= ?1 AND time <= ?2 ORDER BY time, id",
+        )?;
+        let rows = stmt.query_map(
+            params![from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX)],
+            |row| row.get::<_, String>(0),
+        )?;
+        let mut decisions = Vec::new();
+        for json in rows {
+            decisions
+                .push(serde_json::from_str(&json?).context("decisions: could not parse decision")?);
+        }
+        Ok(decisions)
+    }
+
+    pub fn notifications(&self, from: Option<i64>, to: Option<i64>) -> Result<Vec<Notification>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT time, channel, message, dry_run FROM notifications
+             WHERE time >= ?1 AND time <= ?2 ORDER BY time, id",
+        )?;
+        let rows = stmt.query_map(
+            params![from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX)],
+            |row| {
+                Ok(Notification {
+                    time: row.get(0)?,
+                    channel: row.get(1)?,
+                    message: row.get(2)?,
+                    dry_run: row.get(3)?,
+                })
+            },
+        )?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interval {
+    Raw,
+    Hourly,
+    Daily,
+}
+
+impl std::str::FromStr for Interval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Interval> {
+        match s {
+            "raw" => Ok(Interval::Raw),
+            "hourly" => Ok(Interval::Hourly),
+            "daily" => Ok(Interval::Daily),
+            _ => bail!("interval: expected raw, hourly or daily, got {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Aggregate {
+    // start of the hour or day
+    pub time: i64,
+    pub device: Option<String>,
+    pub count: usize,
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+}
+
+fn bucket_in<T: TimeZone>(time: i64, interval: Interval, tz: &T) -> i64 {
+    let Some(local) = tz.timestamp_opt(time, 0).single() else {
+        return time;
+    };
+    let start = match interval {
+        Interval::Raw => return time,
+        Interval::Hourly => local.with_minute(0).and_then(|t| t.with_second(0)),
+        Interval::Daily => local
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .and_then(|midnight| tz.from_local_datetime(&midnight).earliest()),
+    };
+    start.map(|t| t.timestamp()).unwrap_or(time)
+}
+
+/// start of the hour or day containing `time`, days in `timezone` or the host's local time
+fn bucket(time: i64, interval: Interval, timezone: Option<&Tz>) -> i64 {
+    match timezone {
+        Some(tz) => bucket_in(time, interval, tz),
+        None => bucket_in(time, interval, &Local),
+    }
+}
+
+/// min/mean/max per device and hour or day, samples as returned by History::samples
+pub fn aggregate(samples: &[Sample], interval: Interval, timezone: Option<&Tz>) -> Vec<Aggregate> {
+    let mut buckets: std::collections::BTreeMap<(i64, Option<String>), Vec<f64>> =
+        std::collections::BTreeMap::new();
+    for sample in samples {
+        buckets
+            .entry((
+                bucket(sample.time, interval, timezone),
+                sample.device.clone(),
+            ))
+            .or_default()
+            .push(sample.value);
+    }
+    buckets
+        .into_iter()
+        .map(|((time, device), values)| Aggregate {
+            time,
+            device,
+            count: values.len(),
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        })
+        .collect()
+}
+
+/// unix seconds from RFC 3339, YYYY-MM-DD (local midnight) or a duration before now like 24h or 7d
+pub fn parse_time(s: &str, now: DateTime<Utc>) -> Result<i64> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.timestamp());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return date
+            .and_hms_opt(0, 0, 0)
+            .and_then(|midnight| Local.from_local_datetime(&midnight).earliest())
+            .map(|t| t.timestamp())
+            .ok_or_else(|| anyhow!("parse_time: no local midnight on {}", s));
+    }
+    let unit = match s.chars().last() {
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        _ => bail!(
+            "parse_time: expected RFC 3339, YYYY-MM-DD or 24h/7d, got {}",
+            s
+        ),
+    };
+    // a negative or zero duration would be now or later, which no sample is from
+    let n: u32 = s[..s.len() - 1]
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .with_context(|| format!("parse_time: bad duration {}", s))?;
+    Ok(now.timestamp() - i64::from(n) * unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn indoor(time: i64, radon: f64) -> Value {
+        json!({
+            "battery": 99, "humidity": 61.0, "radonShortTermAvg": radon, "temp": 20.0,
+            "time": time, "relayDeviceType": "hub", "co2": 900.0
+        })
+    }
+
+    #[test]
+    fn test_migrations() {
+        let history = History::open_in_memory().unwrap();
+        assert_eq!(history.schema_version().unwrap(), MIGRATIONS.len());
+        // reapplying is a no-op
+        let history = History::migrate(history.conn).unwrap();
+        assert_eq!(history.schema_version().unwrap(), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_indoor_samples() {
+        let history = History::open_in_memory().unwrap();
+        history.record_indoor("2930", &indoor(100, 35.0)).unwrap();
+        history.record_indoor("2930", &indoor(200, 40.0)).unwrap();
+        // the same reading fetched twice is stored once
+        history.record_indoor("2930", &indoor(200, 40.0)).unwrap();
+        history.record_indoor("other", &indoor(150, 90.0)).unwrap();
+        // out of order, an older reading recorded late
+        history.record_indoor("other", &indoor(120, 80.0)).unwrap();
+
+        let radon = history
+            .samples("indoor.radon", Some("2930"), None, None)
+            .unwrap();
+        assert_eq!(
+            radon.iter().map(|s| s.value).collect::<Vec<_>>(),
+            vec![35.0, 40.0]
+        );
+        let temp = history
+            .samples("indoor.temp", None, Some(130), None)
+            .unwrap();
+        assert_eq!(temp.len(), 2);
+        assert_eq!(temp[0].value, 68.0);
+        assert_eq!(
+            history.latest("indoor.radon", None).unwrap().unwrap().value,
+            40.0
+        );
+        let latest = history.latest_per_device("indoor.radon").unwrap();
+        assert_eq!(
+            latest
+                .iter()
+                .map(|s| (s.device.as_deref().unwrap(), s.value))
+                .collect::<Vec<_>>(),
+            vec![("2930", 40.0), ("other", 90.0)]
+        );
+        // Wave Mini readings without voc are skipped
+        assert!(history
+            .samples("indoor.voc", None, None, None)
+            .unwrap()
+            .is_empty());
+        assert!(history.samples("indoor.nope", None, None, None).is_err());
+        assert_eq!(
+            history.latest_json("indoor").unwrap().unwrap()["time"],
+            json!(200)
+        );
+    }
+
+    #[test]
+    fn test_outdoor_samples() {
+        let history = History::open_in_memory().unwrap();
+        history
+            .record_weather(&json!({"dt": 1694880270, "name": "Boston", "main": {"temp": 61.5, "humidity": 80}}))
+            .unwrap();
+        history
+            .record_pollution(&json!({"list": [{"dt": 1694880000, "main": {"aqi": 2}}]}))
+            .unwrap();
+        let temp = history.samples("outdoor.temp", None, None, None).unwrap();
+        assert_eq!(temp[0].device.as_deref(), Some("Boston"));
+        assert_eq!(temp[0].time, 1694880270);
+        let aqi = history.samples("aqi", None, None, None).unwrap();
+        assert_eq!(aqi[0].value, 2.0);
+    }
+
+    #[test]
+    fn test_record_climate() {
+        let history = History::open_in_memory().unwrap();
+        let mut climate = DailyClimate {
+            date: NaiveDate::from_ymd_opt(2023, 9, 15).unwrap(),
+            high: Some(76.0),
+            low: None,
+            precipitation: Precipitation::Trace,
+        };
+        history.record_climate(&climate).unwrap();
+        let rain = history.samples("precipitation", None, None, None).unwrap();
+        assert_eq!(rain.len(), 1);
+        assert_eq!(rain[0].value, 0.0);
+        assert!(history
+            .samples("climate.low", None, None, None)
+            .unwrap()
+            .is_empty());
+
+        // a corrected report for the same day replaces it
+        climate.precipitation = Precipitation::Inches(0.12);
+        history.record_climate(&climate).unwrap();
+        let rain = history.samples("precipitation", None, None, None).unwrap();
+        assert_eq!(rain.len(), 1);
+        assert_eq!(rain[0].value, 0.12);
+    }
+
+    #[test]
+    fn test_fetch_stats() {
+        let history = History::open_in_memory().unwrap();
+        history.record_fetch("airthings", None).unwrap();
+        history
+            .record_fetch("airthings", Some("401 Unauthorized"))
+            .unwrap();
+        history.record_fetch("pushover", Some("timed out")).unwrap();
+        let stats = history.fetch_stats().unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[0].successes, stats[0].failures), (1, 1));
+        assert!(stats[0].last_success.is_some());
+        assert_eq!(stats[1].last_success, None);
+        assert_eq!(stats[1].last_error.as_deref(), Some("timed out"));
+    }
+
+    #[test]
+    fn test_failure_streaks() {
+        let history = History::open_in_memory().unwrap();
+        history
+            .record_fetch("airthings", Some("timed out"))
+            .unwrap();
+        history.record_fetch("airthings", None).unwrap();
+        history.record_fetch("airthings", Some("502")).unwrap();
+        history.record_fetch("airthings", Some("503")).unwrap();
+        history.record_fetch("owm_weather", None).unwrap();
+        history.record_fetch("pushover", Some("timed out")).unwrap();
+        let streaks = history.failure_streaks().unwrap();
+        assert_eq!(streaks.len(), 2);
+        assert_eq!(streaks[0].source, "airthings");
+        assert_eq!(streaks[0].failures, 2);
+        assert_eq!(streaks[0].last_error.as_deref(), Some("503"));
+        assert_eq!(streaks[1].source, "pushover");
+        history.record_fetch("airthings", None).unwrap();
+        assert_eq!(history.failure_streaks().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_notifications() {
+        let history = History::open_in_memory().unwrap();
+        history
+            .record_notification("pushover", "open the windows", true)
+            .unwrap();
+        let sent = history.notifications(None, None).unwrap();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].dry_run);
+        assert!(history.latest_decision().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let tz: Tz = "America/New_York".parse().unwrap();
+        // 2023-09-16 00:00 New York
+        let midnight = 1694836800;
+        let samples: Vec<Sample> = [(0, 60.0), (1800, 62.0), (3600, 70.0), (86400, 50.0)]
+            .iter()
+            .map(|&(offset, value)| Sample {
+                time: midnight + offset,
+                device: None,
+                value,
+            })
+            .collect();
+        let hourly = aggregate(&samples, Interval::Hourly, Some(&tz));
+        assert_eq!(hourly.len(), 3);
+        assert_eq!((hourly[0].count, hourly[0].mean), (2, 61.0));
+        let daily = aggregate(&samples, Interval::Daily, Some(&tz));
+        assert_eq!(daily.len(), 2);
+        assert_eq!(daily[0].time, midnight);
+        assert_eq!((daily[0].min, daily[0].max), (60.0, 70.0));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let now = Utc.with_ymd_and_hms(2023, 9, 17, 12, 0, 0).unwrap();
+        assert_eq!(parse_time("24h", now).unwrap(), now.timestamp() - 86400);
+        assert_eq!(parse_time("2023-09-16T00:00:00Z", now).unwrap(), 1694822400);
+        assert!(parse_time("2023-09-16", now).is_ok());
+        assert!(parse_time("yesterday", now).is_err());
+        for bad in ["-5d", "0h"] {
+            let e = parse_time(bad, now).unwrap_err();
+            assert!(e.to_string().contains("bad duration"), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,499 @@
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::cache::redact_url;
+use crate::cli::Failure;
+
+// Every HTTP request the tools make goes through here so it can be recorded and replayed:
+//
+//   --record DIR   makes the request and saves the exchange to DIR/0001-GET-api.openweathermap.org-data-2.5-weather.json
+//   --replay DIR   answers from the files in DIR instead of the network
+//
+// Exchanges are matched on method and URL with secrets redacted, in the order they were
+// recorded, so a recording made with one API key replays with another. Once every matching
+// exchange has been used the last one is served again. Request bodies are saved with secrets
+// redacted and are not used for matching, tokens in response bodies are redacted too.
+
+#[derive(Clone, Debug)]
+pub enum Mode {
+    Live,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: &'static str,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+    // the client's timeout when None
+    pub timeout: Option<Duration>,
+}
+
+impl Request {
+    fn new(method: &'static str, url: &str) -> Request {
+        Request {
+            method,
+            url: url.into(),
+            headers: vec![],
+            body: None,
+            timeout: None,
+        }
+    }
+
+    pub fn get(url: &str) -> Request {
+        Request::new("GET", url)
+    }
+
+    pub fn post(url: &str) -> Request {
+        Request::new("POST", url)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Request {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn bearer_auth(self, token: &str) -> Request {
+        self.header("Authorization", &format!("Bearer {}", token))
+    }
+
+    pub fn json<T: Serialize>(self, body: &T) -> Result<Request> {
+        let body = serde_json::to_string(body).context("http: could not serialize body")?;
+        Ok(self.header("Content-Type", "application/json").body(body))
+    }
+
+    pub fn body(mut self, body: String) -> Request {
+        self.body = Some(body);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Request {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub url: String,
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// 401 and 403 are auth failures, any other non-2xx a network failure
+    pub fn error_for_status(self) -> Result<Response> {
+        if self.is_success() {
+            return Ok(self);
+        }
+        let failure = match self.status {
+            401 | 403 => Failure::Auth,
+            _ => Failure::Network,
+        };
+        Err(anyhow!(
+            "{} returned {} {}",
+            redact_url(&self.url),
+            self.status,
+            self.body
+        )
+        .context(failure))
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_str(&self.body)
+            .with_context(|| format!("could not parse response from {}", redact_url(&self.url)))
+    }
+}
+
+// method and redacted URL
+type Key = (String, String);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Exchange {
+    method: String,
+    // secrets redacted, see cache::redact_url
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_body: Option<Value>,
+    status: u16,
+    body: String,
+}
+
+impl Exchange {
+    fn key(&self) -> Key {
+        (self.method.clone(), self.url.clone())
+    }
+}
+
+fn request_key(request: &Request) -> Key {
+    (request.method.into(), redact_url(&request.url))
+}
+
+/// JSON bodies with fields named like secrets blanked, None if the body is not JSON
+fn redact_body(body: &str) -> Option<Value> {
+    let mut json: Value = serde_json::from_str(body).ok()?;
+    if let Value::Object(object) = &mut json {
+        for (key, value) in object.iter_mut() {
+            if [
+                "access_token",
+                "refresh_token",
+                "client_secret",
+                "password",
+                "token",
+                "user",
+                "appid",
+                "api_key",
+            ]
+            .contains(&key.as_str())
+            {
+                *value = Value::String("REDACTED".into());
+            }
+        }
+    }
+    Some(json)
+}
+
+/// "GET", "https://api.openweathermap.org/data/2.5/weather?q=.." -> GET-api.openweathermap.org-data-2.5-weather
+fn file_slug(method: &str, url: &str) -> String {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = without_scheme.split('?').next().unwrap_or_default();
+    let slug: String = path
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("{}-{}", method, slug.trim_matches('-'))
+}
+
+// the recorded exchanges and how many of each key have been served
+struct Replay {
+    exchanges: Vec<Exchange>,
+    served: HashMap<Key, usize>,
+}
+
+pub struct Client {
+    mode: Mode,
+    // next file number when recording
+    sequence: AtomicUsize,
+    // read on the first request when replaying
+    replay: Mutex<Option<Replay>>,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(mode: Mode) -> Client {
+        let sequence = match &mode {
+            Mode::Record(dir) => fs::read_dir(dir)
+                .map(|entries| entries.count())
+                .unwrap_or(0),
+            _ => 0,
+        };
+        Client {
+            mode,
+            sequence: AtomicUsize::new(sequence + 1),
+            replay: Mutex::new(None),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// how long a request may take unless it sets its own timeout, 30 seconds by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn send(&self, request: &Request) -> Result<Response> {
+        debug!("{} {}", request.method, redact_url(&request.url));
+        match &self.mode {
+            Mode::Live => send_live(request, self.timeout),
+            Mode::Record(dir) => {
+                let response = send_live(request, self.timeout)?;
+                self.record(dir, request, &response)?;
+                Ok(response)
+            }
+            Mode::Replay(dir) => self.replay(dir, request),
+        }
+    }
+
+    fn record(&self, dir: &Path, request: &Request, response: &Response) -> Result<()> {
+        fs::create_dir_all(dir).with_context(|| format!("record: could not create {:?}", dir))?;
+        let exchange = Exchange {
+            method: request.method.into(),
+            url: redact_url(&request.url),
+            request_body: request.body.as_deref().and_then(redact_body),
+            status: response.status,
+            body: redact_body(&response.body)
+                .map(|json| json.to_string())
+                .unwrap_or_else(|| response.body.clone()),
+        };
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+        let path = dir.join(format!(
+            "{:04}-{}.json",
+            sequence,
+            file_slug(request.method, &request.url)
+        ));
+        fs::write(&path, serde_json::to_string_pretty(&exchange)?)
+            .with_context(|| format!("record: could not write {:?}", path))?;
+        debug!("recorded: {:?}", path);
+        Ok(())
+    }
+
+    fn replay(&self, dir: &Path, request: &Request) -> Result<Response> {
+        let mut replay = self.replay.lock().unwrap_or_else(|e| e.into_inner());
+        if replay.is_none() {
+            *replay = Some(Replay {
+                exchanges: read_exchanges(dir)?,
+                served: HashMap::new(),
+            });
+        }
+        let Some(Replay { exchanges, served }) = replay.as_mut() else {
+            // This else block is unreachable because replay was just filled in.
+            unreachable!();
+        };
+        let key = request_key(request);
+        let matching: Vec<&Exchange> = exchanges.iter().filter(|e| e.key() == key).collect();
+        let Some(last) = matching.last() else {
+            return Err(anyhow!(
+                "replay: no recorded response for {} {} in {:?}",
+                key.0,
+                key.1,
+                dir
+            )
+            .context(Failure::Network));
+        };
+        let count = served.entry(key).or_insert(0);
+        let exchange = matching.get(*count).unwrap_or(last);
+        *count += 1;
+        Ok(Response {
+            url: request.url.clone(),
+            status: exchange.status,
+            body: exchange.body.clone(),
+        })
+    }
+}
+
+fn read_exchanges(dir: &Path) -> Result<Vec<Exchange>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("replay: could not read {:?}", dir))
+        .context(Failure::Config)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("replay: could not read {:?}", path))?;
+            serde_json::from_str(&contents)
+                .with_context(|| format!("replay: could not parse {:?}", path))
+        })
+        .collect()
+}
+
+fn send_live(request: &Request, timeout: Duration) -> Result<Response> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(request.timeout.unwrap_or(timeout))
+        .build()
+        .context("http: could not build client")?;
+    let method = match request.method {
+        "POST" => reqwest::Method::POST,
+        _ => reqwest::Method::GET,
+    };
+    let mut builder = client.request(method, &request.url);
+    for (name, value) in &request.headers {
+        builder = builder.header(name, value);
+    }
+    if let Some(body) = &request.body {
+        builder = builder.body(body.clone());
+    }
+    let redacted = redact_url(&request.url);
+    let response = builder
+        .send()
+        .with_context(|| format!("{} {}", request.method, redacted))
+        .context(Failure::Network)?;
+    let status = response.status().as_u16();
+    let body = response
+        .text()
+        .with_context(|| format!("{} {}: could not read response", request.method, redacted))
+        .context(Failure::Network)?;
+    Ok(Response {
+        url: request.url.clone(),
+        status,
+        body,
+    })
+}
+
+/// a configured base URL joined with `path`, plain http is refused unless `allow_http` is set so
+/// an API key is never sent in the clear
+pub fn endpoint(base_url: &str, path: &str, allow_http: bool) -> Result<String> {
+    let parsed = url::Url::parse(base_url)
+        .with_context(|| format!("endpoint: bad base URL {}", base_url))
+        .context(Failure::Config)?;
+    match parsed.scheme() {
+        "https" => (),
+        "http" if allow_http => (),
+        "http" => {
+            return Err(anyhow!(
+                "endpoint: {} is plain http, use https or set \"allow_http\": true",
+                base_url
+            )
+            .context(Failure::Config))
+        }
+        scheme => {
+            return Err(
+                anyhow!("endpoint: unsupported scheme {} in {}", scheme, base_url)
+                    .context(Failure::Config),
+            )
+        }
+    }
+    Ok(format!("{}{}", base_url.trim_end_matches('/'), path))
+}
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// sets record or replay and the timeout for the whole process, before the first request
+pub fn init(mode: Mode, timeout: Duration) {
+    if CLIENT.set(Client::new(mode).with_timeout(timeout)).is_err() {
+        tracing::warn!("http: already initialized, --record, --replay and --timeout ignored");
+    }
+}
+
+/// the process wide client, live unless `init` was called
+pub fn client() -> &'static Client {
+    CLIENT.get_or_init(|| Client::new(Mode::Live))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("iloveair_http_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn mock_server(responses: Vec<(u16, &'static str)>) -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let request = server.recv().unwrap();
+                request
+                    .respond(tiny_http::Response::from_string(body).with_status_code(status))
+                    .unwrap();
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    #[test]
+    fn test_record_then_replay() {
+        let dir = temp_dir("record");
+        let base = mock_server(vec![(200, r#"{"dt": 1}"#), (200, r#"{"dt": 2}"#)]);
+        let url = format!("{}/data/2.5/weather?q=Boston&appid=secret", base);
+        let recorder = Client::new(Mode::Record(dir.clone()));
+        let first = recorder.send(&Request::get(&url)).unwrap();
+        assert_eq!(first.json::<Value>().unwrap()["dt"], 1);
+        recorder
+            .send(
+                &Request::post(&url)
+                    .json(&json!({"token": "secret", "message": "hi"}))
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].starts_with("0001-GET-127.0.0.1-"));
+        assert!(files[0].ends_with("-data-2.5-weather.json"));
+        let recorded = fs::read_to_string(dir.join(&files[1])).unwrap();
+        assert!(!recorded.contains("secret"));
+        assert!(recorded.contains(r#""message": "hi""#));
+
+        // replays with a different key and without the server
+        let replayer = Client::new(Mode::Replay(dir.clone()));
+        let other_key = format!("{}/data/2.5/weather?q=Boston&appid=other", base);
+        let replayed = replayer.send(&Request::get(&other_key)).unwrap();
+        assert_eq!(replayed.status, 200);
+        assert_eq!(replayed.json::<Value>().unwrap()["dt"], 1);
+        let posted = replayer.send(&Request::post(&other_key)).unwrap();
+        assert_eq!(posted.json::<Value>().unwrap()["dt"], 2);
+        // the last match is served again
+        let again = replayer.send(&Request::get(&other_key)).unwrap();
+        assert_eq!(again.json::<Value>().unwrap()["dt"], 1);
+        assert!(replayer
+            .send(&Request::get(&format!("{}/other", base)))
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_error_for_status() {
+        let response = |status| Response {
+            url: "https://ext-api.airthings.com/v1/devices?token=secret".into(),
+            status,
+            body: "{}".into(),
+        };
+        assert!(response(200).error_for_status().is_ok());
+        let e = response(401).error_for_status().unwrap_err();
+        assert_eq!(crate::cli::failure_class(&e), Some(Failure::Auth));
+        assert!(!format!("{:#}", e).contains("secret"));
+        let e = response(429).error_for_status().unwrap_err();
+        assert_eq!(crate::cli::failure_class(&e), Some(Failure::Network));
+    }
+
+    #[test]
+    fn test_endpoint() {
+        assert_eq!(
+            endpoint("https://api.pushover.net/", "/1/messages.json", false).unwrap(),
+            "https://api.pushover.net/1/messages.json"
+        );
+        let e = endpoint("http://api.openweathermap.org", "/data/2.5/weather", false).unwrap_err();
+        assert_eq!(crate::cli::failure_class(&e), Some(Failure::Config));
+        assert_eq!(
+            endpoint("http://127.0.0.1:8080", "/data/2.5/weather", true).unwrap(),
+            "http://127.0.0.1:8080/data/2.5/weather"
+        );
+        assert!(endpoint("ftp://example.com", "/", true).is_err());
+        assert!(endpoint("api.openweathermap.org", "/", false).is_err());
+    }
+
+    #[test]
+    fn test_file_slug() {
+        assert_eq!(
+            file_slug(
+                "GET",
+                "https://api.openweathermap.org/data/2.5/weather?q=Boston"
+            ),
+            "GET-api.openweathermap.org-data-2.5-weather"
+        );
+    }
+}
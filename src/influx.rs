@@ -0,0 +1,444 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{stdout, Write};
+use std::time::Duration;
+
+use crate::airthings_radon::celsius_to_fahrenheit;
+use crate::audit::read_to_string_with_shellexpand;
+use crate::cli::Failure;
+use crate::http::{self, Request};
+use tracing::{info, warn};
+
+// InfluxDB line protocol for InfluxDB 1.x/2.x and VictoriaMetrics, one point per reading with
+// the time the reading was taken (Airthings `time`, OpenWeatherMap `dt`) in seconds:
+//
+//   indoor,device=2930 temperature_f=68.2,humidity=50,radon_bq_m3=40 1694880000
+//   outdoor,location=Boston temperature_f=61.5,humidity=80,wind_speed_mph=5.8 1694880000
+//   pollution aqi=2,pm2_5=3.5 1694880000
+//
+// When the HTTP endpoint is down points are appended to a spool file and sent ahead of the next
+// points, so a restart of the database does not leave a gap.
+
+#[derive(Serialize, Deserialize)]
+pub struct InfluxConfig {
+    // "-" for stdout, a file to append to, or a write URL like
+    // http://localhost:8086/api/v2/write?org=home&bucket=iloveair&precision=s
+    pub target: String,
+    // sent as `Authorization: Token <token>`
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "default_spool")]
+    pub spool: String,
+    #[serde(default = "default_max_spool_points")]
+    pub max_spool_points: usize,
+    // added to every point that doesn't already have the tag, e.g. {"location": "Boston"}
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+fn default_batch_size() -> usize {
+    5000
+}
+
+fn default_spool() -> String {
+    "~/.cache/iloveair/influx_spool.lp".into()
+}
+
+fn default_max_spool_points() -> usize {
+    100_000
+}
+
+pub fn read_influx_config(influx_config_path: &str) -> Result<InfluxConfig> {
+    let contents = read_to_string_with_shellexpand(&influx_config_path.to_string())?;
+    let config: InfluxConfig = serde_json::from_str(&contents)
+        .with_context(|| format!("read_influx_config: could not parse {}", influx_config_path))?;
+    Ok(config)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Point {
+    pub measurement: String,
+    pub tags: Vec<(String, String)>,
+    pub fields: Vec<(String, f64)>,
+    // unix seconds
+    pub time: i64,
+}
+
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Point {
+    fn new(measurement: &str, time: i64) -> Point {
+        Point {
+            measurement: measurement.into(),
+            tags: vec![],
+            fields: vec![],
+            time,
+        }
+    }
+
+    fn tag(&mut self, key: &str, value: Option<&str>) {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            self.tags.push((key.into(), value.into()));
+        }
+    }
+
+    fn field(&mut self, key: &str, value: Option<f64>) {
+        if let Some(value) = value.filter(|v| v.is_finite()) {
+            self.fields.push((key.into(), value));
+        }
+    }
+
+    /// one line of line protocol with second precision, tags sorted as Influx recommends
+    pub fn to_line(&self) -> String {
+        let mut line = escape(&self.measurement, &[',', ' ']);
+        let mut tags = self.tags.clone();
+        tags.sort();
+        for (key, value) in &tags {
+            line.push(',');
+            line.push_str(&escape(key, &[',', '=', ' ']));
+            line.push('=');
+            line.push_str(&escape(value, &[',', '=', ' ']));
+        }
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", escape(key, &[',', '=', ' ']), value))
+            .collect();
+        format!("{} {} {}", line, fields.join(","), self.time)
+    }
+}
+
+/// an Airthings latest-samples reading (°C), as written by read_waveplus
+pub fn indoor_point(device: &str, indoor_json: &Value) -> Option<Point> {
+    let mut point = Point::new("indoor", indoor_json["time"].as_i64()?);
+    point.tag("device", Some(device));
+    point.field(
+        "temperature_f",
+        indoor_json["temp"].as_f64().map(celsius_to_fahrenheit),
+    );
+    point.field("humidity", indoor_json["humidity"].as_f64());
+    point.field("radon_bq_m3", indoor_json["radonShortTermAvg"].as_f64());
+    point.field("co2_ppm", indoor_json["co2"].as_f64());
+    point.field("voc_ppb", indoor_json["voc"].as_f64());
+    point.field("pressure_hpa", indoor_json["pressure"].as_f64());
+    point.field("battery", indoor_json["battery"].as_f64());
+    (!point.fields.is_empty()).then_some(point)
+}
+
+/// an OWM current weather response (imperial units), as written by getweather
+pub fn weather_point(weather_json: &Value) -> Option<Point> {
+    let mut point = Point::new("outdoor", weather_json["dt"].as_i64()?);
+    point.tag("location", weather_json["name"].as_str());
+    let main = &weather_json["main"];
+    point.field("temperature_f", main["temp"].as_f64());
+    point.field("feels_like_f", main["feels_like"].as_f64());
+    point.field("humidity", main["humidity"].as_f64());
+    point.field("pressure_hpa", main["pressure"].as_f64());
+    point.field("wind_speed_mph", weather_json["wind"]["speed"].as_f64());
+    point.field("wind_gust_mph", weather_json["wind"]["gust"].as_f64());
+    point.field("clouds_percent", weather_json["clouds"]["all"].as_f64());
+    point.field("rain_1h_mm", weather_json["rain"]["1h"].as_f64());
+    point.field("snow_1h_mm", weather_json["snow"]["1h"].as_f64());
+    point.field("visibility_m", weather_json["visibility"].as_f64());
+    (!point.fields.is_empty()).then_some(point)
+}
+
+/// an OWM air pollution response, one point per entry so history backfills work too
+pub fn pollution_points(pollution_json: &Value) -> Vec<Point> {
+    let Some(list) = pollution_json["list"].as_array() else {
+        return vec![];
+    };
+    list.iter()
+        .filter_map(|entry| {
+            let mut point = Point::new("pollution", entry["dt"].as_i64()?);
+            point.field("aqi", entry["main"]["aqi"].as_f64());
+            if let Some(components) = entry["components"].as_object() {
+                for (component, value) in components {
+                    point.field(component, value.as_f64());
+                }
+            }
+            (!point.fields.is_empty()).then_some(point)
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Written {
+    // appended to a file or stdout
+    Wrote(usize),
+    // accepted by the HTTP endpoint, including points from the spool
+    Sent(usize),
+    // the endpoint is down, everything not sent is in the spool
+    Spooled { points: usize, error: String },
+}
+
+enum Post {
+    Sent,
+    Rejected(String),
+    Unavailable(String),
+}
+
+fn post(config: &InfluxConfig, lines: &[String]) -> Post {
+    let mut request = Request::post(&config.target)
+        .body(lines.join("\n"))
+        .timeout(Duration::from_secs(10));
+    if let Some(token) = &config.token {
+        request = request.header("Authorization", &format!("Token {}", token));
+    }
+    match http::client().send(&request) {
+        // 400 means the points themselves are bad, retrying them would block the spool forever
+        Ok(response) if response.status == 400 => Post::Rejected(response.body),
+        Ok(response) if response.is_success() => Post::Sent,
+        Ok(response) => Post::Unavailable(format!("{} {}", response.status, response.body)),
+        Err(e) => Post::Unavailable(format!("{:#}", e)),
+    }
+}
+
+fn read_spool(spool_path: &str) -> Result<Vec<String>> {
+    if !std::path::Path::new(spool_path).exists() {
+        return Ok(vec![]);
+    }
+    let contents = fs::read_to_string(spool_path)
+        .with_context(|| format!("read_spool: could not read {}", spool_path))?;
+    Ok(contents.lines().map(String::from).collect())
+}
+
+/// replaces the spool with `lines`, keeping the newest `max_points`, removes it when empty
+fn write_spool(spool_path: &str, lines: &[String], max_points: usize) -> Result<()> {
+    if lines.is_empty() {
+        if std::path::Path::new(spool_path).exists() {
+            fs::remove_file(spool_path)
+                .with_context(|| format!("write_spool: could not remove {}", spool_path))?;
+        }
+        return Ok(());
+    }
+    if let Some(parent) = std::path::Path::new(spool_path).parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("write_spool: could not create {:?}", parent))?;
+    }
+    let lines = &lines[lines.len().saturating_sub(max_points)..];
+    let tmp_path = format!("{}.tmp", spool_path);
+    fs::write(&tmp_path, lines.join("\n") + "\n")
+        .with_context(|| format!("write_spool: could not write {}", tmp_path))?;
+    fs::rename(&tmp_path, spool_path)
+        .with_context(|| format!("write_spool: could not rename to {}", spool_path))?;
+    Ok(())
+}
+
+fn send(config: &InfluxConfig, lines: Vec<String>) -> Result<Written> {
+    let spool_path = shellexpand::tilde(&config.spool).into_owned();
+    let mut pending = read_spool(&spool_path)?;
+    pending.extend(lines);
+    let mut sent = 0;
+    for batch in pending.chunks(config.batch_size.max(1)) {
+        match post(config, batch) {
+            Post::Sent => sent += batch.len(),
+            Post::Rejected(error) => {
+                write_spool(
+                    &spool_path,
+                    &pending[sent + batch.len()..],
+                    config.max_spool_points,
+                )?;
+                bail!(
+                    "influx: {} rejected {} points {}",
+                    config.target,
+                    batch.len(),
+                    error
+                );
+            }
+            Post::Unavailable(error) => {
+                write_spool(&spool_path, &pending[sent..], config.max_spool_points)?;
+                return Ok(Written::Spooled {
+                    points: (pending.len() - sent).min(config.max_spool_points),
+                    error,
+                });
+            }
+        }
+    }
+    write_spool(&spool_path, &[], config.max_spool_points)?;
+    Ok(Written::Sent(sent))
+}
+
+pub fn write(config: &InfluxConfig, points: &[Point]) -> Result<Written> {
+    let lines: Vec<String> = points
+        .iter()
+        .map(|point| {
+            let mut point = point.clone();
+            for (key, value) in &config.tags {
+                if !point.tags.iter().any(|(k, _)| k == key) {
+                    point.tags.push((key.clone(), value.clone()));
+                }
+            }
+            point.to_line()
+        })
+        .collect();
+    if config.target.starts_with("http://") || config.target.starts_with("https://") {
+        return send(config, lines);
+    }
+    let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    if config.target == "-" {
+        stdout()
+            .write_all(text.as_bytes())
+            .context("influx: could not write to stdout")?;
+    } else {
+        let path = shellexpand::tilde(&config.target).into_owned();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut fout| fout.write_all(text.as_bytes()))
+            .with_context(|| format!("influx: could not append to {}", path))?;
+    }
+    Ok(Written::Wrote(lines.len()))
+}
+
+/// reads the config and writes the points, a down endpoint is reported but not an error
+pub fn write_points(influx_config_path: &str, points: &[Point]) -> Result<()> {
+    let config = read_influx_config(influx_config_path).context(Failure::Config)?;
+    match write(&config, points)? {
+        Written::Wrote(count) if config.target != "-" => {
+            info!("influx: wrote {} points to {}", count, config.target)
+        }
+        Written::Wrote(_) => (),
+        Written::Sent(count) => info!("influx: sent {} points", count),
+        Written::Spooled { points, error } => warn!(
+            "influx: {} is down, {} points spooled to {} {}",
+            config.target, points, config.spool, error
+        ),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_points() {
+        let indoor = indoor_point(
+            "2930",
+            &json!({"temp": 20.0, "humidity": 50.0, "radonShortTermAvg": 40.0, "time": 1694880000, "battery": 99}),
+        )
+        .unwrap();
+        assert_eq!(
+            indoor.to_line(),
+            "indoor,device=2930 temperature_f=68,humidity=50,radon_bq_m3=40,battery=99 1694880000"
+        );
+        let outdoor = weather_point(&json!({
+            "dt": 1694880000, "name": "New York, NY",
+            "main": {"temp": 61.5, "humidity": 80}, "wind": {"speed": 5.75}
+        }))
+        .unwrap();
+        assert_eq!(
+            outdoor.to_line(),
+            "outdoor,location=New\\ York\\,\\ NY temperature_f=61.5,humidity=80,wind_speed_mph=5.75 1694880000"
+        );
+        let pollution = pollution_points(&json!({"list": [
+            {"dt": 1694880000, "main": {"aqi": 2}, "components": {"pm2_5": 3.5}},
+            {"dt": 1694883600, "main": {"aqi": 1}}
+        ]}));
+        assert_eq!(pollution.len(), 2);
+        assert_eq!(
+            pollution[0].to_line(),
+            "pollution aqi=2,pm2_5=3.5 1694880000"
+        );
+        // no timestamp, no point
+        assert_eq!(weather_point(&json!({"main": {"temp": 61.5}})), None);
+    }
+
+    fn config(target: &str, spool: &str) -> InfluxConfig {
+        serde_json::from_value(json!({"target": target, "spool": spool, "batch_size": 2})).unwrap()
+    }
+
+    fn points(n: i64) -> Vec<Point> {
+        (0..n)
+            .map(|i| {
+                let mut point = Point::new("indoor", 1694880000 + i);
+                point.field("humidity", Some(50.0));
+                point
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write_file_with_default_tags() {
+        let dir = std::env::temp_dir().join(format!("iloveair_influx_file_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("out.lp");
+        let mut config = config(out.to_str().unwrap(), "");
+        config.tags.insert("location".into(), "Boston".into());
+        assert_eq!(write(&config, &points(1)).unwrap(), Written::Wrote(1));
+        assert_eq!(write(&config, &points(1)).unwrap(), Written::Wrote(1));
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "indoor,location=Boston humidity=50 1694880000\n".repeat(2)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_spool_while_endpoint_down() {
+        let dir = std::env::temp_dir().join(format!("iloveair_influx_http_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let spool = dir.join("spool.lp");
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/write?precision=s", server.server_addr());
+        let config = config(&url, spool.to_str().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            let mut bodies = vec![];
+            for (i, mut request) in server.incoming_requests().take(4).enumerate() {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                bodies.push(body);
+                // the second batch of the first write fails
+                let status = if i == 1 { 503 } else { 204 };
+                request.respond(tiny_http::Response::empty(status)).unwrap();
+            }
+            bodies
+        });
+
+        let written = write(&config, &points(3)).unwrap();
+        assert!(matches!(written, Written::Spooled { points: 1, .. }));
+        assert_eq!(read_spool(spool.to_str().unwrap()).unwrap().len(), 1);
+        // the spooled point goes first, then the new ones in batches of 2
+        assert_eq!(write(&config, &points(2)).unwrap(), Written::Sent(3));
+        assert!(!spool.exists());
+
+        let bodies = handle.join().unwrap();
+        assert_eq!(bodies[0].lines().count(), 2);
+        assert_eq!(
+            bodies[2],
+            "indoor humidity=50 1694880002\nindoor humidity=50 1694880000"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_spool_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("iloveair_influx_max_{}", std::process::id()));
+        let spool = dir.join("spool.lp");
+        let spool = spool.to_str().unwrap();
+        let lines: Vec<String> = (0..5).map(|i| i.to_string()).collect();
+        write_spool(spool, &lines, 3).unwrap();
+        assert_eq!(read_spool(spool).unwrap(), vec!["2", "3", "4"]);
+        write_spool(spool, &[], 3).unwrap();
+        assert_eq!(read_spool(spool).unwrap(), Vec::<String>::new());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,32 @@
 pub mod airthings_radon;
 pub mod audit;
+pub mod backfill;
+pub mod cache;
+pub mod cli;
 pub mod config;
+pub mod dashboard;
+pub mod decision;
+pub mod energy;
+pub mod export;
+pub mod history;
+pub mod http;
+pub mod influx;
+pub mod metrics;
+pub mod mqtt;
 pub mod notify;
+pub mod pollution;
 pub mod pretty;
+pub mod provider;
+pub mod psychro;
+pub mod radon;
+pub mod rain;
+pub mod report;
+pub mod rules;
+pub mod schedule;
+pub mod scrape;
+pub mod server;
+pub mod staleness;
+pub mod template;
+pub mod ventilation;
+pub mod vetoes;
 pub mod weather;
@@ -0,0 +1,286 @@
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+
+use crate::history::History;
+use crate::weather::weather_conditions;
+
+// Prometheus text exposition of the latest readings in the history database, served at
+// /metrics by `iloveair serve` or written for the node exporter's textfile collector with
+// `iloveair metrics --textfile`.
+
+// history metric, prometheus name, help
+static INDOOR: &[(&str, &str, &str)] = &[
+    (
+        "indoor.temp",
+        "iloveair_indoor_temperature_fahrenheit",
+        "Indoor temperature",
+    ),
+    (
+        "indoor.humidity",
+        "iloveair_indoor_humidity_percent",
+        "Indoor relative humidity",
+    ),
+    (
+        "indoor.radon",
+        "iloveair_indoor_radon_becquerels_per_cubic_meter",
+        "Indoor radon short term average",
+    ),
+    ("indoor.co2", "iloveair_indoor_co2_ppm", "Indoor CO2"),
+    ("indoor.voc", "iloveair_indoor_voc_ppb", "Indoor VOC"),
+    (
+        "indoor.pressure",
+        "iloveair_indoor_pressure_hectopascals",
+        "Indoor air pressure",
+    ),
+    (
+        "indoor.battery",
+        "iloveair_indoor_battery_percent",
+        "Sensor battery level",
+    ),
+];
+
+static OUTDOOR: &[(&str, &str, &str)] = &[
+    (
+        "outdoor.temp",
+        "iloveair_outdoor_temperature_fahrenheit",
+        "Outdoor temperature",
+    ),
+    (
+        "outdoor.humidity",
+        "iloveair_outdoor_humidity_percent",
+        "Outdoor relative humidity",
+    ),
+];
+
+/// writes HELP and TYPE once, then one sample per labelled value
+struct Exposition {
+    text: String,
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str, samples: &[(Vec<(&str, &str)>, f64)]) {
+        if samples.is_empty() {
+            return;
+        }
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                .collect();
+            if labels.is_empty() {
+                let _ = writeln!(self.text, "{} {}", name, value);
+            } else {
+                let _ = writeln!(self.text, "{}{{{}}} {}", name, labels.join(","), value);
+            }
+        }
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, samples: &[(Vec<(&str, &str)>, f64)]) {
+        self.family(name, "gauge", help, samples);
+    }
+}
+
+fn flag(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+pub fn render(history: &History) -> Result<String> {
+    let mut out = Exposition {
+        text: String::new(),
+    };
+    for (metrics, label) in [(INDOOR, "device"), (OUTDOOR, "location")] {
+        for (metric, name, help) in metrics {
+            let latest = history.latest_per_device(metric)?;
+            let samples: Vec<(Vec<(&str, &str)>, f64)> = latest
+                .iter()
+                .map(|s| (vec![(label, s.device.as_deref().unwrap_or(""))], s.value))
+                .collect();
+            out.gauge(name, help, &samples);
+        }
+    }
+    let latest = history.latest_per_device("indoor.temp")?;
+    let samples: Vec<(Vec<(&str, &str)>, f64)> = latest
+        .iter()
+        .map(|s| {
+            (
+                vec![("device", s.device.as_deref().unwrap_or(""))],
+                s.time as f64,
+            )
+        })
+        .collect();
+    out.gauge(
+        "iloveair_indoor_sample_timestamp_seconds",
+        "Time of the latest indoor sample",
+        &samples,
+    );
+
+    if let Some(weather_json) = history.latest_json("weather")? {
+        let conditions = weather_conditions(&weather_json);
+        let location = weather_json["name"].as_str().unwrap_or("");
+        for (name, help, value) in [
+            (
+                "iloveair_outdoor_wind_speed_mph",
+                "Outdoor wind speed",
+                conditions.wind_speed,
+            ),
+            (
+                "iloveair_outdoor_wind_gust_mph",
+                "Outdoor wind gust",
+                conditions.wind_gust,
+            ),
+            (
+                "iloveair_outdoor_rain_1h_millimeters",
+                "Rain in the last hour",
+                conditions.rain_1h,
+            ),
+            (
+                "iloveair_outdoor_sample_timestamp_seconds",
+                "Time of the latest weather report",
+                weather_json["dt"].as_f64().unwrap_or(0.0),
+            ),
+        ] {
+            out.gauge(name, help, &[(vec![("location", location)], value)]);
+        }
+    }
+
+    if let Some(pollution_json) = history.latest_json("pollution")? {
+        let latest = &pollution_json["list"][0];
+        if let Some(aqi) = latest["main"]["aqi"].as_f64() {
+            out.gauge(
+                "iloveair_pollution_aqi",
+                "OpenWeatherMap air quality index, 1 good to 5 very poor",
+                &[(vec![], aqi)],
+            );
+        }
+        if let Some(components) = latest["components"].as_object() {
+            let samples: Vec<(Vec<(&str, &str)>, f64)> = components
+                .iter()
+                .filter_map(|(component, v)| {
+                    v.as_f64()
+                        .map(|v| (vec![("component", component.as_str())], v))
+                })
+                .collect();
+            out.gauge(
+                "iloveair_pollution_concentration_micrograms_per_cubic_meter",
+                "Air pollutant concentration",
+                &samples,
+            );
+        }
+    }
+
+    if let Some(decision) = history.latest_decision()? {
+        out.gauge(
+            "iloveair_window_should_be_open",
+            "1 when the windows should be open",
+            &[(vec![], flag(decision.window_should_be_open))],
+        );
+        out.gauge(
+            "iloveair_decision_unknown",
+            "1 when an input was too old to decide from",
+            &[(vec![], flag(decision.is_unknown()))],
+        );
+        let rules: Vec<(Vec<(&str, &str)>, f64)> = decision
+            .rules
+            .iter()
+            .chain(&decision.vetoes)
+            .map(|r| (vec![("rule", r.name.as_str())], flag(r.passed)))
+            .collect();
+        out.gauge(
+            "iloveair_rule_passed",
+            "1 when the rule or veto allows opening the windows",
+            &rules,
+        );
+    }
+
+    let stats = history.fetch_stats()?;
+    let by_source = |f: &dyn Fn(&crate::history::FetchStats) -> Option<f64>| {
+        stats
+            .iter()
+            .filter_map(|s| f(s).map(|v| (vec![("source", s.source.as_str())], v)))
+            .collect::<Vec<_>>()
+    };
+    out.family(
+        "iloveair_fetch_success_total",
+        "counter",
+        "Successful calls to Airthings, OpenWeatherMap and Pushover",
+        &by_source(&|s| Some(s.successes as f64)),
+    );
+    out.family(
+        "iloveair_fetch_failure_total",
+        "counter",
+        "Failed calls to Airthings, OpenWeatherMap and Pushover",
+        &by_source(&|s| Some(s.failures as f64)),
+    );
+    out.gauge(
+        "iloveair_fetch_last_success_timestamp_seconds",
+        "Time of the last successful call",
+        &by_source(&|s| s.last_success.map(|t| t as f64)),
+    );
+    Ok(out.text)
+}
+
+/// for the node exporter textfile collector, written to a temp file and renamed so it never
+/// scrapes a partial file
+pub fn write_textfile(history: &History, textfile_path: &str) -> Result<()> {
+    let path = shellexpand::tilde(textfile_path).into_owned();
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, render(history)?)
+        .with_context(|| format!("write_textfile: could not write {}", tmp_path))?;
+    fs::rename(&tmp_path, &path)
+        .with_context(|| format!("write_textfile: could not rename to {}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        let history = History::open_in_memory().unwrap();
+        history
+            .record_indoor(
+                "2930",
+                &json!({"temp": 20.0, "humidity": 50.0, "radonShortTermAvg": 40.0, "time": 1694880000, "battery": 99}),
+            )
+            .unwrap();
+        history
+            .record_pollution(&json!({"list": [{"dt": 1694880000, "main": {"aqi": 2}, "components": {"pm2_5": 3.5}}]}))
+            .unwrap();
+        history.record_fetch("airthings", None).unwrap();
+        history.record_fetch("pushover", Some("timed out")).unwrap();
+
+        let text = render(&history).unwrap();
+        assert!(text.contains("# TYPE iloveair_indoor_temperature_fahrenheit gauge\n"));
+        assert!(text.contains("iloveair_indoor_temperature_fahrenheit{device=\"2930\"} 68\n"));
+        assert!(text.contains("iloveair_indoor_battery_percent{device=\"2930\"} 99\n"));
+        assert!(text.contains("iloveair_pollution_aqi 2\n"));
+        assert!(text.contains(
+            "iloveair_pollution_concentration_micrograms_per_cubic_meter{component=\"pm2_5\"} 3.5\n"
+        ));
+        assert!(text.contains("iloveair_fetch_failure_total{source=\"pushover\"} 1\n"));
+        assert!(text.contains("iloveair_fetch_success_total{source=\"airthings\"} 1\n"));
+        // no CO2 sensor, no family
+        assert!(!text.contains("iloveair_indoor_co2_ppm"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
@@ -0,0 +1,515 @@
+use anyhow::{anyhow, bail, Context, Result};
+use rumqttc::{
+    Client, ConnectReturnCode, Connection, ConnectionError, Event, LastWill, MqttOptions, Packet,
+    QoS,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::audit::read_to_string_with_shellexpand;
+use crate::cli::Failure;
+use crate::history::History;
+
+// `iloveair mqtt`: publishes the latest readings and the window recommendation from the
+// history database to an MQTT broker, with Home Assistant discovery so the sensors show up
+// without any YAML.
+//
+//   <base_topic>/availability            online, offline (last will)
+//   <base_topic>/<sensor>                e.g. iloveair/indoor_temperature 68.2
+//   <base_topic>/window_should_be_open   ON, OFF or None (unknown) when an input is stale
+//   <base_topic>/decision                the decision JSON, attributes of the binary sensor
+//   <discovery_prefix>/<component>/<node_id>/<sensor>/config
+//
+// Everything is retained so Home Assistant has the state as soon as it subscribes.
+
+#[derive(Serialize, Deserialize)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default = "default_base_topic")]
+    pub base_topic: String,
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+    // seconds until Home Assistant marks a sensor unavailable, for cron runs without --daemon
+    #[serde(default)]
+    pub expire_after: Option<u64>,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "iloveair".into()
+}
+
+fn default_base_topic() -> String {
+    "iloveair".into()
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".into()
+}
+
+pub fn read_mqtt_config(mqtt_config_path: &str) -> Result<MqttConfig> {
+    let contents = read_to_string_with_shellexpand(&mqtt_config_path.to_string())?;
+    let config: MqttConfig = serde_json::from_str(&contents)
+        .with_context(|| format!("read_mqtt_config: could not parse {}", mqtt_config_path))?;
+    Ok(config)
+}
+
+// history metric, object id, name, unit, device class
+type Sensor = (
+    &'static str,
+    &'static str,
+    &'static str,
+    Option<&'static str>,
+    Option<&'static str>,
+);
+
+static SENSORS: &[Sensor] = &[
+    (
+        "indoor.temp",
+        "indoor_temperature",
+        "Indoor temperature",
+        Some("°F"),
+        Some("temperature"),
+    ),
+    (
+        "indoor.humidity",
+        "indoor_humidity",
+        "Indoor humidity",
+        Some("%"),
+        Some("humidity"),
+    ),
+    ("indoor.radon", "radon", "Radon", Some("Bq/m³"), None),
+    (
+        "indoor.co2",
+        "co2",
+        "CO2",
+        Some("ppm"),
+        Some("carbon_dioxide"),
+    ),
+    (
+        "indoor.voc",
+        "voc",
+        "VOC",
+        Some("ppb"),
+        Some("volatile_organic_compounds_parts"),
+    ),
+    (
+        "indoor.pressure",
+        "pressure",
+        "Pressure",
+        Some("hPa"),
+        Some("atmospheric_pressure"),
+    ),
+    (
+        "indoor.battery",
+        "battery",
+        "Sensor battery",
+        Some("%"),
+        Some("battery"),
+    ),
+    (
+        "outdoor.temp",
+        "outdoor_temperature",
+        "Outdoor temperature",
+        Some("°F"),
+        Some("temperature"),
+    ),
+    (
+        "outdoor.humidity",
+        "outdoor_humidity",
+        "Outdoor humidity",
+        Some("%"),
+        Some("humidity"),
+    ),
+    ("aqi", "aqi", "Air quality index", None, Some("aqi")),
+];
+
+static WINDOW: &str = "window_should_be_open";
+
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+impl MqttConfig {
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.base_topic, name)
+    }
+
+    fn availability_topic(&self) -> String {
+        self.topic("availability")
+    }
+
+    fn node_id(&self) -> String {
+        self.base_topic.replace('/', "_")
+    }
+
+    fn discovery(&self, component: &str, object_id: &str, mut payload: Value) -> Message {
+        let node_id = self.node_id();
+        payload["unique_id"] = json!(format!("{}_{}", node_id, object_id));
+        payload["object_id"] = json!(format!("{}_{}", node_id, object_id));
+        payload["availability_topic"] = json!(self.availability_topic());
+        payload["device"] = json!({
+            "identifiers": [node_id],
+            "name": "iloveair",
+            "manufacturer": "iloveair",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        if let Some(expire_after) = self.expire_after {
+            payload["expire_after"] = json!(expire_after);
+        }
+        Message {
+            topic: format!(
+                "{}/{}/{}/{}/config",
+                self.discovery_prefix, component, node_id, object_id
+            ),
+            payload: payload.to_string(),
+            retain: true,
+        }
+    }
+}
+
+/// discovery configs for the sensors that have readings, so a Wave Mini gets no CO2 sensor
+pub fn discovery_messages(config: &MqttConfig, history: &History) -> Result<Vec<Message>> {
+    let mut messages = vec![];
+    for (metric, object_id, name, unit, device_class) in SENSORS {
+        if history.latest(metric, None)?.is_none() {
+            continue;
+        }
+        let mut payload = json!({
+            "name": name,
+            "state_topic": config.topic(object_id),
+            "state_class": "measurement",
+        });
+        if let Some(unit) = unit {
+            payload["unit_of_measurement"] = json!(unit);
+        }
+        if let Some(device_class) = device_class {
+            payload["device_class"] = json!(device_class);
+        }
+        messages.push(config.discovery("sensor", object_id, payload));
+    }
+    if history.latest_decision()?.is_some() {
+        messages.push(config.discovery(
+            "binary_sensor",
+            WINDOW,
+            json!({
+                "name": "Windows should be open",
+                "state_topic": config.topic(WINDOW),
+                "json_attributes_topic": config.topic("decision"),
+                "device_class": "window",
+            }),
+        ));
+    }
+    Ok(messages)
+}
+
+pub fn state_messages(config: &MqttConfig, history: &History) -> Result<Vec<Message>> {
+    let mut messages = vec![];
+    for (metric, object_id, ..) in SENSORS {
+        if let Some(sample) = history.latest(metric, None)? {
+            messages.push(Message {
+                topic: config.topic(object_id),
+                payload: sample.value.to_string(),
+                retain: true,
+            });
+        }
+    }
+    if let Some(decision) = history.latest_decision()? {
+        messages.push(Message {
+            topic: config.topic(WINDOW),
+            // Home Assistant shows "None" as unknown
+            payload: if decision.is_unknown() {
+                "None"
+            } else if decision.window_should_be_open {
+                "ON"
+            } else {
+                "OFF"
+            }
+            .into(),
+            retain: true,
+        });
+        messages.push(Message {
+            topic: config.topic("decision"),
+            payload: serde_json::to_string(&decision)?,
+            retain: true,
+        });
+    }
+    Ok(messages)
+}
+
+fn online(config: &MqttConfig) -> Message {
+    Message {
+        topic: config.availability_topic(),
+        payload: "online".into(),
+        retain: true,
+    }
+}
+
+fn options(config: &MqttConfig) -> MqttOptions {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        config.availability_topic(),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    options
+}
+
+fn send(client: &Client, messages: &[Message]) -> Result<()> {
+    for message in messages {
+        client
+            .publish(
+                &message.topic,
+                QoS::AtLeastOnce,
+                message.retain,
+                message.payload.as_bytes(),
+            )
+            .with_context(|| format!("mqtt: could not publish {}", message.topic))?;
+    }
+    Ok(())
+}
+
+/// publishes availability, discovery and state once, waits for the broker to acknowledge
+/// every message, then disconnects cleanly so the last will is not sent
+pub fn publish_once(config: &MqttConfig, history: &History, timeout: Duration) -> Result<()> {
+    let mut messages = vec![online(config)];
+    messages.extend(discovery_messages(config, history)?);
+    messages.extend(state_messages(config, history)?);
+
+    let (client, mut connection) = Client::new(options(config), messages.len() + 1);
+    send(&client, &messages)?;
+    let deadline = Instant::now() + timeout;
+    let mut unacknowledged = messages.len();
+    while unacknowledged > 0 {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match connection.recv_timeout(remaining) {
+            Ok(Ok(Event::Incoming(Packet::PubAck(_)))) => unacknowledged -= 1,
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => {
+                let failure = match e {
+                    ConnectionError::ConnectionRefused(
+                        ConnectReturnCode::BadUserNamePassword | ConnectReturnCode::NotAuthorized,
+                    ) => Failure::Auth,
+                    _ => Failure::Network,
+                };
+                return Err(anyhow!(
+                    "publish_once: could not connect to {}:{} {}",
+                    config.host,
+                    config.port,
+                    e
+                )
+                .context(failure));
+            }
+            Err(_) => {
+                return Err(anyhow!(
+                    "publish_once: timed out with {} of {} messages unacknowledged",
+                    unacknowledged,
+                    messages.len()
+                )
+                .context(Failure::Network))
+            }
+        }
+    }
+    client
+        .disconnect()
+        .map_err(|e| anyhow!("publish_once: could not disconnect {}", e))?;
+    // drive the connection until the disconnect has been written
+    while let Ok(Ok(event)) = connection.recv_timeout(Duration::from_secs(1)) {
+        if let Event::Outgoing(rumqttc::Outgoing::Disconnect) = event {
+            break;
+        }
+    }
+    Ok(())
+}
+
+enum Wake {
+    // connected or reconnected, availability needs to be set again after the last will
+    Connected,
+    // Home Assistant restarted and needs the discovery configs again
+    HomeAssistantOnline,
+}
+
+/// polls the connection forever, rumqttc reconnects on the next poll after an error
+fn drive(mut connection: Connection, ha_status_topic: String, wake: mpsc::Sender<Wake>) {
+    for event in connection.iter() {
+        let wake_up = match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => Some(Wake::Connected),
+            Ok(Event::Incoming(Packet::Publish(publish)))
+                if publish.topic == ha_status_topic && &publish.payload[..] == b"online" =>
+            {
+                Some(Wake::HomeAssistantOnline)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("mqtt: connection error, reconnecting in 5s {}", e);
+                thread::sleep(Duration::from_secs(5));
+                None
+            }
+        };
+        if let Some(wake_up) = wake_up {
+            if wake.send(wake_up).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// stays connected and republishes the state every `interval`, the broker publishes the last
+/// will if the process dies
+pub fn publish_daemon(
+    config: &MqttConfig,
+    history_db_path: &str,
+    interval: Duration,
+) -> Result<()> {
+    let history = History::open(history_db_path)?;
+    let (client, connection) = Client::new(options(config), 64);
+    let ha_status_topic = format!("{}/status", config.discovery_prefix);
+    let (wake_tx, wake_rx) = mpsc::channel();
+    {
+        let ha_status_topic = ha_status_topic.clone();
+        thread::spawn(move || drive(connection, ha_status_topic, wake_tx));
+    }
+    let mut next_publish = Instant::now() + interval;
+    loop {
+        let timeout = next_publish.saturating_duration_since(Instant::now());
+        let messages = match wake_rx.recv_timeout(timeout) {
+            Ok(Wake::Connected) => {
+                info!("mqtt: connected to {}:{}", config.host, config.port);
+                client
+                    .subscribe(&ha_status_topic, QoS::AtLeastOnce)
+                    .with_context(|| format!("mqtt: could not subscribe {}", ha_status_topic))?;
+                let mut messages = vec![online(config)];
+                messages.extend(discovery_messages(config, &history)?);
+                messages.extend(state_messages(config, &history)?);
+                messages
+            }
+            Ok(Wake::HomeAssistantOnline) => {
+                let mut messages = discovery_messages(config, &history)?;
+                messages.extend(state_messages(config, &history)?);
+                messages
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                next_publish = Instant::now() + interval;
+                state_messages(config, &history)?
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => bail!("mqtt: connection thread exited"),
+        };
+        send(&client, &messages)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> MqttConfig {
+        serde_json::from_value(json!({"host": "localhost", "base_topic": "home/iloveair"})).unwrap()
+    }
+
+    fn history() -> History {
+        let history = History::open_in_memory().unwrap();
+        history
+            .record_indoor(
+                "2930",
+                &json!({"temp": 20.0, "humidity": 50.0, "radonShortTermAvg": 40.0, "time": 1694880000}),
+            )
+            .unwrap();
+        history
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config = config();
+        assert_eq!(config.port, 1883);
+        assert_eq!(config.discovery_prefix, "homeassistant");
+        assert_eq!(config.availability_topic(), "home/iloveair/availability");
+    }
+
+    #[test]
+    fn test_discovery_messages() {
+        let messages = discovery_messages(&config(), &history()).unwrap();
+        // temp, humidity and radon only, no decision yet
+        assert_eq!(messages.len(), 3);
+        let temp = &messages[0];
+        assert_eq!(
+            temp.topic,
+            "homeassistant/sensor/home_iloveair/indoor_temperature/config"
+        );
+        assert!(temp.retain);
+        let payload: Value = serde_json::from_str(&temp.payload).unwrap();
+        assert_eq!(payload["state_topic"], "home/iloveair/indoor_temperature");
+        assert_eq!(payload["unit_of_measurement"], "°F");
+        assert_eq!(payload["unique_id"], "home_iloveair_indoor_temperature");
+        assert_eq!(payload["availability_topic"], "home/iloveair/availability");
+        assert_eq!(payload.get("expire_after"), None);
+    }
+
+    #[test]
+    fn test_state_messages() {
+        let messages = state_messages(&config(), &history()).unwrap();
+        assert_eq!(
+            messages[0],
+            Message {
+                topic: "home/iloveair/indoor_temperature".into(),
+                payload: "68".into(),
+                retain: true,
+            }
+        );
+        assert_eq!(messages[2].payload, "40");
+    }
+
+    // needs a broker, e.g. `mosquitto -p 1883` and
+    // ILOVEAIR_MQTT_TEST_BROKER=localhost:1883 cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn test_publish_once_to_broker() {
+        let broker = std::env::var("ILOVEAIR_MQTT_TEST_BROKER").unwrap();
+        let (host, port) = broker.split_once(':').unwrap();
+        let mut config = config();
+        config.host = host.into();
+        config.port = port.parse().unwrap();
+        config.base_topic = format!("iloveair_test_{}", std::process::id());
+        publish_once(&config, &history(), Duration::from_secs(10)).unwrap();
+
+        let mut options = MqttOptions::new("iloveair_test_reader", host, config.port);
+        options.set_keep_alive(Duration::from_secs(5));
+        let (client, mut connection) = Client::new(options, 10);
+        client
+            .subscribe(config.topic("indoor_temperature"), QoS::AtLeastOnce)
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if let Event::Incoming(Packet::Publish(publish)) =
+                connection.recv_timeout(remaining).unwrap().unwrap()
+            {
+                assert!(publish.retain);
+                assert_eq!(&publish.payload[..], b"68");
+                break;
+            }
+        }
+    }
+}
@@ -1,14 +1,23 @@
 use crate::audit::read_to_string_with_shellexpand;
+use crate::http::{self, Request};
 use anyhow::anyhow;
 use anyhow::{Context, Result};
-use reqwest::blocking::Client;
 use serde::Deserialize;
 use serde_json::json;
+use tracing::{debug, info};
 
 #[derive(Debug, Deserialize)]
 pub struct PushoverConfig {
     api_key: String,
     user_key: String,
+    #[serde(default = "default_base_url")]
+    base_url: String,
+    #[serde(default)]
+    allow_http: bool,
+}
+
+fn default_base_url() -> String {
+    "https://api.pushover.net".into()
 }
 pub fn read_pushover_json(pushover_config_path: &String) -> Result<PushoverConfig> {
     let contents = read_to_string_with_shellexpand(pushover_config_path)
@@ -32,21 +41,20 @@ pub fn send_pushover_notification(dry_run: bool, config: &PushoverConfig, msg: &
         "message": msg,
     });
 
-    let pushover_url = "https://api.pushover.net/1/messages.json";
-    let client = Client::new();
-    let res = client
-        .post(pushover_url)
-        .json(&params)
-        .send()
+    let pushover_url = &http::endpoint(&config.base_url, "/1/messages.json", config.allow_http)?;
+    let res = http::client()
+        .send(&Request::post(pushover_url).json(&params)?)
         .with_context(|| {
             format!(
                 "send_pushover_notification: could not send notification to {}",
                 pushover_url
             )
         })?;
-    // print response and error code
-    println!("SENT: {}", pushover_url);
-    println!("pushover status: {}", res.status());
-    println!("pushover response: {}", res.text().unwrap());
+    // log response and error code
+    info!("SENT: {}", pushover_url);
+    debug!("pushover status: {}", res.status);
+    debug!("pushover response: {}", res.body);
+    res.error_for_status()
+        .context("send_pushover_notification")?;
     Ok(())
 }
@@ -0,0 +1,41 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::audit::read_to_string_with_path;
+use crate::cache::unwrap_payload;
+
+// https://openweathermap.org/api/air-pollution
+
+pub fn pollution_aqi(pollution_json: &serde_json::Value) -> Result<u64> {
+    pollution_json["list"][0]["main"]["aqi"]
+        .as_u64()
+        .ok_or_else(|| anyhow!("pollution_aqi: no list[0].main.aqi"))
+}
+pub fn pollution_time(pollution_json: &serde_json::Value) -> Option<i64> {
+    pollution_json["list"][0]["dt"].as_i64()
+}
+pub fn aqi_description(aqi: u64) -> &'static str {
+    match aqi {
+        1 => "Good",
+        2 => "Fair",
+        3 => "Moderate",
+        4 => "Poor",
+        5 => "Very Poor",
+        _ => "Unknown",
+    }
+}
+
+pub fn load_pollution_response<P: AsRef<Path>>(
+    pollution_json_path: P,
+) -> Result<serde_json::Value> {
+    let pollution_json = read_to_string_with_path(pollution_json_path.as_ref())?;
+    let pollution_json: serde_json::Value =
+        serde_json::from_str(&pollution_json).with_context(|| {
+            format!(
+                "load_pollution_response: could not load {:?}",
+                pollution_json_path.as_ref()
+            )
+        })?;
+    Ok(unwrap_payload(pollution_json))
+}
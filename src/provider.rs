@@ -0,0 +1,738 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use scraper::Html;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::cli::Failure;
+use crate::config::{NwsConfig, StationConfig, WeatherConfig};
+use crate::history::History;
+use crate::http::{self, Client, Request};
+use crate::scrape::{first_number, TextSelector};
+use crate::weather::Observation;
+
+// Outdoor readings for getweather from OpenWeatherMap, the US National Weather Service,
+// Open-Meteo or a nearby station's web page, tried in the order "providers" lists them in
+// openweathermap.json.
+//
+// The cache, history, Influx points, metrics and weather_notify all read OWM current weather
+// documents, so each provider turns its response into one (°F, mm, mph, OWM condition ids) and
+// the typed Observation is read back from that. Each attempt is counted in fetches under the
+// provider's name.
+//
+// A station only has temperature and humidity. Its reading is laid over the one from the next
+// provider that answers, which keeps the rain, wind and conditions the vetoes need, so a station
+// can't be the last provider listed.
+
+pub trait WeatherProvider {
+    /// source in fetches
+    fn name(&self) -> &'static str;
+
+    fn request(&self) -> Result<Request>;
+
+    /// the provider's response body as an OWM current weather document
+    fn weather_json(&self, body: &str) -> Result<Value>;
+
+    /// only temperature and humidity, laid over the next provider's reading
+    fn is_overlay(&self) -> bool {
+        false
+    }
+
+    fn observe(&self, client: &Client) -> Result<Reading> {
+        let request = self.request()?;
+        let response = client.send(&request)?.error_for_status()?;
+        let weather_json = self.weather_json(&response.body)?;
+        Ok(Reading {
+            observation: Observation::from_weather_json(self.name(), &weather_json)?,
+            weather_json,
+            url: request.url,
+            status: response.status,
+        })
+    }
+}
+
+pub struct Reading {
+    pub observation: Observation,
+    pub weather_json: Value,
+    // for the cache, secrets are redacted when it is written
+    pub url: String,
+    pub status: u16,
+}
+
+/// the providers named in `config.providers`, primary first
+pub fn providers(config: &WeatherConfig) -> Result<Vec<Box<dyn WeatherProvider + '_>>> {
+    let mut providers: Vec<Box<dyn WeatherProvider>> = vec![];
+    for name in &config.providers {
+        providers.push(match name.as_str() {
+            "openweathermap" => Box::new(OpenWeatherMap { config }),
+            "nws" => {
+                let Some(nws) = &config.nws else {
+                    return Err(anyhow!("providers: nws is listed but there is no nws section"))
+                        .context(Failure::Config);
+                };
+                Box::new(Nws { config, nws })
+            }
+            "open_meteo" => Box::new(OpenMeteo { config }),
+            "station" => {
+                let Some(station) = &config.station else {
+                    return Err(anyhow!(
+                        "providers: station is listed but there is no station section"
+                    ))
+                    .context(Failure::Config);
+                };
+                Box::new(Station::new(config, station)?)
+            }
+            _ => {
+                return Err(anyhow!(
+                    "providers: unknown weather provider {}, expected openweathermap, nws, open_meteo or station",
+                    name
+                ))
+                .context(Failure::Config)
+            }
+        });
+    }
+    let Some(last) = providers.last() else {
+        return Err(anyhow!("providers: no weather providers configured")).context(Failure::Config);
+    };
+    if last.is_overlay() {
+        return Err(anyhow!(
+            "providers: {} has only temperature and humidity, list a provider after it for rain, wind and conditions",
+            config.providers[providers.len() - 1]
+        ))
+        .context(Failure::Config);
+    }
+    Ok(providers)
+}
+
+/// the first reading any of `providers` gives, recording every attempt in `history`
+pub fn observe(
+    providers: &[Box<dyn WeatherProvider + '_>],
+    client: &Client,
+    history: Option<&History>,
+) -> Result<Reading> {
+    let mut last_error = None;
+    let mut overlay = None;
+    for provider in providers {
+        if provider.is_overlay() && overlay.is_some() {
+            continue;
+        }
+        let observed = provider.observe(client);
+        if let Some(history) = history {
+            history.record_fetch_result(provider.name(), &observed)?;
+        }
+        match observed {
+            Ok(reading) if provider.is_overlay() => overlay = Some(reading),
+            Ok(reading) => {
+                return match overlay {
+                    Some(overlay) => overlay_reading(overlay, reading),
+                    None => Ok(reading),
+                }
+            }
+            Err(e) => {
+                if providers.len() > 1 {
+                    warn!("{}: {:#}", provider.name(), e);
+                }
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow!("observe: no weather providers")))
+}
+
+/// `reading` with the temperature, humidity and location of `overlay`
+fn overlay_reading(overlay: Reading, reading: Reading) -> Result<Reading> {
+    let provider = overlay.observation.provider;
+    let mut weather_json = reading.weather_json;
+    weather_json["main"]["temp"] = overlay.observation.temp.into();
+    weather_json["main"]["humidity"] = overlay.observation.humidity.into();
+    weather_json["name"] = overlay.weather_json["name"].clone();
+    // the older time, so either page or API going stale makes the decision unknown
+    weather_json["dt"] = overlay
+        .observation
+        .time
+        .min(reading.observation.time)
+        .into();
+    weather_json["provider"] = provider.as_str().into();
+    weather_json["conditions_provider"] = reading.observation.provider.into();
+    Ok(Reading {
+        observation: Observation::from_weather_json(&provider, &weather_json)?,
+        weather_json,
+        url: reading.url,
+        status: reading.status,
+    })
+}
+
+pub struct OpenWeatherMap<'a> {
+    config: &'a WeatherConfig,
+}
+
+impl WeatherProvider for OpenWeatherMap<'_> {
+    fn name(&self) -> &'static str {
+        "owm_weather"
+    }
+
+    fn request(&self) -> Result<Request> {
+        let config = self.config;
+        let url = http::endpoint(&config.base_url, "/data/2.5/weather", config.allow_http)?;
+        Ok(Request::get(&format!(
+            "{}?q={},{}&appid={}&units=imperial",
+            url, config.city, config.country, config.api_key
+        )))
+    }
+
+    // already the shape everything reads, kept whole for the fields only OWM has
+    fn weather_json(&self, body: &str) -> Result<Value> {
+        parse_json(self.name(), body)
+    }
+}
+
+pub struct Nws<'a> {
+    config: &'a WeatherConfig,
+    nws: &'a NwsConfig,
+}
+
+impl WeatherProvider for Nws<'_> {
+    fn name(&self) -> &'static str {
+        "nws_weather"
+    }
+
+    fn request(&self) -> Result<Request> {
+        let url = http::endpoint(
+            &self.nws.base_url,
+            &format!("/stations/{}/observations/latest", self.nws.station),
+            self.config.allow_http,
+        )?;
+        Ok(Request::get(&url)
+            .header("User-Agent", &self.nws.user_agent)
+            .header("Accept", "application/geo+json"))
+    }
+
+    // https://www.weather.gov/documentation/services-web-api#/default/station_observation_latest
+    // values are null when the station did not report them
+    fn weather_json(&self, body: &str) -> Result<Value> {
+        let response = parse_json(self.name(), body)?;
+        let properties = &response["properties"];
+        let Some(timestamp) = properties["timestamp"].as_str() else {
+            return Err(anyhow!(
+                "weather_json: no properties.timestamp in the nws response"
+            ))
+            .context(Failure::Parse);
+        };
+        let time = DateTime::parse_from_rfc3339(timestamp)
+            .with_context(|| format!("weather_json: bad nws timestamp {}", timestamp))
+            .context(Failure::Parse)?;
+        let temp = nws_value(&properties["temperature"]).map(|(unit, value)| match unit {
+            "wmoUnit:degF" => value,
+            _ => value * 9.0 / 5.0 + 32.0,
+        });
+        let mph = |quantity: &Value| {
+            nws_value(quantity).map(|(unit, value)| match unit {
+                "wmoUnit:m_s-1" => value * 2.236_936,
+                _ => value * 0.621_371,
+            })
+        };
+        let wind_speed = mph(&properties["windSpeed"]);
+        let description = properties["textDescription"].as_str().unwrap_or_default();
+        let mut weather_json = json!({
+            "dt": time.timestamp(),
+            "name": self.config.city,
+            "main": {
+                "temp": temp,
+                "humidity": nws_value(&properties["relativeHumidity"]).map(|(_, value)| value),
+            },
+            "wind": {
+                "speed": wind_speed,
+                "gust": mph(&properties["windGust"]).or(wind_speed),
+            },
+            "weather": [{"id": nws_condition(description), "description": description.to_lowercase()}],
+            "provider": self.name(),
+        });
+        if let Some((_, rain)) = nws_value(&properties["precipitationLastHour"]) {
+            weather_json["rain"] = json!({ "1h": rain });
+        }
+        Ok(weather_json)
+    }
+}
+
+fn parse_json(provider: &str, body: &str) -> Result<Value> {
+    serde_json::from_str(body)
+        .with_context(|| format!("weather_json: could not parse the {} response", provider))
+}
+
+// unit code and value of an NWS quantity like {"unitCode": "wmoUnit:degC", "value": 16.7}
+fn nws_value(quantity: &Value) -> Option<(&str, f64)> {
+    Some((quantity["unitCode"].as_str()?, quantity["value"].as_f64()?))
+}
+
+// OWM condition id for an NWS text description like "Light Rain and Fog/Mist", the most severe
+// weather mentioned wins
+fn nws_condition(description: &str) -> u64 {
+    let description = description.to_lowercase();
+    let conditions = [
+        ("thunder", 211),
+        ("snow", 600),
+        ("sleet", 611),
+        ("freezing", 511),
+        ("rain", 500),
+        ("shower", 521),
+        ("drizzle", 300),
+        ("fog", 741),
+        ("mist", 701),
+        ("haze", 721),
+        ("smoke", 711),
+        ("overcast", 804),
+        ("mostly cloudy", 803),
+        ("partly cloudy", 802),
+        ("cloudy", 804),
+        ("clear", 800),
+        ("fair", 800),
+        ("sunny", 800),
+    ];
+    conditions
+        .iter()
+        .find(|(word, _)| description.contains(word))
+        .map(|(_, id)| *id)
+        .unwrap_or(800)
+}
+
+pub struct OpenMeteo<'a> {
+    config: &'a WeatherConfig,
+}
+
+impl WeatherProvider for OpenMeteo<'_> {
+    fn name(&self) -> &'static str {
+        "open_meteo_weather"
+    }
+
+    fn request(&self) -> Result<Request> {
+        let config = self.config;
+        let url = http::endpoint(
+            &config.open_meteo.base_url,
+            "/v1/forecast",
+            config.allow_http,
+        )?;
+        Ok(Request::get(&format!(
+            "{}?latitude={}&longitude={}\
+             &current=temperature_2m,relative_humidity_2m,rain,snowfall,weather_code,wind_speed_10m,wind_gusts_10m\
+             &daily=sunrise,sunset&forecast_days=1\
+             &temperature_unit=fahrenheit&wind_speed_unit=mph&timeformat=unixtime&timezone=GMT",
+            url, config.latitude, config.longitude
+        )))
+    }
+
+    // https://open-meteo.com/en/docs
+    fn weather_json(&self, body: &str) -> Result<Value> {
+        let response = parse_json(self.name(), body)?;
+        let current = &response["current"];
+        let Some(time) = current["time"].as_i64() else {
+            return Err(anyhow!(
+                "weather_json: no current.time in the open_meteo response"
+            ))
+            .context(Failure::Parse);
+        };
+        let (id, description) = wmo_condition(current["weather_code"].as_u64().unwrap_or(0));
+        Ok(json!({
+            "dt": time,
+            "name": self.config.city,
+            "main": {
+                "temp": current["temperature_2m"],
+                "humidity": current["relative_humidity_2m"],
+            },
+            "wind": {
+                "speed": current["wind_speed_10m"],
+                "gust": current["wind_gusts_10m"],
+            },
+            "rain": { "1h": current["rain"] },
+            // cm
+            "snow": { "1h": current["snowfall"].as_f64().map(|snow| snow * 10.0) },
+            "weather": [{"id": id, "description": description}],
+            "sys": {
+                "sunrise": response["daily"]["sunrise"][0],
+                "sunset": response["daily"]["sunset"][0],
+            },
+            "provider": self.name(),
+        }))
+    }
+}
+
+pub struct Station<'a> {
+    config: &'a WeatherConfig,
+    station: &'a StationConfig,
+    temperature: TextSelector,
+    humidity: TextSelector,
+    // selector and chrono format
+    time: Option<(TextSelector, &'a str)>,
+}
+
+impl Station<'_> {
+    fn new<'a>(config: &'a WeatherConfig, station: &'a StationConfig) -> Result<Station<'a>> {
+        if !["F", "C"].contains(&station.temperature_unit.as_str()) {
+            return Err(anyhow!(
+                "Station::new: temperature_unit is {}, expected F or C",
+                station.temperature_unit
+            ))
+            .context(Failure::Config);
+        }
+        let time = match (&station.time, &station.time_format) {
+            (Some(time), Some(time_format)) => {
+                Some((TextSelector::parse(time)?, time_format.as_str()))
+            }
+            (None, None) => None,
+            _ => {
+                return Err(anyhow!("Station::new: time and time_format go together"))
+                    .context(Failure::Config)
+            }
+        };
+        Ok(Station {
+            config,
+            station,
+            temperature: TextSelector::parse(&station.temperature)?,
+            humidity: TextSelector::parse(&station.humidity)?,
+            time,
+        })
+    }
+
+    /// the time on the page, now without a time selector
+    fn time(&self, html: &Html) -> Result<i64> {
+        let Some((selector, time_format)) = &self.time else {
+            return Ok(Utc::now().timestamp());
+        };
+        let Some(text) = selector.text(html) else {
+            return Err(anyhow!(
+                "time: nothing on the page matches the time selector"
+            ))
+            .context(Failure::Parse);
+        };
+        // the first place the format matches, e.g. after "Updated: "
+        let naive = text
+            .char_indices()
+            .find_map(|(i, _)| NaiveDateTime::parse_and_remainder(&text[i..], time_format).ok())
+            .map(|(naive, _)| naive)
+            .with_context(|| format!("time: no {} time in {:?}", time_format, text))
+            .context(Failure::Parse)?;
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|time| time.timestamp())
+            .with_context(|| format!("time: {} does not exist in local time", naive))
+            .context(Failure::Parse)
+    }
+
+    fn number(&self, html: &Html, field: &str, selector: &TextSelector) -> Result<f64> {
+        let Some(text) = selector.text(html) else {
+            return Err(anyhow!(
+                "number: nothing on the page matches the {} selector",
+                field
+            ))
+            .context(Failure::Parse);
+        };
+        first_number(&text)
+            .with_context(|| format!("number: no {} in {:?}", field, text))
+            .context(Failure::Parse)
+    }
+}
+
+impl WeatherProvider for Station<'_> {
+    fn name(&self) -> &'static str {
+        "station_weather"
+    }
+
+    fn is_overlay(&self) -> bool {
+        true
+    }
+
+    fn request(&self) -> Result<Request> {
+        let url = http::endpoint(&self.station.url, "", self.station.allow_http)?;
+        Ok(Request::get(&url))
+    }
+
+    // without a time selector the reading is taken to be from when it was fetched
+    fn weather_json(&self, body: &str) -> Result<Value> {
+        let html = Html::parse_document(body);
+        let temp = self.number(&html, "temperature", &self.temperature)?;
+        let temp = match self.station.temperature_unit.as_str() {
+            "C" => temp * 9.0 / 5.0 + 32.0,
+            _ => temp,
+        };
+        Ok(json!({
+            "dt": self.time(&html)?,
+            "name": self.station.name.as_deref().unwrap_or(&self.config.city),
+            "main": {
+                "temp": temp,
+                "humidity": self.number(&html, "humidity", &self.humidity)?,
+            },
+            "provider": self.name(),
+        }))
+    }
+}
+
+// OWM condition id and description for a WMO weather interpretation code
+// https://open-meteo.com/en/docs#weathervariables
+fn wmo_condition(code: u64) -> (u64, &'static str) {
+    match code {
+        0 => (800, "clear sky"),
+        1 => (801, "mainly clear"),
+        2 => (802, "partly cloudy"),
+        3 => (804, "overcast"),
+        45 | 48 => (741, "fog"),
+        51..=55 => (300, "drizzle"),
+        56 | 57 => (511, "freezing drizzle"),
+        61..=65 => (500, "rain"),
+        66 | 67 => (511, "freezing rain"),
+        71..=77 => (600, "snow"),
+        80..=82 => (521, "rain showers"),
+        85 | 86 => (621, "snow showers"),
+        95..=99 => (211, "thunderstorm"),
+        _ => (800, "unknown"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WeatherConfig {
+        serde_json::from_value(json!({
+            "api_key": "secret", "city": "Boston", "country": "US",
+            "latitude": "42.36", "longitude": "-71.06",
+            "providers": ["nws", "open_meteo", "openweathermap"],
+            "nws": {"station": "KBOS", "user_agent": "(iloveair, test@example.com)"}
+        }))
+        .unwrap()
+    }
+
+    fn fixture(name: &str) -> String {
+        let path = format!(
+            "{}/tests/fixtures/weather/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    fn parse(provider: &dyn WeatherProvider, name: &str) -> Observation {
+        let weather_json = provider.weather_json(&fixture(name)).unwrap();
+        Observation::from_weather_json(provider.name(), &weather_json).unwrap()
+    }
+
+    #[test]
+    fn test_providers() {
+        let config = config();
+        let providers = providers(&config).unwrap();
+        let names: Vec<&str> = providers.iter().map(|p| p.name()).collect();
+        assert_eq!(names, ["nws_weather", "open_meteo_weather", "owm_weather"]);
+        let nws = providers[0].request().unwrap();
+        assert_eq!(
+            nws.url,
+            "https://api.weather.gov/stations/KBOS/observations/latest"
+        );
+        assert!(nws
+            .headers
+            .contains(&("User-Agent".into(), "(iloveair, test@example.com)".into())));
+        assert!(providers[1].request().unwrap().url.starts_with(
+            "https://api.open-meteo.com/v1/forecast?latitude=42.36&longitude=-71.06&"
+        ));
+
+        drop(providers);
+        let mut config = config;
+        config.nws = None;
+        assert_eq!(crate::cli::exit_code(&providers_error(&config)), 78);
+        config.providers = vec!["wunderground".into()];
+        assert_eq!(crate::cli::exit_code(&providers_error(&config)), 78);
+    }
+
+    fn providers_error(config: &WeatherConfig) -> anyhow::Error {
+        providers(config).err().unwrap()
+    }
+
+    #[test]
+    fn test_openweathermap() {
+        let config = config();
+        let observation = parse(&OpenWeatherMap { config: &config }, "openweathermap.json");
+        assert_eq!(observation.provider, "owm_weather");
+        assert_eq!(observation.time, 1694879940);
+        assert_eq!(observation.location.as_deref(), Some("Boston"));
+        assert_eq!(observation.temp, 61.52);
+        assert_eq!(observation.humidity, 88);
+        assert!(observation.conditions.precipitation());
+        assert_eq!(observation.conditions.sunrise, Some(1694859118));
+    }
+
+    #[test]
+    fn test_nws() {
+        let config = config();
+        let provider = Nws {
+            config: &config,
+            nws: config.nws.as_ref().unwrap(),
+        };
+        let observation = parse(&provider, "nws.json");
+        assert_eq!(observation.time, 1694879640);
+        assert_eq!(observation.location.as_deref(), Some("Boston"));
+        assert!((observation.temp - 62.06).abs() < 0.01);
+        assert_eq!(observation.humidity, 94);
+        assert!((observation.conditions.wind_speed - 14.99).abs() < 0.01);
+        // no gust reported
+        assert_eq!(
+            observation.conditions.wind_gust,
+            observation.conditions.wind_speed
+        );
+        assert_eq!(observation.conditions.rain_1h, 1.3);
+        assert_eq!(observation.conditions.condition_ids, vec![500]);
+        assert_eq!(
+            observation.conditions.description,
+            "light rain and fog/mist"
+        );
+
+        // a station that did not report the temperature
+        let mut response: Value = serde_json::from_str(&fixture("nws.json")).unwrap();
+        response["properties"]["temperature"]["value"] = Value::Null;
+        let weather_json = provider.weather_json(&response.to_string()).unwrap();
+        let e = Observation::from_weather_json(provider.name(), &weather_json).unwrap_err();
+        assert_eq!(crate::cli::exit_code(&e), 65);
+    }
+
+    #[test]
+    fn test_open_meteo() {
+        let config = config();
+        let observation = parse(&OpenMeteo { config: &config }, "open_meteo.json");
+        assert_eq!(observation.time, 1694879100);
+        assert_eq!(observation.temp, 62.3);
+        assert_eq!(observation.humidity, 88);
+        assert_eq!(observation.conditions.wind_gust, 21.0);
+        assert_eq!(observation.conditions.condition_ids, vec![500]);
+        assert_eq!(observation.conditions.sunset, Some(1694904219));
+        assert!(observation.conditions.precipitation());
+    }
+
+    fn station_config(temperature: &str, humidity: &str, unit: &str) -> WeatherConfig {
+        let mut config = config();
+        config.station = Some(
+            serde_json::from_value(json!({
+                "url": "http://weather.cos.gmu.edu/", "allow_http": true,
+                "temperature": temperature, "humidity": humidity,
+                "temperature_unit": unit, "name": "Fairfax"
+            }))
+            .unwrap(),
+        );
+        config
+    }
+
+    #[test]
+    fn test_station() {
+        // the XPaths local_weather.py used
+        let config = station_config(
+            "//tr[3]/td[2]/font/strong/small/font/text()",
+            "//tr[4]/td[2]/font/strong/small/font/text()",
+            "F",
+        );
+        let station = Station::new(&config, config.station.as_ref().unwrap()).unwrap();
+        let observation = parse(&station, "station.html");
+        assert_eq!(observation.provider, "station_weather");
+        assert_eq!(observation.location.as_deref(), Some("Fairfax"));
+        assert_eq!(observation.temp, 64.8);
+        assert_eq!(observation.humidity, 83);
+        assert!(!observation.conditions.precipitation());
+
+        let config = station_config(
+            r#"table[border="1"] tr:nth-of-type(3) td:nth-of-type(2)"#,
+            r#"table[border="1"] tr:nth-of-type(4) td:nth-of-type(2)"#,
+            "C",
+        );
+        let station = Station::new(&config, config.station.as_ref().unwrap()).unwrap();
+        assert!((parse(&station, "station.html").temp - 148.64).abs() < 0.01);
+
+        // the page changed and the selector finds nothing
+        let config = station_config("//tr[30]/td[2]", "//tr[4]/td[2]", "F");
+        let station = Station::new(&config, config.station.as_ref().unwrap()).unwrap();
+        let e = station.weather_json(&fixture("station.html")).unwrap_err();
+        assert_eq!(crate::cli::exit_code(&e), 65);
+
+        let config = station_config("//tr[3]/td[2]", "//tr[4]/td[2]", "K");
+        let e = providers_error(&WeatherConfig {
+            providers: vec!["station".into()],
+            ..config
+        });
+        assert_eq!(crate::cli::exit_code(&e), 78);
+
+        // nothing after the station to take rain, wind and conditions from
+        for listed in [vec!["station"], vec!["openweathermap", "station"]] {
+            let e = providers_error(&WeatherConfig {
+                providers: listed.iter().map(|p| p.to_string()).collect(),
+                ..station_config("//tr[3]/td[2]", "//tr[4]/td[2]", "F")
+            });
+            assert_eq!(crate::cli::exit_code(&e), 78, "{:?}", listed);
+        }
+    }
+
+    fn reading(provider: &dyn WeatherProvider, name: &str) -> Reading {
+        let weather_json = provider.weather_json(&fixture(name)).unwrap();
+        Reading {
+            observation: Observation::from_weather_json(provider.name(), &weather_json).unwrap(),
+            weather_json,
+            url: "https://example.com".into(),
+            status: 200,
+        }
+    }
+
+    #[test]
+    fn test_station_overlay() {
+        let config = station_config(
+            "//tr[3]/td[2]/font/strong/small/font/text()",
+            "//tr[4]/td[2]/font/strong/small/font/text()",
+            "F",
+        );
+        let station = Station::new(&config, config.station.as_ref().unwrap()).unwrap();
+        let owm = OpenWeatherMap { config: &config };
+        let overlaid = overlay_reading(
+            reading(&station, "station.html"),
+            reading(&owm, "openweathermap.json"),
+        )
+        .unwrap();
+        let observation = overlaid.observation;
+        assert_eq!(observation.provider, "station_weather");
+        assert_eq!(observation.location.as_deref(), Some("Fairfax"));
+        assert_eq!(observation.temp, 64.8);
+        assert_eq!(observation.humidity, 83);
+        // OWM's rain still vetoes, and its time still goes stale
+        assert!(observation.conditions.precipitation());
+        assert_eq!(observation.time, 1694879940);
+        assert_eq!(overlaid.weather_json["conditions_provider"], "owm_weather");
+    }
+
+    #[test]
+    fn test_station_time() {
+        let page = r#"<html><body><p id="updated">Updated: 09/16/2023 11:54 AM EDT</p>
+            <p id="temp">64.8</p><p id="rh">83</p></body></html>"#;
+        let mut config = station_config("#temp", "#rh", "F");
+        let station_config = config.station.as_mut().unwrap();
+        station_config.time = Some("#updated".into());
+        station_config.time_format = Some("%m/%d/%Y %I:%M %p".into());
+        let station = Station::new(&config, config.station.as_ref().unwrap()).unwrap();
+        let expected = Local
+            .with_ymd_and_hms(2023, 9, 16, 11, 54, 0)
+            .unwrap()
+            .timestamp();
+        assert_eq!(station.weather_json(page).unwrap()["dt"], expected);
+
+        let e = station
+            .weather_json(&page.replace("09/16/2023", "yesterday"))
+            .unwrap_err();
+        assert_eq!(crate::cli::exit_code(&e), 65);
+
+        config.station.as_mut().unwrap().time_format = None;
+        let e = providers_error(&WeatherConfig {
+            providers: vec!["station".into(), "openweathermap".into()],
+            ..config
+        });
+        assert_eq!(crate::cli::exit_code(&e), 78);
+    }
+
+    #[test]
+    fn test_conditions() {
+        assert_eq!(nws_condition("Thunderstorms and Rain"), 211);
+        assert_eq!(nws_condition("Mostly Cloudy"), 803);
+        assert_eq!(nws_condition("Clear"), 800);
+        assert_eq!(wmo_condition(3), (804, "overcast"));
+        assert_eq!(wmo_condition(96).0, 211);
+    }
+}
@@ -0,0 +1,61 @@
+// psychrometric helpers, temperatures are Fahrenheit like the rest of the reports
+
+fn fahrenheit_to_celsius(fahrenheit: f64) -> f64 {
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+/// Magnus approximation, good to ~0.4°F between -40°F and 120°F
+pub fn dew_point(temp: f64, relative_humidity: f64) -> f64 {
+    let (b, c) = (17.62, 243.12);
+    let t = fahrenheit_to_celsius(temp);
+    let rh = relative_humidity.clamp(1.0, 100.0) / 100.0;
+    let gamma = rh.ln() + b * t / (c + t);
+    let dew_point_celsius = c * gamma / (b - gamma);
+    dew_point_celsius * 9.0 / 5.0 + 32.0
+}
+
+// kPa at sea level, close enough indoors without a pressure reading
+static STANDARD_PRESSURE: f64 = 101.325;
+
+/// saturation vapor pressure in kPa (Tetens)
+fn saturation_pressure(temp: f64) -> f64 {
+    let t = fahrenheit_to_celsius(temp);
+    0.61078 * (17.27 * t / (t + 237.3)).exp()
+}
+
+/// kg of water per kg of dry air
+pub fn humidity_ratio(temp: f64, relative_humidity: f64) -> f64 {
+    let vapor_pressure = saturation_pressure(temp) * relative_humidity.clamp(0.0, 100.0) / 100.0;
+    0.622 * vapor_pressure / (STANDARD_PRESSURE - vapor_pressure)
+}
+
+/// specific enthalpy of moist air in kJ/kg, what outside air brings in when it replaces indoor air
+pub fn enthalpy(temp: f64, relative_humidity: f64) -> f64 {
+    let t = fahrenheit_to_celsius(temp);
+    let w = humidity_ratio(temp, relative_humidity);
+    1.006 * t + w * (2501.0 + 1.86 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dew_point_saturated() {
+        assert!((dew_point(68.0, 100.0) - 68.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_enthalpy() {
+        // 77°F 50% is about 50 kJ/kg on a psychrometric chart
+        assert!((enthalpy(77.0, 50.0) - 50.0).abs() < 1.5);
+        // muggy 72°F air carries more heat than dry 77°F air
+        assert!(enthalpy(72.0, 90.0) > enthalpy(77.0, 40.0));
+    }
+
+    #[test]
+    fn test_dew_point() {
+        // 77°F at 50% is a dew point of about 57°F
+        assert!((dew_point(77.0, 50.0) - 57.0).abs() < 0.5);
+    }
+}
@@ -0,0 +1,374 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+
+// Airthings reports radon in Bq/m³
+pub static BQ_M3_PER_PCI_L: f64 = 37.0;
+// EPA action level, 4 pCi/L
+pub static EPA_ACTION_LEVEL_BQ_M3: f64 = 4.0 * BQ_M3_PER_PCI_L;
+// WHO reference level
+pub static WHO_REFERENCE_LEVEL_BQ_M3: f64 = 100.0;
+
+static DAY: i64 = 86400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RadonReading {
+    // unix seconds
+    pub time: i64,
+    pub bq_m3: f64,
+}
+
+/// appends one reading as a JSON line, read_waveplus --radon-log keeps the history this way
+pub fn append_radon_log(path: &str, reading: &RadonReading) -> Result<()> {
+    let path = shellexpand::tilde(path).into_owned();
+    let mut fout = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("append_radon_log: could not open for append {}", path))?;
+    writeln!(fout, "{}", serde_json::to_string(reading)?)
+        .with_context(|| format!("append_radon_log: could not write {}", path))
+}
+
+pub fn read_radon_log(path: &str) -> Result<Vec<RadonReading>> {
+    let path = shellexpand::tilde(path).into_owned();
+    let fin = std::fs::File::open(&path)
+        .with_context(|| format!("read_radon_log: could not open {}", path))?;
+    let mut readings = Vec::new();
+    for (n, line) in BufReader::new(fin).lines().enumerate() {
+        let line = line.with_context(|| format!("read_radon_log: could not read {}", path))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        readings.push(
+            serde_json::from_str(&line)
+                .with_context(|| format!("read_radon_log: bad reading {}:{}", path, n + 1))?,
+        );
+    }
+    readings.sort_by_key(|r: &RadonReading| r.time);
+    Ok(readings)
+}
+
+pub fn bq_m3_to_pci_l(bq_m3: f64) -> f64 {
+    bq_m3 / BQ_M3_PER_PCI_L
+}
+
+fn mean(readings: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, n) = readings.fold((0.0, 0), |(sum, n), v| (sum + v, n + 1));
+    if n == 0 {
+        None
+    } else {
+        Some(sum / n as f64)
+    }
+}
+
+/// mean of the readings in the `days` before `now`
+pub fn average_over_days(readings: &[RadonReading], now: i64, days: i64) -> Option<f64> {
+    let since = now - days * DAY;
+    mean(
+        readings
+            .iter()
+            .filter(|r| r.time > since && r.time <= now)
+            .map(|r| r.bq_m3),
+    )
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RadonAverages {
+    pub seven_day: Option<f64>,
+    pub thirty_day: Option<f64>,
+    pub annual: Option<f64>,
+}
+
+pub fn averages(readings: &[RadonReading], now: i64) -> RadonAverages {
+    RadonAverages {
+        seven_day: average_over_days(readings, now, 7),
+        thirty_day: average_over_days(readings, now, 30),
+        annual: average_over_days(readings, now, 365),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StepChange {
+    pub time: i64,
+    pub before: f64,
+    pub after: f64,
+}
+
+impl StepChange {
+    pub fn is_increase(&self) -> bool {
+        self.after > self.before
+    }
+}
+
+/// Compares the mean of `window_days` before and after each day boundary and reports
+/// boundaries where the level changed by at least `min_ratio` and `min_delta` Bq/m³,
+/// e.g. a mitigation fan failing (up) or being installed (down). Only the strongest
+/// boundary within a window is reported.
+pub fn step_changes(
+    readings: &[RadonReading],
+    window_days: i64,
+    min_ratio: f64,
+    min_delta: f64,
+) -> Vec<StepChange> {
+    let (Some(first), Some(last)) = (
+        readings.iter().map(|r| r.time).min(),
+        readings.iter().map(|r| r.time).max(),
+    ) else {
+        return vec![];
+    };
+    let window = window_days * DAY;
+    let mut changes: Vec<(f64, StepChange)> = Vec::new();
+    let mut boundary = (first / DAY + 1) * DAY;
+    while boundary + window <= last + DAY {
+        let window_mean = |from: i64, to: i64| {
+            mean(
+                readings
+                    .iter()
+                    .filter(|r| r.time >= from && r.time < to)
+                    .map(|r| r.bq_m3),
+            )
+        };
+        if let (Some(before), Some(after)) = (
+            window_mean(boundary - window, boundary),
+            window_mean(boundary, boundary + window),
+        ) {
+            let (lo, hi) = (before.min(after), before.max(after));
+            let ratio = if lo > 0.0 { hi / lo } else { f64::INFINITY };
+            if ratio >= min_ratio && hi - lo >= min_delta {
+                let change = StepChange {
+                    time: boundary,
+                    before,
+                    after,
+                };
+                match changes.last_mut() {
+                    Some((best, last)) if boundary - last.time < window => {
+                        if ratio > *best {
+                            *best = ratio;
+                            *last = change;
+                        }
+                    }
+                    _ => changes.push((ratio, change)),
+                }
+            }
+        }
+        boundary += DAY;
+    }
+    changes.into_iter().map(|(_, c)| c).collect()
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MonthlyRadonReport {
+    pub year: i32,
+    pub month: u32,
+    pub samples: usize,
+    pub mean: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    // days whose mean was over the level
+    pub days_over_epa: usize,
+    pub days_over_who: usize,
+    // as of the end of the month
+    pub averages: RadonAverages,
+    pub step_changes: Vec<StepChange>,
+}
+
+fn month_bounds(year: i32, month: u32) -> Option<(i64, i64)> {
+    let start = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let end = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)?
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)?
+    };
+    Some((
+        start.and_hms_opt(0, 0, 0)?.and_utc().timestamp(),
+        end.and_hms_opt(0, 0, 0)?.and_utc().timestamp(),
+    ))
+}
+
+/// summary of a calendar month (UTC); readings before the month feed the long averages
+pub fn monthly_report(readings: &[RadonReading], year: i32, month: u32) -> MonthlyRadonReport {
+    let (start, end) = month_bounds(year, month).unwrap_or((0, 0));
+    let in_month: Vec<RadonReading> = readings
+        .iter()
+        .filter(|r| r.time >= start && r.time < end)
+        .copied()
+        .collect();
+    let mut days_over_epa = 0;
+    let mut days_over_who = 0;
+    let mut day = start;
+    while day < end {
+        if let Some(daily) = mean(
+            in_month
+                .iter()
+                .filter(|r| r.time >= day && r.time < day + DAY)
+                .map(|r| r.bq_m3),
+        ) {
+            if daily > EPA_ACTION_LEVEL_BQ_M3 {
+                days_over_epa += 1;
+            }
+            if daily > WHO_REFERENCE_LEVEL_BQ_M3 {
+                days_over_who += 1;
+            }
+        }
+        day += DAY;
+    }
+    let values = || in_month.iter().map(|r| r.bq_m3);
+    MonthlyRadonReport {
+        year,
+        month,
+        samples: in_month.len(),
+        mean: mean(values()),
+        min: values().reduce(f64::min),
+        max: values().reduce(f64::max),
+        days_over_epa,
+        days_over_who,
+        averages: averages(readings, end - 1),
+        step_changes: step_changes(readings, 3, 1.5, 40.0)
+            .into_iter()
+            .filter(|c| c.time >= start && c.time < end)
+            .collect(),
+    }
+}
+
+fn level(bq_m3: Option<f64>) -> String {
+    match bq_m3 {
+        Some(v) => {
+            let flag = if v > EPA_ACTION_LEVEL_BQ_M3 {
+                " over EPA action level"
+            } else if v > WHO_REFERENCE_LEVEL_BQ_M3 {
+                " over WHO reference level"
+            } else {
+                ""
+            };
+            format!("{:.0} Bq/m³ ({:.1} pCi/L){}", v, bq_m3_to_pci_l(v), flag)
+        }
+        None => "no data".into(),
+    }
+}
+
+impl fmt::Display for MonthlyRadonReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Radon {}-{:02}", self.year, self.month)?;
+        writeln!(f, "samples: {}", self.samples)?;
+        writeln!(f, "mean: {}", level(self.mean))?;
+        writeln!(f, "min: {}", level(self.min))?;
+        writeln!(f, "max: {}", level(self.max))?;
+        writeln!(
+            f,
+            "days over EPA {:.0} Bq/m³: {}",
+            EPA_ACTION_LEVEL_BQ_M3, self.days_over_epa
+        )?;
+        writeln!(
+            f,
+            "days over WHO {:.0} Bq/m³: {}",
+            WHO_REFERENCE_LEVEL_BQ_M3, self.days_over_who
+        )?;
+        writeln!(f, "7 day average: {}", level(self.averages.seven_day))?;
+        writeln!(f, "30 day average: {}", level(self.averages.thirty_day))?;
+        writeln!(f, "annual average: {}", level(self.averages.annual))?;
+        for change in &self.step_changes {
+            let when = Utc
+                .timestamp_opt(change.time, 0)
+                .single()
+                .map(|t: DateTime<Utc>| t.format("%Y-%m-%d").to_string())
+                .unwrap_or_default();
+            writeln!(
+                f,
+                "step {} on {}: {:.0} -> {:.0} Bq/m³{}",
+                if change.is_increase() { "up" } else { "down" },
+                when,
+                change.before,
+                change.after,
+                if change.is_increase() {
+                    ", check the mitigation fan"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// the month before `now`, what a report run on the 1st should cover
+pub fn previous_month(now: DateTime<Utc>) -> (i32, u32) {
+    if now.month() == 1 {
+        (now.year() - 1, 12)
+    } else {
+        (now.year(), now.month() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // hourly readings for `days` days starting 2023-09-01 at `level`
+    fn hourly(start_day: i64, days: i64, level: f64) -> Vec<RadonReading> {
+        let start = month_bounds(2023, 9).unwrap().0 + start_day * DAY;
+        (0..days * 24)
+            .map(|h| RadonReading {
+                time: start + h * 3600,
+                bq_m3: level,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(EPA_ACTION_LEVEL_BQ_M3, 148.0);
+        assert_eq!(bq_m3_to_pci_l(74.0), 2.0);
+    }
+
+    #[test]
+    fn test_averages() {
+        let mut readings = hourly(0, 23, 50.0);
+        readings.extend(hourly(23, 7, 120.0));
+        let now = readings.last().unwrap().time;
+        let averages = averages(&readings, now);
+        assert_eq!(averages.seven_day, Some(120.0));
+        let thirty = averages.thirty_day.unwrap();
+        assert!(thirty > 50.0 && thirty < 120.0);
+    }
+
+    #[test]
+    fn test_step_up() {
+        let mut readings = hourly(0, 10, 40.0);
+        readings.extend(hourly(10, 10, 200.0));
+        let changes = step_changes(&readings, 3, 1.5, 40.0);
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].is_increase());
+        assert_eq!(changes[0].time, month_bounds(2023, 9).unwrap().0 + 10 * DAY);
+    }
+
+    #[test]
+    fn test_no_step_for_noise() {
+        let mut readings = hourly(0, 10, 40.0);
+        readings.extend(hourly(10, 10, 55.0));
+        assert!(step_changes(&readings, 3, 1.5, 40.0).is_empty());
+    }
+
+    #[test]
+    fn test_monthly_report() {
+        let mut readings = hourly(0, 10, 40.0);
+        readings.extend(hourly(10, 20, 160.0));
+        let report = monthly_report(&readings, 2023, 9);
+        assert_eq!(report.samples, 30 * 24);
+        assert_eq!(report.days_over_epa, 20);
+        assert_eq!(report.days_over_who, 20);
+        assert_eq!(report.step_changes.len(), 1);
+        let text = report.to_string();
+        assert!(text.starts_with("Radon 2023-09\n"));
+        assert!(text.contains("check the mitigation fan"));
+    }
+
+    #[test]
+    fn test_previous_month() {
+        let jan = Utc.with_ymd_and_hms(2024, 1, 1, 6, 0, 0).unwrap();
+        assert_eq!(previous_month(jan), (2023, 12));
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::read_to_string_with_shellexpand;
+use crate::cli::Failure;
+
+// Daily high, low and precipitation from an NWS climate text product for getrain, e.g. the
+// regional temperature and precipitation table at
+// https://forecast.weather.gov/product.php?site=LWX&product=RTP&issuedby=LWX
+//
+// The report is the text of the page's <pre class="glossaryProduct">. rain_regex picks one
+// station's line out of it and captures high, low and precipitation, by those names or in that
+// order. NWS writes M for a missing value and T for a trace, precipitation too small to measure.
+
+#[derive(Serialize, Deserialize)]
+pub struct RainConfig {
+    pub rain_data_url: String,
+    pub rain_regex: String,
+    #[serde(default)]
+    pub allow_http: bool,
+}
+
+pub fn read_rain_config(rain_config_path: &str) -> Result<RainConfig> {
+    let contents = read_to_string_with_shellexpand(&rain_config_path.to_string())?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("read_rain_config: could not parse {}", rain_config_path))
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Precipitation {
+    Inches(f64),
+    Trace,
+    Missing,
+}
+
+impl Precipitation {
+    /// a trace counts as none, like the rain totals NWS publishes
+    pub fn inches(&self) -> Option<f64> {
+        match self {
+            Precipitation::Inches(inches) => Some(*inches),
+            Precipitation::Trace => Some(0.0),
+            Precipitation::Missing => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DailyClimate {
+    pub date: NaiveDate,
+    // °F, None when missing
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub precipitation: Precipitation,
+}
+
+pub fn rain_regex(rain_regex: &str) -> Result<Regex> {
+    let regex = Regex::new(rain_regex)
+        .with_context(|| format!("rain_regex: bad rain_regex {}", rain_regex))
+        .context(Failure::Config)?;
+    let named = ["high", "low", "precipitation"]
+        .iter()
+        .all(|name| regex.capture_names().any(|capture| capture == Some(name)));
+    if !named && regex.captures_len() < 4 {
+        return Err(anyhow!(
+            "rain_regex: {} needs three groups, high, low and precipitation",
+            rain_regex
+        ))
+        .context(Failure::Config);
+    }
+    Ok(regex)
+}
+
+/// the text of the <pre class="glossaryProduct"> NWS puts products in
+pub fn report_text(html: &str) -> Result<String> {
+    let Ok(selector) = Selector::parse("pre.glossaryProduct") else {
+        // This else block is unreachable because the selector is a valid constant.
+        unreachable!();
+    };
+    Html::parse_document(html)
+        .select(&selector)
+        .next()
+        .map(|pre| pre.text().collect())
+        .context("report_text: no <pre class=\"glossaryProduct\"> on the page")
+        .context(Failure::Parse)
+}
+
+/// the station's values in `report`, `date` is the day they are for
+pub fn parse_report(report: &str, regex: &Regex, date: NaiveDate) -> Result<DailyClimate> {
+    let Some(captures) = regex.captures(report) else {
+        return Err(anyhow!(
+            "parse_report: rain_regex {} does not match the report",
+            regex
+        ))
+        .context(Failure::Parse);
+    };
+    let group = |name: &str, index: usize| {
+        captures
+            .name(name)
+            .or_else(|| captures.get(index))
+            .map(|value| value.as_str().trim())
+            .unwrap_or("M")
+    };
+    Ok(DailyClimate {
+        date,
+        high: temperature(group("high", 1))?,
+        low: temperature(group("low", 2))?,
+        precipitation: precipitation(group("precipitation", 3))?,
+    })
+}
+
+fn temperature(value: &str) -> Result<Option<f64>> {
+    if value == "M" {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .with_context(|| format!("temperature: could not parse {:?}", value))
+        .context(Failure::Parse)
+}
+
+fn precipitation(value: &str) -> Result<Precipitation> {
+    match value {
+        "M" => Ok(Precipitation::Missing),
+        "T" => Ok(Precipitation::Trace),
+        _ => value
+            .parse()
+            .map(Precipitation::Inches)
+            .with_context(|| format!("precipitation: could not parse {:?}", value))
+            .context(Failure::Parse),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> String {
+        let path = format!("{}/tests/fixtures/rtp.html", env!("CARGO_MANIFEST_DIR"));
+        report_text(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn station(code: &str) -> DailyClimate {
+        let regex = rain_regex(&format!(
+            r"{}\s*:[^:]*:\s*(\S+)\s*/\s*(\S+)\s*/\s*(\S+)",
+            code
+        ))
+        .unwrap();
+        parse_report(
+            &report(),
+            &regex,
+            NaiveDate::from_ymd_opt(2023, 9, 15).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let dulles = station("IAD");
+        assert_eq!(dulles.high, Some(76.0));
+        assert_eq!(dulles.low, Some(58.0));
+        assert_eq!(dulles.precipitation, Precipitation::Inches(0.12));
+
+        let baltimore = station("BWI");
+        assert_eq!(baltimore.low, None);
+        assert_eq!(baltimore.precipitation, Precipitation::Trace);
+        assert_eq!(baltimore.precipitation.inches(), Some(0.0));
+
+        let frederick = station("FDK");
+        assert_eq!(frederick.high, Some(-2.0));
+        assert_eq!(frederick.precipitation.inches(), None);
+    }
+
+    #[test]
+    fn test_named_groups() {
+        let regex = rain_regex(
+            r"DCA\s*:[^:]*:\s*(?<high>\S+)\s*/\s*(?<low>\S+)\s*/\s*(?<precipitation>\S+)",
+        )
+        .unwrap();
+        let date = NaiveDate::from_ymd_opt(2023, 9, 15).unwrap();
+        let climate = parse_report(&report(), &regex, date).unwrap();
+        assert_eq!(climate.high, Some(78.0));
+        assert_eq!(climate.precipitation, Precipitation::Inches(0.0));
+    }
+
+    #[test]
+    fn test_errors() {
+        for bad in [r"IAD (\d+", r"IAD (\d+) (\d+)"] {
+            let e = rain_regex(bad).unwrap_err();
+            assert_eq!(crate::cli::exit_code(&e), 78, "{}", bad);
+        }
+        let e = report_text("<html><body>product not available</body></html>").unwrap_err();
+        assert_eq!(crate::cli::exit_code(&e), 65);
+        let regex = rain_regex(r"XYZ : (\S+) / (\S+) / (\S+)").unwrap();
+        let date = NaiveDate::from_ymd_opt(2023, 9, 15).unwrap();
+        let e = parse_report(&report(), &regex, date).unwrap_err();
+        assert_eq!(crate::cli::exit_code(&e), 65);
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+
+use crate::decision::Decision;
+use crate::template::render;
+
+static DOWN: &str = "↓";
+static UP: &str = "↗";
+static EQ: &str = "=";
+
+pub fn updown<T: PartialOrd + ToString>(fst: T, snd: T) -> String {
+    if let Some(o) = fst.partial_cmp(&snd) {
+        match o {
+            std::cmp::Ordering::Less => DOWN.into(),
+            std::cmp::Ordering::Greater => UP.into(),
+            std::cmp::Ordering::Equal => EQ.into(),
+        }
+    } else {
+        "?".into()
+    }
+}
+
+/// the emoji report appended to Indoor.txt
+pub fn render_text(decision: &Decision) -> Result<String> {
+    render("text", decision)
+}
+
+/// same as the text report without the time line, so an unchanged decision renders the same message
+pub fn render_pushover(decision: &Decision) -> Result<String> {
+    render("pushover", decision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decision::{decide, Conditions, Inputs, Thresholds};
+    use crate::rules::RuleSet;
+
+    #[test]
+    fn test_updown() {
+        assert_eq!(updown(1, 2), DOWN);
+        assert_eq!(updown(2, 1), UP);
+        assert_eq!(updown(1.0, 1.0), EQ);
+        assert_eq!(updown(f64::NAN, 1.0), "?");
+    }
+
+    #[test]
+    fn test_render_text() {
+        let decision = decide(
+            Inputs {
+                indoor: Conditions {
+                    temp: 70.0,
+                    humidity: 50,
+                    time: None,
+                },
+                outdoor: Conditions {
+                    temp: 65.0,
+                    humidity: 55,
+                    time: None,
+                },
+                pollution: None,
+                weather: None,
+                indoor_air: None,
+            },
+            Thresholds {
+                max_humidity: 60,
+                min_temp: 50.0,
+                max_temp: 84.0,
+            },
+            &RuleSet::default(),
+        )
+        .unwrap();
+        let text = render_text(&decision).unwrap();
+        let expected = format!(
+            "Time: {}\n\
+            window_should_be_open: 🪟✔️\n\
+            indoor temp: 🏠↗ 70🌡️\n\
+            outdoor temp: 🌳↓ 65🌡️\n\
+            Indoor humidity: 🏠↓ 50💧\n\
+            outdoor humidity: 🌳 ↗ 55💧\n\
+            can_let_in_humidify: 💧✔️\n\
+            can_let_in_temperature: 🌡️✔️\n",
+            decision.generated_at.format("%A %Y-%m-%d %I:%M %p")
+        );
+        assert_eq!(text, expected);
+        assert!(!render_pushover(&decision).unwrap().contains("Time: "));
+    }
+}
@@ -0,0 +1,613 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::audit::read_to_string_with_shellexpand;
+use crate::decision::RuleOutcome;
+use crate::energy::EnergySettings;
+use crate::schedule::Schedule;
+use crate::staleness::MaxAgeSettings;
+use crate::ventilation::VentilationSettings;
+use crate::vetoes::VetoSettings;
+
+// Rules are read from ~/.config/iloveair/rules.json
+//
+// {
+//   "rules": [
+//     { "name": "comfortable", "when": "outdoor.temp between 55 and 80" },
+//     { "name": "drier", "when": "outdoor.dew_point < indoor.dew_point - 2" },
+//     { "name": "air", "any": [
+//         { "name": "clean", "when": "aqi <= 2" },
+//         { "name": "stuffy", "when": "indoor.humidity > 70" }
+//     ] },
+//     { "name": "dry", "not": { "name": "raining", "when": "raining" } }
+//   ]
+// }
+//
+// The window should be open when every top level rule passes and no veto
+// (see vetoes.rs and schedule.rs) applies. An "energy" section (see energy.rs)
+// adds a passive cooling or heating rule and "ventilation" (see ventilation.rs)
+// tunes the CO2/VOC purge advice.
+// Expressions support numbers, true/false, fact names, + - * /,
+// < <= > >= == !=, `x between lo and hi`, and, or, not and parentheses.
+
+static DEFAULT_RULES: &str = include_str!("default_rules.json");
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Fact {
+    Bool(bool),
+    Number(f64),
+}
+
+impl fmt::Display for Fact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fact::Bool(b) => write!(f, "{}", b),
+            Fact::Number(n) => write!(f, "{}", (n * 100.0).round() / 100.0),
+        }
+    }
+}
+
+impl From<f64> for Fact {
+    fn from(n: f64) -> Self {
+        Fact::Number(n)
+    }
+}
+
+impl From<u64> for Fact {
+    fn from(n: u64) -> Self {
+        Fact::Number(n as f64)
+    }
+}
+
+impl From<bool> for Fact {
+    fn from(b: bool) -> Self {
+        Fact::Bool(b)
+    }
+}
+
+pub type Facts = BTreeMap<String, Fact>;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Rule {
+    When {
+        name: String,
+        when: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        icon: Option<String>,
+    },
+    All {
+        name: String,
+        all: Vec<Rule>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        icon: Option<String>,
+    },
+    Any {
+        name: String,
+        any: Vec<Rule>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        icon: Option<String>,
+    },
+    Not {
+        name: String,
+        not: Box<Rule>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        icon: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub vetoes: VetoSettings,
+    #[serde(default)]
+    pub schedule: Schedule,
+    #[serde(default)]
+    pub energy: Option<EnergySettings>,
+    #[serde(default)]
+    pub ventilation: VentilationSettings,
+    #[serde(default)]
+    pub max_age: MaxAgeSettings,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        // the builtin rules are covered by test_default_rules_parse
+        serde_json::from_str(DEFAULT_RULES).expect("default_rules.json")
+    }
+}
+
+impl RuleSet {
+    /// parses every expression so a typo is reported when the file is loaded
+    pub fn validate(&self) -> Result<()> {
+        fn validate_rule(rule: &Rule) -> Result<()> {
+            match rule {
+                Rule::When { name, when, .. } => parse(when)
+                    .map(|_| ())
+                    .with_context(|| format!("rule {}", name)),
+                Rule::All { all: rules, .. } | Rule::Any { any: rules, .. } => {
+                    rules.iter().try_for_each(validate_rule)
+                }
+                Rule::Not { not, .. } => validate_rule(not),
+            }
+        }
+        self.rules.iter().try_for_each(validate_rule)?;
+        self.ventilation.validate()?;
+        self.schedule.validate()
+    }
+
+    pub fn evaluate(&self, facts: &Facts) -> Result<Vec<RuleOutcome>> {
+        self.rules.iter().map(|rule| rule.evaluate(facts)).collect()
+    }
+}
+
+pub fn read_rules_json(rules_config_path: &String) -> Result<RuleSet> {
+    let contents = read_to_string_with_shellexpand(rules_config_path)
+        .with_context(|| format!("read_rules_json: could not read {}", rules_config_path))?;
+    let rules: RuleSet = serde_json::from_str(&contents)
+        .with_context(|| format!("read_rules_json: could not parse {}", rules_config_path))?;
+    rules
+        .validate()
+        .with_context(|| format!("read_rules_json: invalid rule in {}", rules_config_path))?;
+    Ok(rules)
+}
+
+impl Rule {
+    pub fn name(&self) -> &str {
+        match self {
+            Rule::When { name, .. }
+            | Rule::All { name, .. }
+            | Rule::Any { name, .. }
+            | Rule::Not { name, .. } => name,
+        }
+    }
+
+    pub fn evaluate(&self, facts: &Facts) -> Result<RuleOutcome> {
+        let outcome = match self {
+            Rule::When {
+                name,
+                when,
+                description,
+                icon,
+            } => {
+                let expr = parse(when).with_context(|| format!("rule {}", name))?;
+                let mut used = Vec::new();
+                let passed = expr
+                    .eval(facts, &mut used)
+                    .and_then(|v| v.as_bool())
+                    .with_context(|| format!("rule {}: {}", name, when))?;
+                let mut seen = std::collections::BTreeSet::new();
+                used.retain(|(k, _)| seen.insert(k.clone()));
+                let values: Vec<String> =
+                    used.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                RuleOutcome {
+                    name: name.clone(),
+                    passed,
+                    reason: format!(
+                        "{} [{}]",
+                        description.as_deref().unwrap_or(when),
+                        values.join(", ")
+                    ),
+                    icon: icon.clone(),
+                    children: vec![],
+                }
+            }
+            Rule::All { name, all, icon } => {
+                let children = all
+                    .iter()
+                    .map(|r| r.evaluate(facts))
+                    .collect::<Result<Vec<_>>>()?;
+                RuleOutcome {
+                    name: name.clone(),
+                    passed: children.iter().all(|c| c.passed),
+                    reason: summarize("all", &children),
+                    icon: icon.clone(),
+                    children,
+                }
+            }
+            Rule::Any { name, any, icon } => {
+                let children = any
+                    .iter()
+                    .map(|r| r.evaluate(facts))
+                    .collect::<Result<Vec<_>>>()?;
+                RuleOutcome {
+                    name: name.clone(),
+                    passed: children.iter().any(|c| c.passed),
+                    reason: summarize("any", &children),
+                    icon: icon.clone(),
+                    children,
+                }
+            }
+            Rule::Not { name, not, icon } => {
+                let child = not.evaluate(facts)?;
+                RuleOutcome {
+                    name: name.clone(),
+                    passed: !child.passed,
+                    reason: format!("not {} ({})", child.name, child.reason),
+                    icon: icon.clone(),
+                    children: vec![child],
+                }
+            }
+        };
+        Ok(outcome)
+    }
+}
+
+fn summarize(kind: &str, children: &[RuleOutcome]) -> String {
+    let passed: Vec<&str> = children
+        .iter()
+        .filter(|c| c.passed)
+        .map(|c| c.name.as_str())
+        .collect();
+    let failed: Vec<&str> = children
+        .iter()
+        .filter(|c| !c.passed)
+        .map(|c| c.name.as_str())
+        .collect();
+    format!(
+        "{} of {}: passed [{}] failed [{}]",
+        kind,
+        children.len(),
+        passed.join(", "),
+        failed.join(", ")
+    )
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == '.' && i + 1 < chars.len() && chars[i + 1].is_ascii_digit())
+        {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let n = text
+                .parse()
+                .map_err(|e| anyhow!("bad number {} {}", text, e))?;
+            tokens.push(Token::Number(n));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else {
+            let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let op = match two.as_str() {
+                "<=" => "<=",
+                ">=" => ">=",
+                "==" => "==",
+                "!=" => "!=",
+                _ => match c {
+                    '<' => "<",
+                    '>' => ">",
+                    '+' => "+",
+                    '-' => "-",
+                    '*' => "*",
+                    '/' => "/",
+                    _ => bail!("unexpected '{}' at {} in {}", c, i, src),
+                },
+            };
+            i += op.len();
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Literal(Fact),
+    Fact(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Between(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+    fn keyword(&mut self, word: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(w)) if w == word) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn or(&mut self) -> Result<Expr> {
+        let mut lhs = self.and()?;
+        while self.keyword("or") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+    fn and(&mut self) -> Result<Expr> {
+        let mut lhs = self.not()?;
+        while self.keyword("and") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.not()?));
+        }
+        Ok(lhs)
+    }
+    fn not(&mut self) -> Result<Expr> {
+        if self.keyword("not") {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.comparison()
+        }
+    }
+    fn comparison(&mut self) -> Result<Expr> {
+        let lhs = self.sum()?;
+        if self.keyword("between") {
+            let lo = self.sum()?;
+            if !self.keyword("and") {
+                bail!("expected 'and' after between");
+            }
+            let hi = self.sum()?;
+            return Ok(Expr::Between(Box::new(lhs), Box::new(lo), Box::new(hi)));
+        }
+        if let Some(Token::Op(op @ ("<" | "<=" | ">" | ">=" | "==" | "!="))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            let rhs = self.sum()?;
+            return Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+    fn sum(&mut self) -> Result<Expr> {
+        let mut lhs = self.product()?;
+        while let Some(Token::Op(op @ ("+" | "-"))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.product()?));
+        }
+        Ok(lhs)
+    }
+    fn product(&mut self) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op @ ("*" | "/"))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+    fn unary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Op("-")) => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(Token::Number(n)) => Ok(Expr::Literal(Fact::Number(n))),
+            Some(Token::Ident(w)) => match w.as_str() {
+                "true" => Ok(Expr::Literal(Fact::Bool(true))),
+                "false" => Ok(Expr::Literal(Fact::Bool(false))),
+                "and" | "or" | "not" | "between" => bail!("unexpected '{}'", w),
+                _ => Ok(Expr::Fact(w)),
+            },
+            Some(Token::LParen) => {
+                let inner = self.or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(inner),
+                    _ => bail!("expected ')'"),
+                }
+            }
+            Some(t) => bail!("unexpected {:?}", t),
+            None => bail!("unexpected end of expression"),
+        }
+    }
+}
+
+pub fn parse(src: &str) -> Result<Expr> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+    };
+    let expr = parser
+        .or()
+        .with_context(|| format!("could not parse '{}'", src))?;
+    if let Some(t) = parser.peek() {
+        bail!("could not parse '{}': unexpected {:?}", src, t);
+    }
+    Ok(expr)
+}
+
+impl Fact {
+    fn as_bool(self) -> Result<bool> {
+        match self {
+            Fact::Bool(b) => Ok(b),
+            Fact::Number(n) => bail!("expected true/false, got number {}", n),
+        }
+    }
+    fn as_number(self) -> Result<f64> {
+        match self {
+            Fact::Number(n) => Ok(n),
+            Fact::Bool(b) => bail!("expected a number, got {}", b),
+        }
+    }
+}
+
+impl Expr {
+    /// `used` collects every fact the expression read, for explanations
+    pub fn eval(&self, facts: &Facts, used: &mut Vec<(String, Fact)>) -> Result<Fact> {
+        let value = match self {
+            Expr::Literal(v) => *v,
+            Expr::Fact(name) => {
+                let v = *facts.get(name).ok_or_else(|| {
+                    anyhow!(
+                        "unknown fact {}, known facts are {}",
+                        name,
+                        facts.keys().cloned().collect::<Vec<_>>().join(", ")
+                    )
+                })?;
+                used.push((name.clone(), v));
+                v
+            }
+            Expr::Neg(e) => Fact::Number(-e.eval(facts, used)?.as_number()?),
+            Expr::Not(e) => Fact::Bool(!e.eval(facts, used)?.as_bool()?),
+            // both sides are evaluated so the explanation shows every input
+            Expr::And(a, b) => {
+                let a = a.eval(facts, used)?.as_bool()?;
+                let b = b.eval(facts, used)?.as_bool()?;
+                Fact::Bool(a && b)
+            }
+            Expr::Or(a, b) => {
+                let a = a.eval(facts, used)?.as_bool()?;
+                let b = b.eval(facts, used)?.as_bool()?;
+                Fact::Bool(a || b)
+            }
+            Expr::Between(x, lo, hi) => {
+                let x = x.eval(facts, used)?.as_number()?;
+                let lo = lo.eval(facts, used)?.as_number()?;
+                let hi = hi.eval(facts, used)?.as_number()?;
+                Fact::Bool(x >= lo && x <= hi)
+            }
+            Expr::Binary(op, a, b) => {
+                let a = a.eval(facts, used)?;
+                let b = b.eval(facts, used)?;
+                match *op {
+                    "==" => Fact::Bool(a == b),
+                    "!=" => Fact::Bool(a != b),
+                    _ => {
+                        let (a, b) = (a.as_number()?, b.as_number()?);
+                        match *op {
+                            "<" => Fact::Bool(a < b),
+                            "<=" => Fact::Bool(a <= b),
+                            ">" => Fact::Bool(a > b),
+                            ">=" => Fact::Bool(a >= b),
+                            "+" => Fact::Number(a + b),
+                            "-" => Fact::Number(a - b),
+                            "*" => Fact::Number(a * b),
+                            "/" => Fact::Number(a / b),
+                            _ => unreachable!(),
+                        }
+                    }
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts() -> Facts {
+        let mut facts = Facts::new();
+        facts.insert("outdoor.temp".into(), 70.0.into());
+        facts.insert("outdoor.dew_point".into(), 50.0.into());
+        facts.insert("indoor.dew_point".into(), 55.0.into());
+        facts.insert("aqi".into(), 3u64.into());
+        facts.insert("raining".into(), false.into());
+        facts
+    }
+
+    fn check(src: &str) -> bool {
+        parse(src)
+            .unwrap()
+            .eval(&facts(), &mut vec![])
+            .unwrap()
+            .as_bool()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert!(check("outdoor.temp between 55 and 80"));
+        assert!(!check("outdoor.temp between 71 and 80"));
+        assert!(check("outdoor.dew_point < indoor.dew_point - 2"));
+        assert!(!check("aqi <= 2"));
+        assert!(check("not raining"));
+        assert!(check("aqi <= 2 or not raining and outdoor.temp > 60"));
+        assert!(!check("(aqi <= 2 or raining) and outdoor.temp > 60"));
+        assert!(check("-outdoor.temp * 2 == -140"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("outdoor.temp between 55").is_err());
+        assert!(parse("outdoor.temp >").is_err());
+        assert!(parse("(aqi <= 2").is_err());
+        assert!(parse("aqi <= 2 2").is_err());
+        assert!(parse("aqi % 2").is_err());
+    }
+
+    #[test]
+    fn test_unknown_fact() {
+        let err = parse("pollen < 3")
+            .unwrap()
+            .eval(&facts(), &mut vec![])
+            .unwrap_err();
+        assert!(err.to_string().contains("unknown fact pollen"));
+    }
+
+    #[test]
+    fn test_rule_set() {
+        let rules: RuleSet = serde_json::from_str(
+            r#"{ "rules": [
+                { "name": "dry", "not": { "name": "raining", "when": "raining" } },
+                { "name": "air", "any": [
+                    { "name": "clean", "when": "aqi <= 2" },
+                    { "name": "mild", "when": "outdoor.temp between 55 and 80" }
+                ] }
+            ] }"#,
+        )
+        .unwrap();
+        rules.validate().unwrap();
+        let outcomes = rules.evaluate(&facts()).unwrap();
+        assert!(outcomes.iter().all(|o| o.passed));
+        assert_eq!(outcomes[1].children.len(), 2);
+        assert!(!outcomes[1].children[0].passed);
+        assert_eq!(outcomes[1].children[0].reason, "aqi <= 2 [aqi=3]");
+    }
+
+    #[test]
+    fn test_default_rules_parse() {
+        let rules = RuleSet::default();
+        rules.validate().unwrap();
+        assert_eq!(rules.rules[0].name(), "can_let_in_humidify");
+    }
+}
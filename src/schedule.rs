@@ -0,0 +1,256 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::decision::RuleOutcome;
+use crate::rules::Facts;
+
+// Time of day settings under "schedule" in rules.json:
+//
+// "schedule": {
+//   "timezone": "America/New_York",
+//   "allowed_hours": {
+//     "default": "06:00-22:00",
+//     "sat": "08:00-23:30",
+//     "sun": "00:00-01:00,08:00-23:30"
+//   }
+// }
+//
+// Outside the allowed hours the schedule vetoes opening windows. Ranges may wrap
+// midnight ("20:00-06:00"). Without a timezone the host's local time is used.
+//
+// Rules can refer to time.hour (22.5 is 10:30 PM), time.weekday (1 Monday to 7 Sunday)
+// and, when the weather has sunrise/sunset, time.is_night, time.minutes_to_sunrise and
+// time.minutes_to_sunset, e.g. "time.is_night and time.minutes_to_sunrise > 30".
+
+static WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Schedule {
+    pub timezone: Option<String>,
+    // weekday (mon..sun) or "default" to comma separated HH:MM-HH:MM ranges
+    pub allowed_hours: BTreeMap<String, String>,
+}
+
+fn parse_minutes(hhmm: &str) -> Result<u32> {
+    let (h, m) = hhmm
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| anyhow!("expected HH:MM, got {}", hhmm))?;
+    let (h, m): (u32, u32) = (h.parse()?, m.parse()?);
+    if h > 24 || m > 59 || (h == 24 && m > 0) {
+        bail!("{} is not a time of day", hhmm);
+    }
+    Ok(h * 60 + m)
+}
+
+fn parse_ranges(ranges: &str) -> Result<Vec<(u32, u32)>> {
+    ranges
+        .split(',')
+        .map(|range| {
+            let (start, end) = range
+                .split_once('-')
+                .ok_or_else(|| anyhow!("expected HH:MM-HH:MM, got {}", range))?;
+            Ok((parse_minutes(start)?, parse_minutes(end)?))
+        })
+        .collect()
+}
+
+fn in_ranges(ranges: &[(u32, u32)], minute: u32) -> bool {
+    ranges.iter().any(|&(start, end)| {
+        if start <= end {
+            minute >= start && minute < end
+        } else {
+            minute >= start || minute < end
+        }
+    })
+}
+
+impl Schedule {
+    pub fn validate(&self) -> Result<()> {
+        self.tz()?;
+        for (day, ranges) in &self.allowed_hours {
+            if day != "default" && !WEEKDAYS.contains(&day.as_str()) {
+                bail!(
+                    "schedule: {} is not default or one of {}",
+                    day,
+                    WEEKDAYS.join(", ")
+                );
+            }
+            parse_ranges(ranges).with_context(|| format!("schedule: allowed_hours.{}", day))?;
+        }
+        Ok(())
+    }
+
+    fn tz(&self) -> Result<Option<Tz>> {
+        match &self.timezone {
+            Some(name) => {
+                Ok(Some(name.parse().map_err(|e| {
+                    anyhow!("schedule: unknown timezone {} {}", name, e)
+                })?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// wall clock time in the configured timezone
+    pub fn local_time(&self, now: DateTime<Utc>) -> Result<NaiveDateTime> {
+        Ok(match self.tz()? {
+            Some(tz) => now.with_timezone(&tz).naive_local(),
+            None => now.with_timezone(&Local).naive_local(),
+        })
+    }
+
+    pub fn facts(
+        &self,
+        now: DateTime<Utc>,
+        sunrise: Option<i64>,
+        sunset: Option<i64>,
+    ) -> Result<Facts> {
+        let local = self.local_time(now)?;
+        let mut facts = Facts::new();
+        facts.insert(
+            "time.hour".into(),
+            (local.hour() as f64 + local.minute() as f64 / 60.0).into(),
+        );
+        facts.insert(
+            "time.weekday".into(),
+            (local.weekday().number_from_monday() as u64).into(),
+        );
+        if let (Some(sunrise), Some(sunset)) = (sunrise, sunset) {
+            let now = now.timestamp();
+            // OWM reports the sunrise and sunset of the day weather.json was fetched, which is
+            // days off from now with an old weather.json or a past --now. Moved in whole days
+            // to the next one after now, a day or two changes them by only a few minutes.
+            let next = |t: i64| now + (t - now - 1).rem_euclid(86400) + 1;
+            facts.insert(
+                "time.is_night".into(),
+                (next(sunrise) < next(sunset)).into(),
+            );
+            facts.insert(
+                "time.minutes_to_sunrise".into(),
+                (((next(sunrise) - now) / 60) as u64).into(),
+            );
+            facts.insert(
+                "time.minutes_to_sunset".into(),
+                (((next(sunset) - now) / 60) as u64).into(),
+            );
+        }
+        Ok(facts)
+    }
+
+    /// a veto when allowed_hours excludes the current time, None when no hours are configured
+    pub fn evaluate(&self, now: DateTime<Utc>) -> Result<Option<RuleOutcome>> {
+        let local = self.local_time(now)?;
+        let day = WEEKDAYS[local.weekday().num_days_from_monday() as usize];
+        let Some(ranges) = self
+            .allowed_hours
+            .get(day)
+            .or_else(|| self.allowed_hours.get("default"))
+        else {
+            return Ok(None);
+        };
+        let allowed = in_ranges(&parse_ranges(ranges)?, local.hour() * 60 + local.minute());
+        Ok(Some(RuleOutcome {
+            name: "allowed_hours".into(),
+            passed: allowed,
+            reason: format!("{} {} allowed {}", day, local.format("%H:%M"), ranges),
+            icon: Some("🕙".into()),
+            children: vec![],
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Fact;
+    use chrono::TimeZone;
+
+    fn schedule(allowed_hours: &[(&str, &str)]) -> Schedule {
+        Schedule {
+            timezone: Some("America/New_York".into()),
+            allowed_hours: allowed_hours
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    // Saturday 2023-09-16 22:30 in New York
+    fn saturday_night() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 9, 17, 2, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn test_allowed_hours() {
+        let s = schedule(&[("default", "06:00-22:00")]);
+        s.validate().unwrap();
+        assert!(!s.evaluate(saturday_night()).unwrap().unwrap().passed);
+        let s = schedule(&[("default", "06:00-22:00"), ("sat", "08:00-23:00")]);
+        assert!(s.evaluate(saturday_night()).unwrap().unwrap().passed);
+        let s = schedule(&[("sat", "20:00-06:00")]);
+        assert!(s.evaluate(saturday_night()).unwrap().unwrap().passed);
+        assert!(schedule(&[]).evaluate(saturday_night()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_schedule() {
+        assert!(schedule(&[("someday", "06:00-22:00")]).validate().is_err());
+        assert!(schedule(&[("default", "6-22")]).validate().is_err());
+        assert!(schedule(&[("default", "06:00-25:00")]).validate().is_err());
+        let mut s = schedule(&[]);
+        s.timezone = Some("Mars/Olympus_Mons".into());
+        assert!(s.validate().is_err());
+    }
+
+    #[test]
+    fn test_time_facts() {
+        let now = saturday_night();
+        // sunrise 06:50 and sunset 19:15 New York time that day
+        let sunrise = Utc
+            .with_ymd_and_hms(2023, 9, 16, 10, 50, 0)
+            .unwrap()
+            .timestamp();
+        let sunset = Utc
+            .with_ymd_and_hms(2023, 9, 16, 23, 15, 0)
+            .unwrap()
+            .timestamp();
+        let facts = schedule(&[])
+            .facts(now, Some(sunrise), Some(sunset))
+            .unwrap();
+        assert_eq!(facts["time.hour"], Fact::Number(22.5));
+        assert_eq!(facts["time.weekday"], Fact::Number(6.0));
+        assert_eq!(facts["time.is_night"], Fact::Bool(true));
+        assert_eq!(facts["time.minutes_to_sunrise"], Fact::Number(500.0));
+    }
+
+    #[test]
+    fn test_time_facts_from_old_weather() {
+        let now = saturday_night();
+        // sunrise and sunset two days before, from a weather.json that stopped updating
+        let sunrise = Utc
+            .with_ymd_and_hms(2023, 9, 14, 10, 50, 0)
+            .unwrap()
+            .timestamp();
+        let sunset = Utc
+            .with_ymd_and_hms(2023, 9, 14, 23, 15, 0)
+            .unwrap()
+            .timestamp();
+        let s = schedule(&[]);
+        let facts = s.facts(now, Some(sunrise), Some(sunset)).unwrap();
+        assert_eq!(facts["time.is_night"], Fact::Bool(true));
+        assert_eq!(facts["time.minutes_to_sunrise"], Fact::Number(500.0));
+        assert_eq!(facts["time.minutes_to_sunset"], Fact::Number(1245.0));
+
+        // Saturday 16:30 in New York, between sunrise and sunset
+        let afternoon = Utc.with_ymd_and_hms(2023, 9, 16, 20, 30, 0).unwrap();
+        let facts = s.facts(afternoon, Some(sunrise), Some(sunset)).unwrap();
+        assert_eq!(facts["time.is_night"], Fact::Bool(false));
+        assert_eq!(facts["time.minutes_to_sunset"], Fact::Number(165.0));
+        assert_eq!(facts["time.minutes_to_sunrise"], Fact::Number(860.0));
+    }
+}
@@ -0,0 +1,325 @@
+use anyhow::{anyhow, bail, Context, Result};
+use scraper::{ElementRef, Html};
+use std::collections::HashSet;
+
+use crate::cli::Failure;
+
+// Text out of an HTML page picked by a CSS selector or an XPath, for weather stations that only
+// publish a web page. XPaths start with /, anything else is CSS.
+//
+// The XPaths are the subset a browser's "copy XPath" or lxml scripts use: child (/) and
+// descendant (//) steps on element names or *, position predicates like tr[3] counting among
+// siblings with that name, attribute predicates like table[@border="1"] or td[@class], and a
+// final text() for the element's own text rather than all the text inside it.
+
+pub enum TextSelector {
+    Css(scraper::Selector),
+    XPath(XPath),
+}
+
+impl TextSelector {
+    pub fn parse(selector: &str) -> Result<TextSelector> {
+        let parsed = if selector.starts_with('/') {
+            XPath::parse(selector).map(TextSelector::XPath)
+        } else {
+            scraper::Selector::parse(selector)
+                .map(TextSelector::Css)
+                .map_err(|e| anyhow!("{:?}", e))
+        };
+        parsed
+            .with_context(|| format!("TextSelector::parse: bad selector {}", selector))
+            .context(Failure::Config)
+    }
+
+    /// whitespace collapsed text of the first match
+    pub fn text(&self, html: &Html) -> Option<String> {
+        let text = match self {
+            TextSelector::Css(selector) => html.select(selector).next()?.text().collect(),
+            TextSelector::XPath(xpath) => {
+                let element = *xpath.evaluate(html).first()?;
+                if xpath.text {
+                    element
+                        .children()
+                        .filter_map(|node| node.value().as_text())
+                        .map(|text| text.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                } else {
+                    element.text().collect()
+                }
+            }
+        };
+        Some(text.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// the first number in text like "72.3°F" or "Humidity: 45 %"
+pub fn first_number(text: &str) -> Option<f64> {
+    let start = text.char_indices().position(|(i, c)| {
+        c.is_ascii_digit()
+            || (c == '-'
+                && text[i + 1..]
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_digit()))
+    })?;
+    let number: String = text
+        .chars()
+        .skip(start)
+        .enumerate()
+        .take_while(|(i, c)| c.is_ascii_digit() || *c == '.' || (*i == 0 && *c == '-'))
+        .map(|(_, c)| c)
+        .collect();
+    number.trim_end_matches('.').parse().ok()
+}
+
+pub struct XPath {
+    steps: Vec<Step>,
+    text: bool,
+}
+
+struct Step {
+    descendant: bool,
+    // lowercase element name or *
+    name: String,
+    predicates: Vec<Predicate>,
+}
+
+enum Predicate {
+    // 1 based
+    Position(usize),
+    Attribute(String, Option<String>),
+}
+
+impl Predicate {
+    fn parse(predicate: &str) -> Result<Predicate> {
+        if let Ok(position) = predicate.parse::<usize>() {
+            if position == 0 {
+                bail!("positions start at 1");
+            }
+            return Ok(Predicate::Position(position));
+        }
+        let Some(attribute) = predicate.strip_prefix('@') else {
+            bail!("unsupported predicate [{}]", predicate);
+        };
+        match attribute.split_once('=') {
+            None => Ok(Predicate::Attribute(attribute.trim().into(), None)),
+            Some((name, value)) => {
+                let value = value.trim();
+                let unquoted = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .or_else(|| {
+                        value
+                            .strip_prefix('\'')
+                            .and_then(|value| value.strip_suffix('\''))
+                    })
+                    .with_context(|| format!("unquoted value in [{}]", predicate))?;
+                Ok(Predicate::Attribute(
+                    name.trim().into(),
+                    Some(unquoted.into()),
+                ))
+            }
+        }
+    }
+
+    fn filter<'a>(&self, elements: Vec<ElementRef<'a>>) -> Vec<ElementRef<'a>> {
+        match self {
+            Predicate::Position(position) => {
+                elements.into_iter().skip(position - 1).take(1).collect()
+            }
+            Predicate::Attribute(name, value) => elements
+                .into_iter()
+                .filter(|element| match (element.value().attr(name), value) {
+                    (Some(actual), Some(value)) => actual == value,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl XPath {
+    pub fn parse(xpath: &str) -> Result<XPath> {
+        let mut steps = vec![];
+        let mut text = false;
+        let mut rest = xpath.trim();
+        while !rest.is_empty() {
+            if text {
+                bail!("text() must be last");
+            }
+            let Some(after_slash) = rest.strip_prefix('/') else {
+                bail!("expected / at {}", rest);
+            };
+            let (descendant, after_slash) = match after_slash.strip_prefix('/') {
+                Some(after) => (true, after),
+                None => (false, after_slash),
+            };
+            if let Some(after) = after_slash.strip_prefix("text()") {
+                text = true;
+                rest = after;
+                continue;
+            }
+            let name_end = after_slash
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '*'))
+                .unwrap_or(after_slash.len());
+            let name = &after_slash[..name_end];
+            if name.is_empty() {
+                bail!("expected an element name at {}", after_slash);
+            }
+            rest = &after_slash[name_end..];
+            let mut predicates = vec![];
+            while let Some(after_bracket) = rest.strip_prefix('[') {
+                let end = closing_bracket(after_bracket)
+                    .with_context(|| format!("unclosed [ at {}", rest))?;
+                predicates.push(Predicate::parse(after_bracket[..end].trim())?);
+                rest = &after_bracket[end + 1..];
+            }
+            steps.push(Step {
+                descendant,
+                name: name.to_lowercase(),
+                predicates,
+            });
+        }
+        if steps.is_empty() {
+            bail!("no element in {}", xpath);
+        }
+        Ok(XPath { steps, text })
+    }
+
+    /// matching elements, per parent in document order
+    fn evaluate<'a>(&self, html: &'a Html) -> Vec<ElementRef<'a>> {
+        let root = html.root_element();
+        // None is the document itself, whose only child is the root element
+        let mut context: Option<Vec<ElementRef<'a>>> = None;
+        for step in &self.steps {
+            // the elements a step picks from, grouped by parent so positions count per parent
+            let groups: Vec<Vec<ElementRef<'a>>> = match (&context, step.descendant) {
+                (None, false) => vec![vec![root]],
+                (None, true) => std::iter::once(vec![root])
+                    .chain(descendants(root).map(children))
+                    .collect(),
+                (Some(context), false) => context.iter().copied().map(children).collect(),
+                (Some(context), true) => context
+                    .iter()
+                    .flat_map(|element| descendants(*element))
+                    .map(children)
+                    .collect(),
+            };
+            let mut seen = HashSet::new();
+            let mut matched = vec![];
+            for group in groups {
+                let mut elements: Vec<ElementRef<'a>> = group
+                    .into_iter()
+                    .filter(|element| step.name == "*" || element.value().name() == step.name)
+                    .collect();
+                for predicate in &step.predicates {
+                    elements = predicate.filter(elements);
+                }
+                matched.extend(
+                    elements
+                        .into_iter()
+                        .filter(|element| seen.insert(element.id())),
+                );
+            }
+            context = Some(matched);
+        }
+        context.unwrap_or_default()
+    }
+}
+
+// the element and everything inside it
+fn descendants(element: ElementRef) -> impl Iterator<Item = ElementRef> {
+    element.descendants().filter_map(ElementRef::wrap)
+}
+
+fn children(element: ElementRef) -> Vec<ElementRef> {
+    element.children().filter_map(ElementRef::wrap).collect()
+}
+
+// index of the ] closing a predicate, skipping quoted values
+fn closing_bracket(predicate: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in predicate.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, ']') => return Some(i),
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<html><body>
+        <table border="0"><tr><td>menu</td><td>1.0</td></tr></table>
+        <table border="1">
+          <tr><td>Station</td><td class="name">Fairfax</td></tr>
+          <tr><td>Temperature</td><td><b>72.3 °F</b></td></tr>
+          <tr><td>Humidity</td><td><b>45<small>%</small></b></td></tr>
+        </table>
+    </body></html>"#;
+
+    fn text(selector: &str) -> Option<String> {
+        TextSelector::parse(selector)
+            .unwrap()
+            .text(&Html::parse_document(PAGE))
+    }
+
+    #[test]
+    fn test_xpath() {
+        assert_eq!(text("//tr[2]/td[2]/b/text()").as_deref(), Some("72.3 °F"));
+        assert_eq!(text("//tr[3]/td[2]/b/text()").as_deref(), Some("45"));
+        assert_eq!(text("//tr[3]/td[2]").as_deref(), Some("45%"));
+        // positions count per parent, the first table's row comes first
+        assert_eq!(text("//tr[1]/td[1]").as_deref(), Some("menu"));
+        assert_eq!(
+            text(r#"//table[@border="1"]//tr[1]/td[1]"#).as_deref(),
+            Some("Station")
+        );
+        assert_eq!(text("//td[@class]").as_deref(), Some("Fairfax"));
+        assert_eq!(
+            text("/html/body/table[2]/tbody/tr[2]/td[2]/b").as_deref(),
+            Some("72.3 °F")
+        );
+        assert_eq!(text("//tr[9]/td[2]"), None);
+    }
+
+    #[test]
+    fn test_css() {
+        assert_eq!(
+            text(r#"table[border="1"] tr:nth-of-type(2) td:nth-child(2)"#).as_deref(),
+            Some("72.3 °F")
+        );
+        assert_eq!(text("td.name").as_deref(), Some("Fairfax"));
+    }
+
+    #[test]
+    fn test_bad_selectors() {
+        for selector in [
+            "//tr[0]",
+            "//tr[",
+            "//tr[last()]",
+            "//text()/td",
+            "//",
+            "td[[",
+        ] {
+            let e = TextSelector::parse(selector).err().unwrap();
+            assert_eq!(crate::cli::exit_code(&e), 78, "{}", selector);
+        }
+    }
+
+    #[test]
+    fn test_first_number() {
+        assert_eq!(first_number("72.3 °F"), Some(72.3));
+        assert_eq!(first_number("Humidity: 45 %"), Some(45.0));
+        assert_eq!(first_number("-3.5°F"), Some(-3.5));
+        assert_eq!(first_number("5 - 10"), Some(5.0));
+        assert_eq!(first_number("n/a"), None);
+    }
+}
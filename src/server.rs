@@ -0,0 +1,247 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use tiny_http::{Header, Response, Server};
+use tracing::{info, warn};
+
+use crate::history::{aggregate, metric_names, parse_time, History, Interval, Sample};
+use crate::metrics;
+use crate::pollution::aqi_description;
+use crate::psychro::dew_point;
+
+// `iloveair serve`: a small HTTP server over the history database for phones on the LAN.
+//
+//   GET /                 page with the recommendation and 24 hour charts
+//   GET /api/current      latest indoor, outdoor, pollution and decision
+//   GET /api/history?metric=indoor.temp&from=24h&to=&interval=hourly&device=
+//   GET /metrics          Prometheus text format
+//
+// Requests are answered one at a time, which is plenty for a household.
+
+static INDEX_HTML: &str = include_str!("web/index.html");
+
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Reply {
+    fn json(status: u16, body: Value) -> Reply {
+        Reply {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+}
+
+fn latest(history: &History, metric: &str) -> Result<Option<Sample>> {
+    history.latest(metric, None)
+}
+
+pub fn current(history: &History) -> Result<Value> {
+    let value = |sample: &Option<Sample>| sample.as_ref().map(|s| s.value);
+    let indoor_temp = latest(history, "indoor.temp")?;
+    let indoor_humidity = value(&latest(history, "indoor.humidity")?);
+    let outdoor_temp = latest(history, "outdoor.temp")?;
+    let outdoor_humidity = value(&latest(history, "outdoor.humidity")?);
+    let aqi = latest(history, "aqi")?;
+    Ok(json!({
+        "indoor": {
+            "time": indoor_temp.as_ref().map(|s| s.time),
+            "device": indoor_temp.as_ref().and_then(|s| s.device.clone()),
+            "temp": value(&indoor_temp),
+            "humidity": indoor_humidity,
+            "dew_point": value(&indoor_temp).zip(indoor_humidity).map(|(t, h)| dew_point(t, h)),
+            "radon": value(&latest(history, "indoor.radon")?),
+            "co2": value(&latest(history, "indoor.co2")?),
+            "voc": value(&latest(history, "indoor.voc")?),
+        },
+        "outdoor": {
+            "time": outdoor_temp.as_ref().map(|s| s.time),
+            "location": outdoor_temp.as_ref().and_then(|s| s.device.clone()),
+            "temp": value(&outdoor_temp),
+            "humidity": outdoor_humidity,
+            "dew_point": value(&outdoor_temp).zip(outdoor_humidity).map(|(t, h)| dew_point(t, h)),
+        },
+        "pollution": {
+            "time": aqi.as_ref().map(|s| s.time),
+            "aqi": value(&aqi),
+            "description": value(&aqi).map(|aqi| aqi_description(aqi as u64)),
+        },
+        "decision": history.latest_decision()?,
+    }))
+}
+
+struct HistoryQuery<'a> {
+    metric: &'a str,
+    device: Option<&'a str>,
+    from: i64,
+    to: Option<i64>,
+    interval: Interval,
+}
+
+fn parse_history_query(query: &[(String, String)], now: DateTime<Utc>) -> Result<HistoryQuery<'_>> {
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(k, v)| k == name && !v.is_empty())
+            .map(|(_, v)| v.as_str())
+    };
+    let Some(metric) = param("metric") else {
+        bail!("metric is required, one of {}", metric_names().join(", "));
+    };
+    if !metric_names().contains(&metric) {
+        bail!(
+            "unknown metric {}, expected one of {}",
+            metric,
+            metric_names().join(", ")
+        );
+    }
+    Ok(HistoryQuery {
+        metric,
+        device: param("device"),
+        from: parse_time(param("from").unwrap_or("24h"), now)?,
+        to: match param("to") {
+            Some(to) => Some(parse_time(to, now)?),
+            None => None,
+        },
+        interval: param("interval").unwrap_or("raw").parse()?,
+    })
+}
+
+fn query_history(history: &History, query: &HistoryQuery) -> Result<Value> {
+    let samples = history.samples(query.metric, query.device, Some(query.from), query.to)?;
+    Ok(match query.interval {
+        Interval::Raw => serde_json::to_value(samples)?,
+        _ => serde_json::to_value(aggregate(&samples, query.interval, None))?,
+    })
+}
+
+/// answers one request, `url` is the path and query as the client sent it
+pub fn route(history: &History, method: &str, url: &str, now: DateTime<Utc>) -> Reply {
+    if method != "GET" {
+        return Reply::json(405, json!({"error": "only GET is supported"}));
+    }
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let query: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let result = match path {
+        "/" | "/index.html" => {
+            return Reply {
+                status: 200,
+                content_type: "text/html; charset=utf-8",
+                body: INDEX_HTML.into(),
+            }
+        }
+        "/api/current" => current(history),
+        "/metrics" => {
+            return match metrics::render(history) {
+                Ok(body) => Reply {
+                    status: 200,
+                    content_type: "text/plain; version=0.0.4",
+                    body,
+                },
+                Err(e) => Reply::json(500, json!({"error": e.to_string()})),
+            }
+        }
+        "/api/history" => match parse_history_query(&query, now) {
+            Ok(query) => query_history(history, &query),
+            Err(e) => return Reply::json(400, json!({"error": e.to_string()})),
+        },
+        _ => return Reply::json(404, json!({"error": format!("no such page {}", path)})),
+    };
+    match result {
+        Ok(body) => Reply::json(200, body),
+        Err(e) => Reply::json(500, json!({"error": e.to_string()})),
+    }
+}
+
+pub fn serve(history_db_path: &str, listen: &str) -> Result<()> {
+    let history = History::open(history_db_path)?;
+    let server =
+        Server::http(listen).map_err(|e| anyhow!("serve: could not listen on {} {}", listen, e))?;
+    info!("listening: http://{}", listen);
+    for request in server.incoming_requests() {
+        let reply = route(
+            &history,
+            request.method().as_str(),
+            request.url(),
+            Utc::now(),
+        );
+        let content_type = Header::from_bytes("Content-Type", reply.content_type)
+            .map_err(|_| anyhow!("serve: bad content type {}", reply.content_type))?;
+        let response = Response::from_string(reply.body)
+            .with_status_code(reply.status)
+            .with_header(content_type);
+        if let Err(e) = request.respond(response) {
+            warn!("serve: could not respond {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> History {
+        let history = History::open_in_memory().unwrap();
+        history
+            .record_indoor(
+                "2930",
+                &json!({"temp": 20.0, "humidity": 50.0, "radonShortTermAvg": 40.0, "time": 1694880000}),
+            )
+            .unwrap();
+        history
+            .record_weather(&json!({"dt": 1694880000, "name": "Boston", "main": {"temp": 61.5, "humidity": 80}}))
+            .unwrap();
+        history
+    }
+
+    fn get(url: &str) -> (u16, Value) {
+        let now = DateTime::from_timestamp(1694880600, 0).unwrap();
+        let reply = route(&history(), "GET", url, now);
+        (
+            reply.status,
+            serde_json::from_str(&reply.body).unwrap_or(Value::Null),
+        )
+    }
+
+    #[test]
+    fn test_current() {
+        let (status, body) = get("/api/current");
+        assert_eq!(status, 200);
+        assert_eq!(body["indoor"]["temp"], 68.0);
+        assert_eq!(body["outdoor"]["location"], "Boston");
+        assert_eq!(body["pollution"]["aqi"], Value::Null);
+        assert_eq!(body["decision"], Value::Null);
+    }
+
+    #[test]
+    fn test_history() {
+        let (status, body) = get("/api/history?metric=indoor.radon&from=1h");
+        assert_eq!(status, 200);
+        assert_eq!(body[0]["value"], 40.0);
+        let (status, body) = get("/api/history?metric=indoor.radon&from=1h&interval=hourly");
+        assert_eq!(status, 200);
+        assert_eq!(body[0]["count"], 1);
+        let (status, body) = get("/api/history?metric=nope");
+        assert_eq!(status, 400);
+        assert!(body["error"].as_str().unwrap().contains("unknown metric"));
+    }
+
+    #[test]
+    fn test_pages() {
+        let reply = route(&history(), "GET", "/", Utc::now());
+        assert_eq!(reply.status, 200);
+        assert!(reply.body.contains("/api/current"));
+        assert_eq!(get("/nope").0, 404);
+        let reply = route(&history(), "GET", "/metrics", Utc::now());
+        assert_eq!(reply.status, 200);
+        assert!(reply.body.contains("iloveair_indoor_radon"));
+        assert_eq!(route(&history(), "POST", "/", Utc::now()).status, 405);
+    }
+}
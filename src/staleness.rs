@@ -0,0 +1,145 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::decision::Inputs;
+
+// A reading older than its max age makes the decision unknown instead of deciding from data that
+// may be days old because a fetch has been failing. The age is taken from the reading's own
+// time (Airthings `time`, OWM `dt`), not the cache file. Configured under "max_age" in
+// rules.json, in minutes, null to disable:
+//
+// "max_age": { "indoor": 180, "outdoor": 180, "pollution": 360 }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaxAgeSettings {
+    pub indoor: Option<i64>,
+    pub outdoor: Option<i64>,
+    pub pollution: Option<i64>,
+}
+
+impl Default for MaxAgeSettings {
+    fn default() -> Self {
+        MaxAgeSettings {
+            indoor: Some(180),
+            outdoor: Some(180),
+            // OWM air pollution only updates hourly
+            pollution: Some(360),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StaleInput {
+    // indoor, outdoor or pollution
+    pub input: String,
+    // unix seconds of the reading
+    pub time: i64,
+    pub age_minutes: i64,
+    pub max_age_minutes: i64,
+    pub reason: String,
+}
+
+impl MaxAgeSettings {
+    /// inputs without a reading time can't be checked and are assumed fresh
+    pub fn check(&self, inputs: &Inputs, now: DateTime<Utc>) -> Vec<StaleInput> {
+        let readings = [
+            ("indoor", inputs.indoor.time, self.indoor),
+            ("outdoor", inputs.outdoor.time, self.outdoor),
+            (
+                "pollution",
+                inputs.pollution.as_ref().and_then(|p| p.time),
+                self.pollution,
+            ),
+        ];
+        readings
+            .into_iter()
+            .filter_map(|(input, time, max_age)| {
+                let (time, max_age) = (time?, max_age?);
+                let age_minutes = (now.timestamp() - time) / 60;
+                (age_minutes > max_age).then(|| StaleInput {
+                    input: input.into(),
+                    time,
+                    age_minutes,
+                    max_age_minutes: max_age,
+                    reason: format!(
+                        "{} reading is {} old, max {}",
+                        input,
+                        minutes_text(age_minutes),
+                        minutes_text(max_age)
+                    ),
+                })
+            })
+            .collect()
+    }
+}
+
+fn minutes_text(minutes: i64) -> String {
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    match (days, hours, minutes) {
+        (0, 0, m) => format!("{}m", m),
+        (0, h, 0) => format!("{}h", h),
+        (0, h, m) => format!("{}h{}m", h, m),
+        (d, 0, _) => format!("{}d", d),
+        (d, h, _) => format!("{}d{}h", d, h),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decision::{AirQuality, Conditions};
+
+    fn inputs(indoor_time: Option<i64>, outdoor_time: Option<i64>) -> Inputs {
+        Inputs {
+            indoor: Conditions {
+                temp: 70.0,
+                humidity: 50,
+                time: indoor_time,
+            },
+            outdoor: Conditions {
+                temp: 65.0,
+                humidity: 55,
+                time: outdoor_time,
+            },
+            pollution: Some(AirQuality {
+                aqi: 2,
+                time: Some(1694880000),
+            }),
+            weather: None,
+            indoor_air: None,
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let now = DateTime::from_timestamp(1694880000 + 3 * 3600, 0).unwrap();
+        let settings = MaxAgeSettings::default();
+        assert_eq!(
+            settings.check(&inputs(Some(1694880000), Some(1694880000)), now),
+            vec![]
+        );
+        let stale = settings.check(&inputs(Some(1694880000 - 2 * 86400), None), now);
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].input, "indoor");
+        assert_eq!(stale[0].age_minutes, 51 * 60);
+        assert_eq!(stale[0].reason, "indoor reading is 2d3h old, max 3h");
+
+        let disabled = MaxAgeSettings {
+            indoor: None,
+            ..Default::default()
+        };
+        assert_eq!(
+            disabled.check(&inputs(Some(1694880000 - 2 * 86400), None), now),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_minutes_text() {
+        assert_eq!(minutes_text(45), "45m");
+        assert_eq!(minutes_text(90), "1h30m");
+        assert_eq!(minutes_text(48 * 60 + 5), "2d");
+        assert_eq!(minutes_text(27087 * 60), "1128d15h");
+    }
+}
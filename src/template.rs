@@ -0,0 +1,192 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{NaiveDateTime, Utc};
+use handlebars::{handlebars_helper, no_escape, Handlebars};
+use serde_json::{json, Value};
+
+use crate::audit::read_to_string_with_shellexpand;
+use crate::decision::Decision;
+use crate::pretty::PrettyBool;
+use crate::report::updown;
+use crate::schedule::Schedule;
+
+// (name, source, is_html)
+static BUILTIN_TEMPLATES: &[(&str, &str, bool)] = &[
+    ("text", include_str!("templates/text.hbs"), false),
+    ("pushover", include_str!("templates/pushover.hbs"), false),
+    ("plain", include_str!("templates/plain.hbs"), false),
+    ("sms", include_str!("templates/sms.hbs"), false),
+    ("markdown", include_str!("templates/markdown.hbs"), false),
+    ("html", include_str!("templates/html.hbs"), true),
+];
+
+pub fn builtin_template_names() -> Vec<&'static str> {
+    BUILTIN_TEMPLATES.iter().map(|(name, _, _)| *name).collect()
+}
+
+handlebars_helper!(check: |b: bool| PrettyBool::new(b).to_string());
+handlebars_helper!(yesno: |b: bool| if b { "yes" } else { "no" });
+// serde_json prints 70.0 as "70.0", the reports always printed f64 with Display
+handlebars_helper!(num: |v: Value| match v.as_f64() {
+    Some(f) => f.to_string(),
+    None => v.to_string(),
+});
+handlebars_helper!(arrow: |fst: Value, snd: Value| match (fst.as_f64(), snd.as_f64()) {
+    (Some(a), Some(b)) => updown(a, b),
+    _ => "?".to_string(),
+});
+
+fn registry(escape_html: bool) -> Result<Handlebars<'static>> {
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(false);
+    if !escape_html {
+        handlebars.register_escape_fn(no_escape);
+    }
+    handlebars.register_helper("check", Box::new(check));
+    handlebars.register_helper("yesno", Box::new(yesno));
+    handlebars.register_helper("num", Box::new(num));
+    handlebars.register_helper("updown", Box::new(arrow));
+    for (name, source, _) in BUILTIN_TEMPLATES {
+        handlebars
+            .register_template_string(name, source)
+            .map_err(|e| anyhow!("template: builtin {} does not compile {}", name, e))?;
+    }
+    Ok(handlebars)
+}
+
+fn report_time(decision: &Decision) -> Result<NaiveDateTime> {
+    let schedule = Schedule {
+        timezone: decision.timezone.clone(),
+        ..Default::default()
+    };
+    schedule.local_time(decision.generated_at.with_timezone(&Utc))
+}
+
+/// every input value, threshold and rule outcome of the decision plus
+/// `time` (formatted local time) and `rule.<name>` for lookups by rule name
+pub fn template_context(decision: &Decision) -> Result<Value> {
+    let mut context = serde_json::to_value(decision)?;
+    let rule: serde_json::Map<String, Value> = decision
+        .rules
+        .iter()
+        .map(|r| Ok((r.name.clone(), serde_json::to_value(r)?)))
+        .collect::<Result<_>>()?;
+    context["rule"] = Value::Object(rule);
+    context["time"] = json!(report_time(decision)?
+        .format("%A %Y-%m-%d %I:%M %p")
+        .to_string());
+    Ok(context)
+}
+
+/// `template` is either the name of a builtin template or the path to a handlebars file.
+/// Files ending in .html are html-escaped.
+pub fn render(template: &str, decision: &Decision) -> Result<String> {
+    let context = template_context(decision)?;
+    if let Some((name, _, is_html)) = BUILTIN_TEMPLATES.iter().find(|(n, _, _)| *n == template) {
+        return registry(*is_html)?
+            .render(name, &context)
+            .map_err(|e| anyhow!("template: could not render {} {}", name, e));
+    }
+    let source = read_to_string_with_shellexpand(&template.to_string()).with_context(|| {
+        format!(
+            "template: {} is not a file or one of {}",
+            template,
+            builtin_template_names().join(", ")
+        )
+    })?;
+    registry(template.ends_with(".html"))?
+        .render_template(&source, &context)
+        .map_err(|e| anyhow!("template: could not render {} {}", template, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decision::{decide, Conditions, Inputs, Thresholds};
+    use crate::rules::RuleSet;
+    use crate::staleness::StaleInput;
+
+    fn decision() -> Decision {
+        decide(
+            Inputs {
+                indoor: Conditions {
+                    temp: 70.5,
+                    humidity: 50,
+                    time: None,
+                },
+                outdoor: Conditions {
+                    temp: 90.0,
+                    humidity: 55,
+                    time: None,
+                },
+                pollution: None,
+                weather: None,
+                indoor_air: None,
+            },
+            Thresholds {
+                max_humidity: 60,
+                min_temp: 50.0,
+                max_temp: 84.0,
+            },
+            &RuleSet::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_builtin_templates_render() {
+        let decision = decision();
+        for name in builtin_template_names() {
+            let out = render(name, &decision).unwrap();
+            assert!(out.contains("70.5"), "{} {}", name, out);
+        }
+    }
+
+    #[test]
+    fn test_plain_has_no_emoji() {
+        let out = render("plain", &decision()).unwrap();
+        assert!(out.is_ascii(), "{}", out);
+        assert!(out.contains("Windows: closed\n"));
+        assert!(out.contains("can_let_in_temperature: fail ("));
+    }
+
+    #[test]
+    fn test_sms_is_short() {
+        let out = render("sms", &decision()).unwrap();
+        assert!(out.len() <= 160, "{}", out);
+        assert!(out.starts_with("CLOSE windows."));
+    }
+
+    #[test]
+    fn test_html_is_escaped() {
+        let mut decision = decision();
+        decision.rules[0].reason = "<script>".into();
+        let out = render("html", &decision).unwrap();
+        assert!(out.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn test_stale_input_is_unknown() {
+        let mut decision = decision();
+        decision.stale_inputs = vec![StaleInput {
+            input: "outdoor".into(),
+            time: 1694880000,
+            age_minutes: 600,
+            max_age_minutes: 180,
+            reason: "outdoor reading is 10h old, max 3h".into(),
+        }];
+        let out = render("plain", &decision).unwrap();
+        assert!(out.contains("Windows: unknown\n"), "{}", out);
+        assert!(out.contains("stale outdoor: outdoor reading is 10h old, max 3h\n"));
+        let out = render("sms", &decision).unwrap();
+        assert!(
+            out.starts_with("UNKNOWN windows, stale outdoor."),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn test_unknown_template() {
+        assert!(render("/nonexistent/template.hbs", &decision()).is_err());
+    }
+}
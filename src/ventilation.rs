@@ -0,0 +1,242 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::decision::{Inputs, RuleOutcome};
+
+// Airing out for indoor air quality, under "ventilation" in rules.json:
+//
+// "ventilation": {
+//   "co2_high": 1000.0, "co2_target": 800.0,
+//   "voc_high": 250.0, "voc_target": 150.0,
+//   "max_aqi": 3, "min_temp": 40.0, "max_temp": 95.0,
+//   "air_changes_per_hour": 6.0, "max_purge_minutes": 30
+// }
+//
+// Independent of window_should_be_open: when CO2 (ppm) or VOC (ppb) is high the report
+// suggests opening for a short purge even if the temperature is only marginal, as long as
+// the outdoor air is clean enough and no veto applies. Very high indoor levels (half again
+// over the limit) tolerate one step worse outdoor AQI.
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VentilationSettings {
+    pub co2_high: f64,
+    pub co2_target: f64,
+    pub voc_high: f64,
+    pub voc_target: f64,
+    // worst OWM AQI (1 good to 5 very poor) worth letting in
+    pub max_aqi: u64,
+    // a purge is short, so the comfortable range is wider than the rules'
+    pub min_temp: f64,
+    pub max_temp: f64,
+    // with windows wide open
+    pub air_changes_per_hour: f64,
+    pub max_purge_minutes: u32,
+}
+
+impl Default for VentilationSettings {
+    fn default() -> Self {
+        VentilationSettings {
+            co2_high: 1000.0,
+            co2_target: 800.0,
+            voc_high: 250.0,
+            voc_target: 150.0,
+            max_aqi: 3,
+            min_temp: 40.0,
+            max_temp: 95.0,
+            air_changes_per_hour: 6.0,
+            max_purge_minutes: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VentilationAdvice {
+    pub recommended: bool,
+    pub purge_minutes: u32,
+    pub reason: String,
+}
+
+// outdoor air is about 420 ppm CO2 and carries next to no VOC
+static OUTDOOR_CO2: f64 = 420.0;
+
+/// minutes for exponential decay toward outdoor levels to bring `level` down to `target`
+fn minutes_to_target(level: f64, target: f64, outdoor: f64, air_changes_per_hour: f64) -> f64 {
+    if level <= target || target <= outdoor {
+        return 0.0;
+    }
+    ((level - outdoor) / (target - outdoor)).ln() / air_changes_per_hour * 60.0
+}
+
+impl VentilationSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_purge_minutes < 5 {
+            bail!(
+                "ventilation: max_purge_minutes {} is under the 5 minute minimum purge",
+                self.max_purge_minutes
+            );
+        }
+        Ok(())
+    }
+
+    /// None without CO2/VOC readings (e.g. a Wave Mini or an old indoor.json)
+    pub fn evaluate(&self, inputs: &Inputs, vetoes: &[RuleOutcome]) -> Option<VentilationAdvice> {
+        let air = inputs.indoor_air.as_ref()?;
+        if air.co2.is_none() && air.voc.is_none() {
+            return None;
+        }
+        let co2 = air.co2.unwrap_or(0.0);
+        let voc = air.voc.unwrap_or(0.0);
+        let load = format!("CO2 {} ppm, VOC {} ppb", co2, voc);
+        let severity = (co2 / self.co2_high).max(voc / self.voc_high);
+        let advice = |recommended: bool, purge_minutes: u32, reason: String| {
+            Some(VentilationAdvice {
+                recommended,
+                purge_minutes,
+                reason,
+            })
+        };
+        if severity <= 1.0 {
+            return advice(false, 0, format!("{} within limits", load));
+        }
+        if let Some(veto) = vetoes.iter().find(|v| !v.passed) {
+            return advice(false, 0, format!("{} but vetoed by {}", load, veto.name));
+        }
+        if let Some(pollution) = &inputs.pollution {
+            let max_aqi = if severity >= 1.5 {
+                self.max_aqi + 1
+            } else {
+                self.max_aqi
+            };
+            if pollution.aqi > max_aqi {
+                return advice(
+                    false,
+                    0,
+                    format!("{} but outdoor AQI {} is worse", load, pollution.aqi),
+                );
+            }
+        }
+        let outdoor_temp = inputs.outdoor.temp;
+        if outdoor_temp < self.min_temp || outdoor_temp > self.max_temp {
+            return advice(
+                false,
+                0,
+                format!("{} but outdoor temp {} is too harsh", load, outdoor_temp),
+            );
+        }
+        let minutes =
+            minutes_to_target(co2, self.co2_target, OUTDOOR_CO2, self.air_changes_per_hour).max(
+                minutes_to_target(voc, self.voc_target, 0.0, self.air_changes_per_hour),
+            );
+        // round up to 5 minutes, at least 5
+        let purge_minutes = ((minutes / 5.0).ceil() as u32 * 5)
+            .max(5)
+            .min(self.max_purge_minutes.max(5));
+        advice(
+            true,
+            purge_minutes,
+            format!(
+                "{} above limits, air out for {} minutes",
+                load, purge_minutes
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decision::{AirQuality, Conditions, IndoorAir};
+
+    fn inputs(co2: f64, voc: f64, aqi: u64) -> Inputs {
+        Inputs {
+            indoor: Conditions {
+                temp: 70.0,
+                humidity: 45,
+                time: None,
+            },
+            outdoor: Conditions {
+                temp: 45.0,
+                humidity: 60,
+                time: None,
+            },
+            pollution: Some(AirQuality { aqi, time: None }),
+            weather: None,
+            indoor_air: Some(IndoorAir {
+                co2: Some(co2),
+                voc: Some(voc),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_purge_for_high_co2() {
+        let advice = VentilationSettings::default()
+            .evaluate(&inputs(1400.0, 100.0, 1), &[])
+            .unwrap();
+        assert!(advice.recommended);
+        // ln(980/380)/6 hours is about 9.5 minutes
+        assert_eq!(advice.purge_minutes, 10);
+    }
+
+    #[test]
+    fn test_no_purge_when_fine() {
+        let advice = VentilationSettings::default()
+            .evaluate(&inputs(700.0, 100.0, 1), &[])
+            .unwrap();
+        assert!(!advice.recommended);
+    }
+
+    #[test]
+    fn test_bad_outdoor_air() {
+        let settings = VentilationSettings::default();
+        assert!(
+            !settings
+                .evaluate(&inputs(1200.0, 100.0, 4), &[])
+                .unwrap()
+                .recommended
+        );
+        // very stuffy air is worth one step worse AQI
+        assert!(
+            settings
+                .evaluate(&inputs(1600.0, 100.0, 4), &[])
+                .unwrap()
+                .recommended
+        );
+    }
+
+    #[test]
+    fn test_vetoed() {
+        let veto = RuleOutcome {
+            name: "precipitation".into(),
+            passed: false,
+            reason: "".into(),
+            icon: None,
+            children: vec![],
+        };
+        let advice = VentilationSettings::default()
+            .evaluate(&inputs(1400.0, 100.0, 1), &[veto])
+            .unwrap();
+        assert!(!advice.recommended);
+        assert!(advice.reason.contains("precipitation"));
+    }
+
+    #[test]
+    fn test_short_max_purge() {
+        let settings: VentilationSettings =
+            serde_json::from_str(r#"{"max_purge_minutes": 3}"#).unwrap();
+        assert!(settings.validate().is_err());
+        // still no panic for settings that were never validated
+        let advice = settings.evaluate(&inputs(1400.0, 100.0, 1), &[]).unwrap();
+        assert_eq!(advice.purge_minutes, 5);
+    }
+
+    #[test]
+    fn test_no_readings() {
+        let mut inputs = inputs(1400.0, 100.0, 1);
+        inputs.indoor_air = None;
+        assert!(VentilationSettings::default()
+            .evaluate(&inputs, &[])
+            .is_none());
+    }
+}
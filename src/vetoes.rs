@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+
+use crate::decision::RuleOutcome;
+use crate::rules::{Fact, Facts};
+
+// Vetoes are checked after the rules and keep the windows closed no matter what the rules say.
+// They are configured under "vetoes" in rules.json:
+//
+// "vetoes": {
+//   "precipitation": true,
+//   "thunderstorm": true,
+//   "fog": false,
+//   "max_wind_speed": 20.0,
+//   "max_wind_gust": 30.0
+// }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VetoSettings {
+    pub precipitation: bool,
+    pub thunderstorm: bool,
+    pub fog: bool,
+    // mph, None to disable
+    pub max_wind_speed: Option<f64>,
+    pub max_wind_gust: Option<f64>,
+}
+
+impl Default for VetoSettings {
+    fn default() -> Self {
+        VetoSettings {
+            precipitation: true,
+            thunderstorm: true,
+            fog: false,
+            max_wind_speed: Some(20.0),
+            max_wind_gust: Some(30.0),
+        }
+    }
+}
+
+fn flag(facts: &Facts, name: &str) -> Option<bool> {
+    match facts.get(name) {
+        Some(Fact::Bool(b)) => Some(*b),
+        _ => None,
+    }
+}
+
+fn number(facts: &Facts, name: &str) -> Option<f64> {
+    match facts.get(name) {
+        Some(Fact::Number(n)) => Some(*n),
+        _ => None,
+    }
+}
+
+fn veto(name: &str, icon: &str, vetoed: bool, reason: String) -> RuleOutcome {
+    RuleOutcome {
+        name: name.into(),
+        // a veto passes when it does not keep the windows closed
+        passed: !vetoed,
+        reason,
+        icon: Some(icon.into()),
+        children: vec![],
+    }
+}
+
+impl VetoSettings {
+    /// vetoes whose facts are missing, e.g. from an old weather.json, are skipped
+    pub fn evaluate(&self, facts: &Facts) -> Vec<RuleOutcome> {
+        let mut vetoes = Vec::new();
+        if self.precipitation {
+            if let Some(raining) = flag(facts, "raining") {
+                let rain = number(facts, "outdoor.rain_1h").unwrap_or(0.0);
+                let snow = number(facts, "outdoor.snow_1h").unwrap_or(0.0);
+                vetoes.push(veto(
+                    "precipitation",
+                    "☔",
+                    raining,
+                    format!("rain {} mm, snow {} mm in the last hour", rain, snow),
+                ));
+            }
+        }
+        if self.thunderstorm {
+            if let Some(thunderstorm) = flag(facts, "thunderstorm") {
+                vetoes.push(veto(
+                    "thunderstorm",
+                    "⛈️",
+                    thunderstorm,
+                    format!("thunderstorm reported: {}", thunderstorm),
+                ));
+            }
+        }
+        if self.fog {
+            if let Some(fog) = flag(facts, "fog") {
+                vetoes.push(veto(
+                    "fog",
+                    "🌫️",
+                    fog,
+                    format!("fog or mist reported: {}", fog),
+                ));
+            }
+        }
+        if let (Some(max), Some(speed)) = (self.max_wind_speed, number(facts, "outdoor.wind_speed"))
+        {
+            vetoes.push(veto(
+                "high_wind",
+                "💨",
+                speed > max,
+                format!("wind {} mph, max {} mph", speed, max),
+            ));
+        }
+        if let (Some(max), Some(gust)) = (self.max_wind_gust, number(facts, "outdoor.wind_gust")) {
+            vetoes.push(veto(
+                "wind_gust",
+                "💨",
+                gust > max,
+                format!("gusts {} mph, max {} mph", gust, max),
+            ));
+        }
+        vetoes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts(raining: bool, wind_gust: f64) -> Facts {
+        let mut facts = Facts::new();
+        facts.insert("raining".into(), raining.into());
+        facts.insert("thunderstorm".into(), false.into());
+        facts.insert("fog".into(), true.into());
+        facts.insert("outdoor.rain_1h".into(), 1.5.into());
+        facts.insert("outdoor.snow_1h".into(), 0.0.into());
+        facts.insert("outdoor.wind_speed".into(), 10.0.into());
+        facts.insert("outdoor.wind_gust".into(), wind_gust.into());
+        facts
+    }
+
+    #[test]
+    fn test_default_vetoes() {
+        let vetoes = VetoSettings::default().evaluate(&facts(true, 35.0));
+        let failed: Vec<&str> = vetoes
+            .iter()
+            .filter(|v| !v.passed)
+            .map(|v| v.name.as_str())
+            .collect();
+        // fog is off by default
+        assert_eq!(failed, vec!["precipitation", "wind_gust"]);
+    }
+
+    #[test]
+    fn test_disabled_vetoes() {
+        let settings = VetoSettings {
+            precipitation: false,
+            thunderstorm: false,
+            fog: false,
+            max_wind_speed: None,
+            max_wind_gust: None,
+        };
+        assert!(settings.evaluate(&facts(true, 35.0)).is_empty());
+    }
+
+    #[test]
+    fn test_missing_facts_are_skipped() {
+        assert!(VetoSettings::default().evaluate(&Facts::new()).is_empty());
+    }
+}
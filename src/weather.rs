@@ -2,16 +2,138 @@ extern crate reqwest;
 use std::path::Path;
 
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 
 use crate::audit::read_to_string_with_path;
+use crate::cache::unwrap_payload;
+use crate::cli::Failure;
 
 pub fn weather_tempurature(weather_json: &serde_json::Value) -> Result<f64> {
-    let temperature = weather_json["main"]["temp"].as_f64().unwrap();
-    Ok(temperature)
+    weather_json["main"]["temp"]
+        .as_f64()
+        .context("weather_tempurature: no main.temp")
 }
+// NWS and station humidity has decimals, rounded like Observation
 pub fn weather_humidity(weather_json: &serde_json::Value) -> Result<u64> {
-    let humidity = weather_json["main"]["humidity"].as_u64().unwrap();
-    Ok(humidity)
+    let humidity = weather_json["main"]["humidity"]
+        .as_f64()
+        .context("weather_humidity: no main.humidity")?;
+    Ok(humidity.round() as u64)
+}
+pub fn weather_time(weather_json: &serde_json::Value) -> Option<i64> {
+    weather_json["dt"].as_i64()
+}
+
+// precipitation, wind and condition codes from the current weather payload
+// https://openweathermap.org/weather-conditions
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WeatherConditions {
+    // mm in the last hour
+    pub rain_1h: f64,
+    pub snow_1h: f64,
+    // mph with units=imperial
+    pub wind_speed: f64,
+    pub wind_gust: f64,
+    pub condition_ids: Vec<u64>,
+    pub description: String,
+    // unix seconds, today's sunrise and sunset at the location
+    #[serde(default)]
+    pub sunrise: Option<i64>,
+    #[serde(default)]
+    pub sunset: Option<i64>,
+}
+
+impl WeatherConditions {
+    fn any_condition(&self, f: impl Fn(u64) -> bool) -> bool {
+        self.condition_ids.iter().any(|id| f(*id))
+    }
+    pub fn thunderstorm(&self) -> bool {
+        self.any_condition(|id| (200..300).contains(&id))
+    }
+    // drizzle, rain and snow groups or any measured precipitation
+    pub fn precipitation(&self) -> bool {
+        self.rain_1h > 0.0
+            || self.snow_1h > 0.0
+            || self.any_condition(|id| (300..700).contains(&id))
+    }
+    // 701 mist, 741 fog
+    pub fn fog(&self) -> bool {
+        self.any_condition(|id| id == 701 || id == 741)
+    }
+}
+
+pub fn weather_conditions(weather_json: &serde_json::Value) -> WeatherConditions {
+    let wind_speed = weather_json["wind"]["speed"].as_f64().unwrap_or(0.0);
+    let conditions = weather_json["weather"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    WeatherConditions {
+        rain_1h: weather_json["rain"]["1h"].as_f64().unwrap_or(0.0),
+        snow_1h: weather_json["snow"]["1h"].as_f64().unwrap_or(0.0),
+        wind_speed,
+        wind_gust: weather_json["wind"]["gust"].as_f64().unwrap_or(wind_speed),
+        condition_ids: conditions.iter().filter_map(|c| c["id"].as_u64()).collect(),
+        description: conditions
+            .iter()
+            .filter_map(|c| c["description"].as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+        sunrise: weather_json["sys"]["sunrise"].as_i64(),
+        sunset: weather_json["sys"]["sunset"].as_i64(),
+    }
+}
+
+/// one outdoor reading, whichever provider it came from
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Observation {
+    // source in fetches, e.g. owm_weather
+    pub provider: String,
+    // unix seconds of the reading itself
+    pub time: i64,
+    pub location: Option<String>,
+    // °F
+    pub temp: f64,
+    pub humidity: u64,
+    pub conditions: WeatherConditions,
+}
+
+impl Observation {
+    /// reads an OWM current weather document, the shape every provider's response is turned into
+    pub fn from_weather_json(
+        provider: &str,
+        weather_json: &serde_json::Value,
+    ) -> Result<Observation> {
+        let time = weather_time(weather_json)
+            .with_context(|| format!("from_weather_json: no dt in the {} response", provider))
+            .context(Failure::Parse)?;
+        let temp = weather_json["main"]["temp"]
+            .as_f64()
+            .with_context(|| {
+                format!(
+                    "from_weather_json: no main.temp in the {} response",
+                    provider
+                )
+            })
+            .context(Failure::Parse)?;
+        let humidity = weather_json["main"]["humidity"]
+            .as_f64()
+            .with_context(|| {
+                format!(
+                    "from_weather_json: no main.humidity in the {} response",
+                    provider
+                )
+            })
+            .context(Failure::Parse)?;
+        Ok(Observation {
+            provider: provider.into(),
+            time,
+            location: weather_json["name"].as_str().map(String::from),
+            temp,
+            humidity: humidity.round() as u64,
+            conditions: weather_conditions(weather_json),
+        })
+    }
 }
 
 pub fn load_weather_response<P: AsRef<Path>>(weather_json_path: P) -> Result<serde_json::Value> {
@@ -23,5 +145,38 @@ pub fn load_weather_response<P: AsRef<Path>>(weather_json_path: P) -> Result<ser
                 weather_json_path.as_ref()
             )
         })?;
-    Ok(weather_json)
+    Ok(unwrap_payload(weather_json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weather_conditions() {
+        let weather_json = serde_json::json!({
+            "weather": [{ "id": 211, "description": "thunderstorm" }, { "id": 741, "description": "fog" }],
+            "wind": { "speed": 12.5 },
+            "rain": { "1h": 3.2 }
+        });
+        let conditions = weather_conditions(&weather_json);
+        assert!(conditions.thunderstorm());
+        assert!(conditions.precipitation());
+        assert!(conditions.fog());
+        assert_eq!(conditions.wind_gust, 12.5);
+        assert_eq!(conditions.snow_1h, 0.0);
+        assert_eq!(conditions.description, "thunderstorm, fog");
+    }
+
+    #[test]
+    fn test_clear_sky() {
+        let weather_json = serde_json::json!({
+            "weather": [{ "id": 800, "description": "clear sky" }],
+            "wind": { "speed": 3.0, "gust": 7.0 }
+        });
+        let conditions = weather_conditions(&weather_json);
+        assert!(!conditions.thunderstorm());
+        assert!(!conditions.precipitation());
+        assert!(!conditions.fog());
+    }
 }
@@ -0,0 +1,236 @@
+mod common;
+
+use common::{exit_code, read_json, run, stderr, MockServer, Route, TempDir};
+use iloveair::history::History;
+
+const READ_WAVEPLUS: &str = env!("CARGO_BIN_EXE_read_waveplus");
+
+const TOKEN: &str =
+    r#"{"access_token": "fresh-token", "expires_in": 10800, "token_type": "Bearer"}"#;
+const LATEST: &str = r#"{"data": {"battery": 99, "co2": 1042.0, "humidity": 61.0, "pressure": 1007.8, "radonShortTermAvg": 35.0, "temp": 21.5, "time": 1694880270, "voc": 120.0, "relayDeviceType": "hub"}}"#;
+const DEVICES: &str = r#"{"devices": [{"id": "2930000000", "deviceType": "WAVE_PLUS"}]}"#;
+
+fn config(dir: &TempDir, server: &MockServer) -> String {
+    dir.write(
+        "airthings.json",
+        &format!(
+            r#"{{"client_id": "id", "client_secret": "secret", "device_id": "2930000000", "accounts_url": "{0}", "api_url": "{0}", "allow_http": true}}"#,
+            server.base_url
+        ),
+    )
+}
+
+fn airthings_server(token: (u16, &str), latest: (u16, &str)) -> MockServer {
+    MockServer::start(vec![
+        Route::new("POST", "/v1/token", token.0, token.1),
+        Route::new("GET", "/v1/devices", 200, DEVICES),
+        Route::new(
+            "GET",
+            "/v1/devices/2930000000/latest-samples",
+            latest.0,
+            latest.1,
+        ),
+    ])
+}
+
+fn read_waveplus(dir: &TempDir, server: &MockServer, extra: &[&str]) -> std::process::Output {
+    let config = config(dir, server);
+    let indoor = dir.path("waveplus.json");
+    let token = dir.path("airthings_token.json");
+    let mut args = vec!["-c", &config, "-i", &indoor, "-t", &token, "--force"];
+    args.extend(extra);
+    run(READ_WAVEPLUS, &args)
+}
+
+fn write_token(dir: &TempDir, token: &str, expiration: &str) {
+    dir.write(
+        "airthings_token.json",
+        &format!(
+            r#"{{"access_token": "{}", "expiration": "{}"}}"#,
+            token, expiration
+        ),
+    );
+}
+
+#[test]
+fn test_read_waveplus() {
+    let dir = TempDir::new("read_waveplus");
+    let server = airthings_server((200, TOKEN), (200, LATEST));
+    let db = dir.path("history.db");
+    let output = read_waveplus(&dir, &server, &["--db", &db]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+
+    let token_requests = server.received_path("/v1/token");
+    assert_eq!(token_requests.len(), 1);
+    let body: serde_json::Value = serde_json::from_str(&token_requests[0].body).unwrap();
+    assert_eq!(body["grant_type"], "client_credentials");
+    assert_eq!(body["client_secret"], "secret");
+    let latest = server.received_path("/v1/devices/2930000000/latest-samples");
+    assert_eq!(
+        latest[0].authorization.as_deref(),
+        Some("Bearer fresh-token")
+    );
+    let indoor = read_json(&dir.path("waveplus.json"));
+    assert_eq!(indoor["payload"]["radonShortTermAvg"], 35.0);
+    assert_eq!(indoor["payload"]["co2"], 1042.0);
+    assert_eq!(
+        read_json(&dir.path("airthings_token.json"))["access_token"],
+        "fresh-token"
+    );
+    let history = History::open(&db).unwrap();
+    let stats = history.fetch_stats().unwrap();
+    assert_eq!((stats[0].successes, stats[0].failures), (2, 0));
+    assert_eq!(
+        history
+            .samples("indoor.radon", None, None, None)
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn test_cached_token_is_reused() {
+    let dir = TempDir::new("read_waveplus_cached");
+    let server = airthings_server((200, TOKEN), (200, LATEST));
+    write_token(&dir, "cached-token", "2999-01-01T00:00:00Z");
+    let output = read_waveplus(&dir, &server, &[]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert!(server.received_path("/v1/token").is_empty());
+    let latest = server.received_path("/v1/devices/2930000000/latest-samples");
+    assert_eq!(
+        latest[0].authorization.as_deref(),
+        Some("Bearer cached-token")
+    );
+}
+
+#[test]
+fn test_expired_token_is_refreshed() {
+    let dir = TempDir::new("read_waveplus_expired");
+    let server = airthings_server((200, TOKEN), (200, LATEST));
+    write_token(&dir, "expired-token", "2020-01-01T00:00:00Z");
+    let output = read_waveplus(&dir, &server, &[]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert_eq!(server.received_path("/v1/token").len(), 1);
+    let latest = server.received_path("/v1/devices/2930000000/latest-samples");
+    assert_eq!(
+        latest[0].authorization.as_deref(),
+        Some("Bearer fresh-token")
+    );
+}
+
+#[test]
+fn test_bad_credentials() {
+    let dir = TempDir::new("read_waveplus_credentials");
+    let server = airthings_server(
+        (
+            401,
+            r#"{"error": "invalid_client", "error_description": "bad client secret", "error_code": "1001"}"#,
+        ),
+        (200, LATEST),
+    );
+    let output = read_waveplus(&dir, &server, &[]);
+    assert_eq!(exit_code(&output), 77, "{}", stderr(&output));
+    assert!(stderr(&output).contains("bad client secret"));
+    assert!(server
+        .received_path("/v1/devices/2930000000/latest-samples")
+        .is_empty());
+}
+
+#[test]
+fn test_rejected_token() {
+    let dir = TempDir::new("read_waveplus_401");
+    let latest_path = "/v1/devices/2930000000/latest-samples";
+    let server = MockServer::start(vec![
+        Route::new("POST", "/v1/token", 200, TOKEN),
+        Route::new("GET", latest_path, 200, LATEST).authorization("Bearer fresh-token"),
+        Route::new("GET", latest_path, 401, r#"{"message": "Unauthorized"}"#),
+    ]);
+    // revoked on the server before its local expiration
+    write_token(&dir, "revoked-token", "2999-01-01T00:00:00Z");
+    let db = dir.path("history.db");
+    let output = read_waveplus(&dir, &server, &["--db", &db]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert_eq!(server.received_path("/v1/token").len(), 1);
+    let authorizations: Vec<_> = server
+        .received_path(latest_path)
+        .into_iter()
+        .map(|r| r.authorization.unwrap_or_default())
+        .collect();
+    assert_eq!(
+        authorizations,
+        ["Bearer revoked-token", "Bearer fresh-token"]
+    );
+    assert_eq!(
+        read_json(&dir.path("airthings_token.json"))["access_token"],
+        "fresh-token"
+    );
+    assert_eq!(
+        read_json(&dir.path("waveplus.json"))["payload"]["co2"],
+        1042.0
+    );
+    let stats = History::open(&db).unwrap().fetch_stats().unwrap();
+    assert_eq!((stats[0].successes, stats[0].failures), (2, 1));
+}
+
+#[test]
+fn test_fresh_token_rejected() {
+    let dir = TempDir::new("read_waveplus_401_fresh");
+    let server = airthings_server((200, TOKEN), (401, r#"{"message": "Unauthorized"}"#));
+    write_token(&dir, "revoked-token", "2999-01-01T00:00:00Z");
+    let db = dir.path("history.db");
+    let output = read_waveplus(&dir, &server, &["--db", &db]);
+    assert_eq!(exit_code(&output), 77, "{}", stderr(&output));
+    // retried once, not in a loop
+    assert_eq!(server.received_path("/v1/token").len(), 1);
+    assert_eq!(
+        server
+            .received_path("/v1/devices/2930000000/latest-samples")
+            .len(),
+        2
+    );
+    let stats = History::open(&db).unwrap().fetch_stats().unwrap();
+    assert_eq!((stats[0].successes, stats[0].failures), (1, 2));
+
+    // without a cached token there is nothing to retry
+    let dir = TempDir::new("read_waveplus_401_uncached");
+    let output = read_waveplus(&dir, &server, &[]);
+    assert_eq!(exit_code(&output), 77, "{}", stderr(&output));
+    assert_eq!(server.received_path("/v1/token").len(), 2);
+}
+
+#[test]
+fn test_rate_limited_and_server_error() {
+    for status in [429, 500, 503] {
+        let dir = TempDir::new(&format!("read_waveplus_{}", status));
+        let server = airthings_server((200, TOKEN), (status, "try again later"));
+        let output = read_waveplus(&dir, &server, &[]);
+        assert_eq!(exit_code(&output), 69, "{} {}", status, stderr(&output));
+    }
+    let dir = TempDir::new("read_waveplus_token_500");
+    let server = airthings_server((500, "internal error"), (200, LATEST));
+    let output = read_waveplus(&dir, &server, &[]);
+    assert_eq!(exit_code(&output), 69, "{}", stderr(&output));
+}
+
+#[test]
+fn test_malformed_json() {
+    let dir = TempDir::new("read_waveplus_malformed");
+    for latest in [r#"{"data": "#, r#"{"data": {"battery": 99}}"#] {
+        let server = airthings_server((200, TOKEN), (200, latest));
+        let output = read_waveplus(&dir, &server, &[]);
+        assert_eq!(exit_code(&output), 65, "{}", stderr(&output));
+    }
+}
+
+#[test]
+fn test_list_devices() {
+    let dir = TempDir::new("read_waveplus_devices");
+    let server = airthings_server((200, TOKEN), (200, LATEST));
+    let output = read_waveplus(&dir, &server, &["--list-devices"]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("WAVE_PLUS"));
+    assert!(server
+        .received_path("/v1/devices/2930000000/latest-samples")
+        .is_empty());
+}
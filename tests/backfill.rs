@@ -0,0 +1,295 @@
+mod common;
+
+use common::{exit_code, run, stderr, MockServer, Route, TempDir};
+use iloveair::backfill::{backfill, RateLimit, Source, Summary, WINDOW};
+use iloveair::config::WeatherConfig;
+use iloveair::history::History;
+use iloveair::http::{Client, Mode};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const ILOVEAIR: &str = env!("CARGO_BIN_EXE_iloveair");
+
+const POLLUTION: &str = r#"{"coord": [-71.06, 42.36], "list": [{"dt": 1693526400, "main": {"aqi": 2}}, {"dt": 1693530000, "main": {"aqi": 3}}]}"#;
+const WEATHER: &str = r#"{"cod": "200", "cnt": 1, "list": [{"dt": 1693526400, "main": {"temp": 70.2, "humidity": 60}}]}"#;
+
+fn config(dir: &TempDir, server: &MockServer) -> String {
+    dir.write(
+        "openweathermap.json",
+        &format!(
+            r#"{{"api_key": "secret", "city": "Boston", "country": "US", "latitude": "42.36", "longitude": "-71.06", "base_url": "{0}", "history_base_url": "{0}", "allow_http": true}}"#,
+            server.base_url
+        ),
+    )
+}
+
+#[test]
+fn test_backfill() {
+    let dir = TempDir::new("backfill");
+    let server = MockServer::start(vec![
+        Route::new("GET", "/data/2.5/air_pollution/history", 200, POLLUTION),
+        Route::new("GET", "/data/2.5/history/city", 200, WEATHER),
+    ]);
+    let db = dir.path("history.db");
+    let args = [
+        "backfill",
+        "-c",
+        &config(&dir, &server),
+        "--db",
+        &db,
+        "--from",
+        "2023-09-01",
+        "--to",
+        "2023-09-10",
+        "--requests-per-minute",
+        "6000",
+    ];
+    let output = run(ILOVEAIR, &args);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    // nine days are two one week windows per source
+    assert_eq!(
+        server
+            .received_path("/data/2.5/air_pollution/history")
+            .len(),
+        2
+    );
+    assert_eq!(server.received_path("/data/2.5/history/city").len(), 2);
+    let history = History::open(&db).unwrap();
+    assert_eq!(history.samples("aqi", None, None, None).unwrap().len(), 2);
+    assert_eq!(
+        history
+            .samples("outdoor.temp", Some("Boston"), None, None)
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // already done, nothing is fetched again
+    let output = run(ILOVEAIR, &args);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert_eq!(server.received().len(), 4);
+}
+
+#[test]
+fn test_backfill_unauthorized() {
+    let dir = TempDir::new("backfill_401");
+    let server = MockServer::start(vec![Route::new(
+        "GET",
+        "/data/2.5/history/city",
+        401,
+        r#"{"cod": 401, "message": "Invalid API key"}"#,
+    )]);
+    let output = run(
+        ILOVEAIR,
+        &[
+            "backfill",
+            "-c",
+            &config(&dir, &server),
+            "--db",
+            &dir.path("history.db"),
+            "--from",
+            "2023-09-01",
+            "--to",
+            "2023-09-02",
+            "-s",
+            "weather",
+        ],
+    );
+    assert_eq!(exit_code(&output), 77, "{}", stderr(&output));
+    assert!(!stderr(&output).contains("secret"));
+}
+
+const POLLUTION_PATH: &str = "/data/2.5/air_pollution/history";
+const FROM: i64 = 1694000000;
+
+fn weather_config(server: &MockServer) -> WeatherConfig {
+    serde_json::from_value(json!({
+        "api_key": "secret", "city": "Boston", "country": "US",
+        "latitude": "42.36", "longitude": "-71.06",
+        "base_url": server.base_url, "history_base_url": server.base_url, "allow_http": true
+    }))
+    .unwrap()
+}
+
+fn pollution(times: &[i64]) -> String {
+    let list: Vec<Value> = times
+        .iter()
+        .map(|dt| json!({"dt": dt, "main": {"aqi": 2}, "components": {"pm2_5": 3.5}}))
+        .collect();
+    json!({"coord": [-71.06, 42.36], "list": list}).to_string()
+}
+
+fn urls(server: &MockServer) -> Vec<String> {
+    server.received().into_iter().map(|r| r.url).collect()
+}
+
+#[test]
+fn test_backfill_pollution_in_windows() {
+    let server = MockServer::start(vec![Route::new(
+        "GET",
+        POLLUTION_PATH,
+        200,
+        &pollution(&[FROM, FROM + 3600]),
+    )
+    .then(200, &pollution(&[FROM + WINDOW]))]);
+    let history = History::open_in_memory().unwrap();
+    let mut slept = vec![];
+    let summary = backfill(
+        &Client::new(Mode::Live),
+        &weather_config(&server),
+        &history,
+        Source::Pollution,
+        FROM,
+        FROM + WINDOW + 3600,
+        &RateLimit::per_minute(60),
+        &mut |wait| slept.push(wait),
+    )
+    .unwrap();
+    assert_eq!(
+        summary,
+        Summary {
+            requests: 2,
+            records: 3
+        }
+    );
+    let urls = urls(&server);
+    assert!(urls[0].starts_with("/data/2.5/air_pollution/history?lat=42.36&lon=-71.06"));
+    assert!(urls[0].contains(&format!("start={}&end={}", FROM, FROM + WINDOW)));
+    assert!(urls[1].contains(&format!(
+        "start={}&end={}",
+        FROM + WINDOW,
+        FROM + WINDOW + 3600
+    )));
+    assert_eq!(slept, vec![Duration::from_secs(1)]);
+    assert_eq!(history.samples("aqi", None, None, None).unwrap().len(), 3);
+    assert_eq!(
+        history
+            .backfill_progress("owm_pollution_history", FROM)
+            .unwrap(),
+        Some(FROM + WINDOW + 3600)
+    );
+}
+
+#[test]
+fn test_backfill_weather() {
+    let body = json!({"cod": "200", "cnt": 1, "list": [
+        {"dt": FROM, "main": {"temp": 61.5, "humidity": 80}, "wind": {"speed": 5.75}}
+    ]});
+    let server = MockServer::start(vec![Route::new(
+        "GET",
+        "/data/2.5/history/city",
+        200,
+        &body.to_string(),
+    )]);
+    let history = History::open_in_memory().unwrap();
+    backfill(
+        &Client::new(Mode::Live),
+        &weather_config(&server),
+        &history,
+        Source::Weather,
+        FROM,
+        FROM + 3600,
+        &RateLimit::per_minute(60),
+        &mut |_| (),
+    )
+    .unwrap();
+    assert!(urls(&server)[0].starts_with("/data/2.5/history/city?"));
+    let samples = history
+        .samples("outdoor.temp", Some("Boston"), None, None)
+        .unwrap();
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].value, 61.5);
+}
+
+#[test]
+fn test_backfill_resumes() {
+    let history = History::open_in_memory().unwrap();
+    let to = FROM + 3 * WINDOW;
+    // the second window keeps failing
+    let server = MockServer::start(vec![Route::new(
+        "GET",
+        POLLUTION_PATH,
+        200,
+        &pollution(&[FROM]),
+    )
+    .then(500, "internal error")]);
+    let rate = RateLimit {
+        interval: Duration::ZERO,
+        retries: 2,
+        backoff: Duration::from_secs(10),
+    };
+    let mut slept = vec![];
+    let e = backfill(
+        &Client::new(Mode::Live),
+        &weather_config(&server),
+        &history,
+        Source::Pollution,
+        FROM,
+        to,
+        &rate,
+        &mut |wait| slept.push(wait),
+    )
+    .unwrap_err();
+    assert_eq!(iloveair::cli::exit_code(&e), 69);
+    assert_eq!(
+        slept,
+        vec![
+            Duration::ZERO,
+            Duration::from_secs(10),
+            Duration::from_secs(20)
+        ]
+    );
+    assert_eq!(
+        history
+            .backfill_progress("owm_pollution_history", FROM)
+            .unwrap(),
+        Some(FROM + WINDOW)
+    );
+
+    // a rerun starts at the second window
+    let server = MockServer::start(vec![Route::new(
+        "GET",
+        POLLUTION_PATH,
+        200,
+        &pollution(&[FROM + WINDOW]),
+    )]);
+    let summary = backfill(
+        &Client::new(Mode::Live),
+        &weather_config(&server),
+        &history,
+        Source::Pollution,
+        FROM,
+        to,
+        &rate,
+        &mut |_| (),
+    )
+    .unwrap();
+    assert_eq!(summary.requests, 2);
+    assert!(urls(&server)[0].contains(&format!("start={}&", FROM + WINDOW)));
+}
+
+#[test]
+fn test_rate_limited_then_ok() {
+    let server = MockServer::start(vec![Route::new(
+        "GET",
+        POLLUTION_PATH,
+        429,
+        r#"{"cod": 429, "message": "slow down"}"#,
+    )
+    .then(200, &pollution(&[FROM]))]);
+    let history = History::open_in_memory().unwrap();
+    let mut slept = vec![];
+    backfill(
+        &Client::new(Mode::Live),
+        &weather_config(&server),
+        &history,
+        Source::Pollution,
+        FROM,
+        FROM + 3600,
+        &RateLimit::per_minute(60),
+        &mut |wait| slept.push(wait),
+    )
+    .unwrap();
+    assert_eq!(server.received().len(), 2);
+    assert_eq!(slept, vec![Duration::from_secs(10)]);
+}
@@ -0,0 +1,171 @@
+// Shared by the integration tests: in-process mock HTTP servers standing in for Airthings,
+// OpenWeatherMap and Pushover, and helpers to run the binaries against them. The binaries are
+// pointed at a mock with "base_url" and "allow_http" in their config files.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub struct Route {
+    method: &'static str,
+    path: String,
+    // served in order, the last one repeated
+    responses: Vec<(u16, String)>,
+    delay: Duration,
+    authorization: Option<String>,
+}
+
+impl Route {
+    /// answers `method path`, the query string is ignored
+    pub fn new(method: &'static str, path: &str, status: u16, body: &str) -> Route {
+        Route {
+            method,
+            path: path.into(),
+            responses: vec![(status, body.into())],
+            delay: Duration::ZERO,
+            authorization: None,
+        }
+    }
+
+    /// only answers requests with this Authorization header, routes are tried in order
+    pub fn authorization(mut self, authorization: &str) -> Route {
+        self.authorization = Some(authorization.into());
+        self
+    }
+
+    /// answers with this once the responses before it have been served
+    pub fn then(mut self, status: u16, body: &str) -> Route {
+        self.responses.push((status, body.into()));
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Route {
+        self.delay = delay;
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Received {
+    pub method: String,
+    pub url: String,
+    pub authorization: Option<String>,
+    pub body: String,
+}
+
+pub struct MockServer {
+    pub base_url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl MockServer {
+    /// serves `routes` on a free port until the test process exits, unknown paths get a 404
+    pub fn start(routes: Vec<Route>) -> MockServer {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let received = Arc::new(Mutex::new(vec![]));
+        let log = received.clone();
+        thread::spawn(move || {
+            let mut served = vec![0; routes.len()];
+            for mut request in server.incoming_requests() {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                let url = request.url().to_string();
+                let path = url.split('?').next().unwrap_or_default().to_string();
+                let method = request.method().to_string();
+                let authorization = request
+                    .headers()
+                    .iter()
+                    .find(|header| header.field.equiv("Authorization"))
+                    .map(|header| header.value.to_string());
+                let response = match routes.iter().position(|route| {
+                    route.method == method
+                        && route.path == path
+                        && (route.authorization.is_none() || route.authorization == authorization)
+                }) {
+                    Some(i) => {
+                        let route = &routes[i];
+                        let (status, body) =
+                            &route.responses[served[i].min(route.responses.len() - 1)];
+                        served[i] += 1;
+                        thread::sleep(route.delay);
+                        tiny_http::Response::from_string(body.clone()).with_status_code(*status)
+                    }
+                    None => tiny_http::Response::from_string("not found").with_status_code(404),
+                };
+                log.lock().unwrap().push(Received {
+                    method,
+                    url,
+                    authorization,
+                    body,
+                });
+                let _ = request.respond(response);
+            }
+        });
+        MockServer { base_url, received }
+    }
+
+    pub fn received(&self) -> Vec<Received> {
+        self.received.lock().unwrap().clone()
+    }
+
+    pub fn received_path(&self, path: &str) -> Vec<Received> {
+        self.received()
+            .into_iter()
+            .filter(|r| r.url.split('?').next() == Some(path))
+            .collect()
+    }
+}
+
+/// a fresh directory under the system temp dir, removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("iloveair_it_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self, name: &str) -> String {
+        self.0.join(name).to_str().unwrap().into()
+    }
+
+    pub fn write(&self, name: &str, contents: &str) -> String {
+        let path = self.path(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// runs a binary built for the tests, `bin` is the CARGO_BIN_EXE_ path
+pub fn run(bin: &str, args: &[&str]) -> Output {
+    Command::new(bin)
+        .args(args)
+        .output()
+        .unwrap_or_else(|e| panic!("could not run {} {}", bin, e))
+}
+
+pub fn exit_code(output: &Output) -> i32 {
+    output.status.code().unwrap_or(-1)
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into()
+}
+
+pub fn read_json(path: &str) -> serde_json::Value {
+    let contents = fs::read_to_string(Path::new(path)).unwrap();
+    serde_json::from_str(&contents).unwrap()
+}
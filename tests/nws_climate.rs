@@ -0,0 +1,90 @@
+mod common;
+
+use common::{exit_code, run, stderr, MockServer, Route, TempDir};
+use iloveair::history::History;
+
+const GETRAIN: &str = env!("CARGO_BIN_EXE_getrain");
+
+const RTP: &str = include_str!("fixtures/rtp.html");
+
+fn config(dir: &TempDir, server: &MockServer, station: &str) -> String {
+    let rain_regex = format!(
+        r"{}\\s*:[^:]*:\\s*(\\S+)\\s*/\\s*(\\S+)\\s*/\\s*(\\S+)",
+        station
+    );
+    dir.write(
+        "rain.json",
+        &format!(
+            r#"{{"rain_data_url": "{}/product.php?site=LWX&product=RTP", "rain_regex": "{}", "allow_http": true}}"#,
+            server.base_url, rain_regex
+        ),
+    )
+}
+
+fn getrain(dir: &TempDir, server: &MockServer, station: &str) -> std::process::Output {
+    run(
+        GETRAIN,
+        &[
+            "-c",
+            &config(dir, server, station),
+            "--db",
+            &dir.path("history.db"),
+            "--date",
+            "2023-09-15",
+        ],
+    )
+}
+
+#[test]
+fn test_getrain() {
+    let dir = TempDir::new("getrain");
+    let server = MockServer::start(vec![Route::new("GET", "/product.php", 200, RTP)]);
+    let output = getrain(&dir, &server, "IAD");
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Precipitation: 0.12 inches"));
+    assert!(server.received()[0].url.contains("product=RTP"));
+
+    let history = History::open(&dir.path("history.db")).unwrap();
+    let rain = history.samples("precipitation", None, None, None).unwrap();
+    assert_eq!(rain.len(), 1);
+    assert_eq!(rain[0].value, 0.12);
+    let high = history.samples("climate.high", None, None, None).unwrap();
+    assert_eq!(high[0].value, 76.0);
+    let stats = history.fetch_stats().unwrap();
+    assert_eq!(stats[0].source, "nws_climate");
+
+    // a trace and a missing low
+    let output = getrain(&dir, &server, "BWI");
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Precipitation: trace"));
+    let rain = history.samples("precipitation", None, None, None).unwrap();
+    assert_eq!(rain.len(), 1);
+    assert_eq!(rain[0].value, 0.0);
+}
+
+#[test]
+fn test_getrain_errors() {
+    let dir = TempDir::new("getrain_errors");
+    let server = MockServer::start(vec![Route::new("GET", "/product.php", 200, RTP)]);
+    // the station is not in the report
+    let output = getrain(&dir, &server, "XYZ");
+    assert_eq!(exit_code(&output), 65, "{}", stderr(&output));
+    let stats = History::open(&dir.path("history.db"))
+        .unwrap()
+        .fetch_stats()
+        .unwrap();
+    assert_eq!((stats[0].successes, stats[0].failures), (0, 1));
+
+    let server = MockServer::start(vec![Route::new(
+        "GET",
+        "/product.php",
+        200,
+        "<html><body>product not available</body></html>",
+    )]);
+    let output = getrain(&dir, &server, "IAD");
+    assert_eq!(exit_code(&output), 65, "{}", stderr(&output));
+
+    let server = MockServer::start(vec![Route::new("GET", "/product.php", 503, "")]);
+    let output = getrain(&dir, &server, "IAD");
+    assert_eq!(exit_code(&output), 69, "{}", stderr(&output));
+}
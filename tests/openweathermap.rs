@@ -0,0 +1,138 @@
+mod common;
+
+use common::{exit_code, read_json, run, stderr, MockServer, Route, TempDir};
+use iloveair::history::History;
+use std::time::Duration;
+
+const GETWEATHER: &str = env!("CARGO_BIN_EXE_getweather");
+const GETPOLLUTION: &str = env!("CARGO_BIN_EXE_getpollution");
+
+const WEATHER: &str =
+    r#"{"dt": 1694880000, "name": "Boston", "main": {"temp": 61.5, "humidity": 80}}"#;
+const POLLUTION: &str = r#"{"coord": [50, 50], "list": [{"dt": 1694880000, "main": {"aqi": 2}, "components": {"pm2_5": 3.5}}]}"#;
+
+fn config(dir: &TempDir, server: &MockServer) -> String {
+    dir.write(
+        "openweathermap.json",
+        &format!(
+            r#"{{"api_key": "secret", "city": "Boston", "country": "US", "latitude": "42.36", "longitude": "-71.06", "base_url": "{}", "allow_http": true}}"#,
+            server.base_url
+        ),
+    )
+}
+
+fn weather_server(status: u16, body: &str) -> MockServer {
+    MockServer::start(vec![Route::new("GET", "/data/2.5/weather", status, body)])
+}
+
+#[test]
+fn test_getweather() {
+    let dir = TempDir::new("getweather");
+    let server = weather_server(200, WEATHER);
+    let out = dir.path("weather.json");
+    let db = dir.path("history.db");
+    let output = run(
+        GETWEATHER,
+        &["-c", &config(&dir, &server), "-o", &out, "--db", &db],
+    );
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    let cached = read_json(&out);
+    assert_eq!(cached["payload"]["main"]["temp"], 61.5);
+    assert_eq!(cached["status"], 200);
+    assert!(!cached["url"].as_str().unwrap().contains("secret"));
+
+    let received = server.received_path("/data/2.5/weather");
+    assert_eq!(received.len(), 1);
+    assert!(received[0].url.contains("q=Boston,US"));
+    assert!(received[0].url.contains("appid=secret"));
+    let stats = History::open(&db).unwrap().fetch_stats().unwrap();
+    assert_eq!((stats[0].successes, stats[0].failures), (1, 0));
+
+    // fresh cache, no second request
+    let output = run(GETWEATHER, &["-c", &config(&dir, &server), "-o", &out]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert_eq!(server.received().len(), 1);
+}
+
+#[test]
+fn test_getweather_unauthorized() {
+    let dir = TempDir::new("getweather_401");
+    let server = weather_server(401, r#"{"cod": 401, "message": "Invalid API key"}"#);
+    let db = dir.path("history.db");
+    let output = run(GETWEATHER, &["-c", &config(&dir, &server), "--db", &db]);
+    assert_eq!(exit_code(&output), 77, "{}", stderr(&output));
+    assert!(stderr(&output).contains("Invalid API key"));
+    assert!(!stderr(&output).contains("secret"));
+    let stats = History::open(&db).unwrap().fetch_stats().unwrap();
+    assert_eq!((stats[0].successes, stats[0].failures), (0, 1));
+}
+
+#[test]
+fn test_getweather_rate_limited_and_server_error() {
+    let dir = TempDir::new("getweather_429");
+    for status in [429, 500] {
+        let server = weather_server(status, r#"{"cod": 429, "message": "slow down"}"#);
+        let output = run(GETWEATHER, &["-c", &config(&dir, &server)]);
+        assert_eq!(exit_code(&output), 69, "{}", stderr(&output));
+    }
+}
+
+#[test]
+fn test_getweather_malformed_json() {
+    let dir = TempDir::new("getweather_malformed");
+    let out = dir.path("weather.json");
+    for body in [r#"{"main": "#, r#"{"main": {}}"#] {
+        let server = weather_server(200, body);
+        let output = run(
+            GETWEATHER,
+            &["-c", &config(&dir, &server), "-o", &out, "--force"],
+        );
+        assert_eq!(exit_code(&output), 65, "{}", stderr(&output));
+    }
+}
+
+#[test]
+fn test_getweather_slow_response() {
+    let dir = TempDir::new("getweather_slow");
+    let server = MockServer::start(vec![
+        Route::new("GET", "/data/2.5/weather", 200, WEATHER).delay(Duration::from_secs(3))
+    ]);
+    let output = run(
+        GETWEATHER,
+        &["-c", &config(&dir, &server), "--timeout", "1"],
+    );
+    assert_eq!(exit_code(&output), 69, "{}", stderr(&output));
+}
+
+#[test]
+fn test_getpollution() {
+    let dir = TempDir::new("getpollution");
+    let server = MockServer::start(vec![Route::new(
+        "GET",
+        "/data/2.5/air_pollution",
+        200,
+        POLLUTION,
+    )]);
+    let out = dir.path("pollution.json");
+    let output = run(GETPOLLUTION, &["-c", &config(&dir, &server), "-o", &out]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert_eq!(read_json(&out)["payload"]["list"][0]["main"]["aqi"], 2);
+    let received = server.received_path("/data/2.5/air_pollution");
+    assert!(received[0].url.contains("lat=42.36&lon=-71.06"));
+}
+
+#[test]
+fn test_plain_http_refused() {
+    let dir = TempDir::new("getpollution_http");
+    let server = MockServer::start(vec![]);
+    let config = dir.write(
+        "openweathermap.json",
+        &format!(
+            r#"{{"api_key": "secret", "city": "Boston", "country": "US", "latitude": "42.36", "longitude": "-71.06", "base_url": "{}"}}"#,
+            server.base_url
+        ),
+    );
+    let output = run(GETPOLLUTION, &["-c", &config]);
+    assert_eq!(exit_code(&output), 78, "{}", stderr(&output));
+    assert!(server.received().is_empty());
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::{exit_code, read_json, run, stderr, MockServer, Route, TempDir};
+
+const GETWEATHER: &str = env!("CARGO_BIN_EXE_getweather");
+const READ_WAVEPLUS: &str = env!("CARGO_BIN_EXE_read_waveplus");
+const WEATHER_NOTIFY: &str = env!("CARGO_BIN_EXE_weather_notify");
+const PUSHOVER_NOTIFY: &str = env!("CARGO_BIN_EXE_pushover_notify");
+
+// the cron pipeline end to end against one mock standing in for all three services
+#[test]
+fn test_fetch_decide_notify() {
+    let dir = TempDir::new("pipeline");
+    let server = MockServer::start(vec![
+        Route::new(
+            "GET",
+            "/data/2.5/weather",
+            200,
+            r#"{"dt": 1694880000, "name": "Boston", "main": {"temp": 61.5, "humidity": 50}, "sys": {"sunrise": 1694859000, "sunset": 1694904000}}"#,
+        ),
+        Route::new(
+            "POST",
+            "/v1/token",
+            200,
+            r#"{"access_token": "token", "expires_in": 10800}"#,
+        ),
+        Route::new(
+            "GET",
+            "/v1/devices/2930000000/latest-samples",
+            200,
+            r#"{"data": {"battery": 99, "humidity": 55.0, "radonShortTermAvg": 35.0, "temp": 23.5, "time": 1694880270, "relayDeviceType": "hub"}}"#,
+        ),
+        Route::new("POST", "/1/messages.json", 200, r#"{"status": 1}"#),
+    ]);
+    let owm_config = dir.write(
+        "openweathermap.json",
+        &format!(
+            r#"{{"api_key": "k", "city": "Boston", "country": "US", "latitude": "42.36", "longitude": "-71.06", "base_url": "{}", "allow_http": true}}"#,
+            server.base_url
+        ),
+    );
+    let airthings_config = dir.write(
+        "airthings.json",
+        &format!(
+            r#"{{"client_id": "id", "client_secret": "secret", "device_id": "2930000000", "accounts_url": "{0}", "api_url": "{0}", "allow_http": true}}"#,
+            server.base_url
+        ),
+    );
+    let pushover_config = dir.write(
+        "pushover.json",
+        &format!(
+            r#"{{"api_key": "app", "user_key": "user", "base_url": "{}", "allow_http": true}}"#,
+            server.base_url
+        ),
+    );
+    let weather = dir.path("weather.json");
+    let indoor = dir.path("waveplus.json");
+    let decision = dir.path("decision.json");
+
+    let output = run(GETWEATHER, &["-c", &owm_config, "-o", &weather]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    let output = run(
+        READ_WAVEPLUS,
+        &[
+            "-c",
+            &airthings_config,
+            "-i",
+            &indoor,
+            "-t",
+            &dir.path("token.json"),
+        ],
+    );
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    let output = run(
+        WEATHER_NOTIFY,
+        &[
+            "-w",
+            &weather,
+            "-i",
+            &indoor,
+            "-o",
+            &dir.path("Indoor.txt"),
+            "--json-out",
+            &decision,
+            "--now",
+            "2023-09-16T16:30:00Z",
+        ],
+    );
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert_eq!(read_json(&decision)["window_should_be_open"], true);
+
+    let output = run(PUSHOVER_NOTIFY, &["-p", &pushover_config, "-j", &decision]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    let sent = server.received_path("/1/messages.json");
+    assert_eq!(sent.len(), 1);
+    let body: serde_json::Value = serde_json::from_str(&sent[0].body).unwrap();
+    assert!(body["message"].as_str().unwrap().contains("🪟"));
+
+    // a --json-in without .json gets its own checksum instead of being overwritten by one
+    let extensionless = dir.write("decision", &std::fs::read_to_string(&decision).unwrap());
+    let output = run(
+        PUSHOVER_NOTIFY,
+        &["-p", &pushover_config, "-j", &extensionless],
+    );
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert_eq!(read_json(&extensionless)["window_should_be_open"], true);
+    assert!(std::path::Path::new(&dir.path("decision.pushover.crc32")).exists());
+
+    // the same readings a day later are stale
+    let output = run(
+        WEATHER_NOTIFY,
+        &[
+            "-w",
+            &weather,
+            "-i",
+            &indoor,
+            "-o",
+            &dir.path("Indoor.txt"),
+            "--now",
+            "2023-09-17T16:30:00Z",
+        ],
+    );
+    assert_eq!(exit_code(&output), 75, "{}", stderr(&output));
+
+    // a weather.json without main.temp or main.humidity is a parse error, not a panic
+    for main in [r#"{"humidity": 50}"#, r#"{"temp": 61.5}"#] {
+        let weather = dir.write(
+            "weather.json",
+            &format!(
+                r#"{{"dt": 1694880000, "name": "Boston", "main": {}}}"#,
+                main
+            ),
+        );
+        let output = run(
+            WEATHER_NOTIFY,
+            &[
+                "-w",
+                &weather,
+                "-i",
+                &indoor,
+                "-o",
+                &dir.path("Indoor.txt"),
+                "--now",
+                "2023-09-16T16:30:00Z",
+            ],
+        );
+        assert_eq!(exit_code(&output), 65, "{} {}", main, stderr(&output));
+    }
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use common::{exit_code, run, stderr, MockServer, Route, TempDir};
+use iloveair::history::History;
+use std::time::Duration;
+
+const PUSHOVER_NOTIFY: &str = env!("CARGO_BIN_EXE_pushover_notify");
+
+const SENT: &str = r#"{"status": 1, "request": "647d2300-702c-4b38-8b2f-d56326ae460b"}"#;
+
+fn config(dir: &TempDir, server: &MockServer) -> String {
+    dir.write(
+        "pushover.json",
+        &format!(
+            r#"{{"api_key": "app-token", "user_key": "user-key", "base_url": "{}", "allow_http": true}}"#,
+            server.base_url
+        ),
+    )
+}
+
+fn pushover_server(status: u16, body: &str) -> MockServer {
+    MockServer::start(vec![Route::new("POST", "/1/messages.json", status, body)])
+}
+
+#[test]
+fn test_pushover_notify() {
+    let dir = TempDir::new("pushover");
+    let server = pushover_server(200, SENT);
+    let text_in = dir.write("Indoor.txt", "window_should_be_open: 🪟✔️\n");
+    let db = dir.path("history.db");
+    let args = ["-p", &config(&dir, &server), "-i", &text_in, "--db", &db];
+    let output = run(PUSHOVER_NOTIFY, &args);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+
+    let received = server.received_path("/1/messages.json");
+    assert_eq!(received.len(), 1);
+    let body: serde_json::Value = serde_json::from_str(&received[0].body).unwrap();
+    assert_eq!(body["token"], "app-token");
+    assert_eq!(body["user"], "user-key");
+    assert_eq!(body["message"], "window_should_be_open: 🪟✔️\n");
+    let notifications = History::open(&db)
+        .unwrap()
+        .notifications(None, None)
+        .unwrap();
+    assert_eq!(notifications.len(), 1);
+
+    // unchanged text is not sent again
+    let output = run(PUSHOVER_NOTIFY, &args);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert_eq!(server.received().len(), 1);
+}
+
+#[test]
+fn test_dry_run_sends_nothing() {
+    let dir = TempDir::new("pushover_dry_run");
+    let server = pushover_server(200, SENT);
+    let text_in = dir.write("Indoor.txt", "window_should_be_open: 🪟✔️\n");
+    let output = run(
+        PUSHOVER_NOTIFY,
+        &["-p", &config(&dir, &server), "-i", &text_in, "--dry-run"],
+    );
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("dry-run"));
+    assert!(server.received().is_empty());
+}
+
+#[test]
+fn test_pushover_errors() {
+    for (status, code) in [(401, 77), (429, 69), (500, 69)] {
+        let dir = TempDir::new(&format!("pushover_{}", status));
+        let server = pushover_server(
+            status,
+            r#"{"token": "invalid", "errors": ["application token is invalid"], "status": 0}"#,
+        );
+        let text_in = dir.write("Indoor.txt", "window_should_be_open: 🪟✔️\n");
+        let db = dir.path("history.db");
+        let output = run(
+            PUSHOVER_NOTIFY,
+            &["-p", &config(&dir, &server), "-i", &text_in, "--db", &db],
+        );
+        assert_eq!(exit_code(&output), code, "{} {}", status, stderr(&output));
+        let history = History::open(&db).unwrap();
+        assert!(history.notifications(None, None).unwrap().is_empty());
+        let stats = history.fetch_stats().unwrap();
+        assert_eq!((stats[0].successes, stats[0].failures), (0, 1));
+    }
+}
+
+#[test]
+fn test_failed_send_is_retried() {
+    let dir = TempDir::new("pushover_retry");
+    let text_in = dir.write("Indoor.txt", "window_should_be_open: 🪟✔️\n");
+    let server = pushover_server(500, r#"{"status": 0}"#);
+    let output = run(
+        PUSHOVER_NOTIFY,
+        &["-p", &config(&dir, &server), "-i", &text_in],
+    );
+    assert_eq!(exit_code(&output), 69, "{}", stderr(&output));
+
+    // the text has not changed but it never went out
+    let server = pushover_server(200, SENT);
+    let output = run(
+        PUSHOVER_NOTIFY,
+        &["-p", &config(&dir, &server), "-i", &text_in],
+    );
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    let received = server.received_path("/1/messages.json");
+    assert_eq!(received.len(), 1);
+    let body: serde_json::Value = serde_json::from_str(&received[0].body).unwrap();
+    assert_eq!(body["message"], "window_should_be_open: 🪟✔️\n");
+}
+
+#[test]
+fn test_pushover_slow_response() {
+    let dir = TempDir::new("pushover_slow");
+    let server = MockServer::start(vec![
+        Route::new("POST", "/1/messages.json", 200, SENT).delay(Duration::from_secs(3))
+    ]);
+    let text_in = dir.write("Indoor.txt", "window_should_be_open: 🪟✔️\n");
+    let output = run(
+        PUSHOVER_NOTIFY,
+        &[
+            "-p",
+            &config(&dir, &server),
+            "-i",
+            &text_in,
+            "--timeout",
+            "1",
+        ],
+    );
+    assert_eq!(exit_code(&output), 69, "{}", stderr(&output));
+}
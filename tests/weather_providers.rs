@@ -0,0 +1,189 @@
+mod common;
+
+use common::{exit_code, read_json, run, stderr, MockServer, Route, TempDir};
+use iloveair::history::History;
+
+const GETWEATHER: &str = env!("CARGO_BIN_EXE_getweather");
+
+const OWM: &str = include_str!("fixtures/weather/openweathermap.json");
+const NWS: &str = include_str!("fixtures/weather/nws.json");
+const OPEN_METEO: &str = include_str!("fixtures/weather/open_meteo.json");
+const STATION: &str = include_str!("fixtures/weather/station.html");
+
+const NWS_PATH: &str = "/stations/KBOS/observations/latest";
+
+fn config(dir: &TempDir, server: &MockServer, providers: &str) -> String {
+    dir.write(
+        "openweathermap.json",
+        &format!(
+            r#"{{"api_key": "secret", "city": "Boston", "country": "US", "latitude": "42.36", "longitude": "-71.06",
+                "base_url": "{0}", "allow_http": true, "providers": {1},
+                "nws": {{"station": "KBOS", "user_agent": "(iloveair, test@example.com)", "base_url": "{0}"}},
+                "open_meteo": {{"base_url": "{0}"}},
+                "station": {{"url": "{0}/station.html", "allow_http": true, "name": "Fairfax",
+                    "temperature": "//tr[3]/td[2]/font/strong/small/font/text()",
+                    "humidity": "//tr[4]/td[2]/font/strong/small/font/text()"}}}}"#,
+            server.base_url, providers
+        ),
+    )
+}
+
+#[test]
+fn test_primary_provider() {
+    for (providers, path, body, temp) in [
+        (r#"["nws"]"#, NWS_PATH, NWS, 62.06),
+        (r#"["open_meteo"]"#, "/v1/forecast", OPEN_METEO, 62.3),
+    ] {
+        let dir = TempDir::new("weather_provider");
+        let server = MockServer::start(vec![
+            Route::new("GET", path, 200, body),
+            Route::new("GET", "/data/2.5/weather", 200, OWM),
+        ]);
+        let out = dir.path("weather.json");
+        let output = run(
+            GETWEATHER,
+            &["-c", &config(&dir, &server, providers), "-o", &out],
+        );
+        assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+        let temp_out = read_json(&out)["payload"]["main"]["temp"].as_f64().unwrap();
+        assert!((temp_out - temp).abs() < 0.01, "{} {}", providers, temp_out);
+        assert!(server.received_path("/data/2.5/weather").is_empty());
+    }
+}
+
+#[test]
+fn test_fallback_provider() {
+    let dir = TempDir::new("weather_fallback");
+    let server = MockServer::start(vec![
+        Route::new("GET", NWS_PATH, 503, "service unavailable"),
+        Route::new("GET", "/data/2.5/weather", 200, OWM),
+    ]);
+    let out = dir.path("weather.json");
+    let db = dir.path("history.db");
+    let output = run(
+        GETWEATHER,
+        &[
+            "-c",
+            &config(&dir, &server, r#"["nws", "openweathermap"]"#),
+            "-o",
+            &out,
+            "--db",
+            &db,
+        ],
+    );
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+    assert_eq!(
+        server.received_path(NWS_PATH)[0].url,
+        "/stations/KBOS/observations/latest"
+    );
+    assert_eq!(read_json(&out)["payload"]["main"]["temp"], 61.52);
+    let stats = History::open(&db).unwrap().fetch_stats().unwrap();
+    let counts: Vec<(&str, u64, u64)> = stats
+        .iter()
+        .map(|s| (s.source.as_str(), s.successes, s.failures))
+        .collect();
+    assert_eq!(counts, [("nws_weather", 0, 1), ("owm_weather", 1, 0)]);
+
+    // every provider down, the last error decides the exit code
+    let server = MockServer::start(vec![
+        Route::new("GET", NWS_PATH, 503, "service unavailable"),
+        Route::new("GET", "/data/2.5/weather", 401, r#"{"cod": 401}"#),
+    ]);
+    let output = run(
+        GETWEATHER,
+        &[
+            "-c",
+            &config(&dir, &server, r#"["nws", "openweathermap"]"#),
+            "--force",
+        ],
+    );
+    assert_eq!(exit_code(&output), 77, "{}", stderr(&output));
+}
+
+#[test]
+fn test_station_overrides_owm() {
+    let dir = TempDir::new("weather_station");
+    let out = dir.path("weather.json");
+    let db = dir.path("history.db");
+    for (page, temp, location) in [
+        (STATION, 64.8, "Fairfax"),
+        // the page changed, the city level reading stands in
+        ("<html><body>moved</body></html>", 61.52, "Boston"),
+    ] {
+        let server = MockServer::start(vec![
+            Route::new("GET", "/station.html", 200, page),
+            Route::new("GET", "/data/2.5/weather", 200, OWM),
+        ]);
+        let output = run(
+            GETWEATHER,
+            &[
+                "-c",
+                &config(&dir, &server, r#"["station", "openweathermap"]"#),
+                "-o",
+                &out,
+                "--db",
+                &db,
+                "--force",
+            ],
+        );
+        assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+        let weather = read_json(&out);
+        assert_eq!(weather["payload"]["main"]["temp"], temp);
+        assert_eq!(weather["payload"]["name"], location);
+        // rain and wind come from OWM either way
+        assert_eq!(weather["payload"]["weather"][0]["id"], 500);
+        assert_eq!(weather["payload"]["wind"]["gust"], 18.41);
+    }
+
+    // a station alone would have no rain, wind or conditions for the vetoes
+    let server = MockServer::start(vec![Route::new("GET", "/station.html", 200, STATION)]);
+    let output = run(
+        GETWEATHER,
+        &["-c", &config(&dir, &server, r#"["station"]"#), "--force"],
+    );
+    assert_eq!(exit_code(&output), 78, "{}", stderr(&output));
+    assert!(server.received().is_empty());
+}
+
+#[test]
+fn test_cache_ttl_of_answering_provider() {
+    // NWS answered 15 minutes ago, within its 20 minutes but past OWM's 10
+    let fetched_at = chrono::Utc::now() - chrono::Duration::minutes(15);
+    for (provider, fetched) in [(r#""nws_weather""#, false), ("null", true)] {
+        let dir = TempDir::new("weather_cache_ttl");
+        let server = MockServer::start(vec![
+            Route::new("GET", "/data/2.5/weather", 200, OWM),
+            Route::new("GET", NWS_PATH, 200, NWS),
+        ]);
+        let out = dir.write(
+            "weather.json",
+            &format!(
+                r#"{{"fetched_at": "{}", "payload": {{"dt": 1694879940, "main": {{"temp": 62.06, "humidity": 94}}, "provider": {}}}}}"#,
+                fetched_at.to_rfc3339(),
+                provider
+            ),
+        );
+        let output = run(
+            GETWEATHER,
+            &[
+                "-c",
+                &config(&dir, &server, r#"["openweathermap", "nws"]"#),
+                "-o",
+                &out,
+            ],
+        );
+        assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+        assert_eq!(!server.received().is_empty(), fetched, "{}", provider);
+    }
+}
+
+#[test]
+fn test_unknown_provider() {
+    let dir = TempDir::new("weather_unknown_provider");
+    let server = MockServer::start(vec![]);
+    let output = run(
+        GETWEATHER,
+        &["-c", &config(&dir, &server, r#"["wunderground"]"#)],
+    );
+    assert_eq!(exit_code(&output), 78, "{}", stderr(&output));
+}